@@ -16,10 +16,10 @@
         controller: 'searchProcedureController',
         controllerAs: 'search',
       })
-      .state('searchInnerProcedure', {
-        url: '/searchInnerProcedure/:fileName/:innerName',
-        templateUrl: '/static/html/searchInnerProcedure.html',
-        controller: 'searchInnerProcedureController',
+      .state('search', {
+        url: '/search/:query',
+        templateUrl: '/static/html/search.html',
+        controller: 'searchController',
         controllerAs: 'search',
       })
       .state('program', {
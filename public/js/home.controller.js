@@ -3,17 +3,13 @@
         var homeController = this;
         homeController.searchProcedureText = "";
 
-        homeController.innerSearchProcedureText = "";
-        homeController.innerSearchInnerProcedureText = "";
+        homeController.searchText = "";
 
         homeController.onSearchProcedure = function() {
             state.go('searchProcedure', {'contents': homeController.searchProcedureText });
         }
-        homeController.onSearchInnerProcedure = function() {
-            state.go('searchInnerProcedure', {
-              'fileName': homeController.innerSearchProcedureText,
-              'innerName': homeController.innerSearchInnerProcedureText,
-            });
+        homeController.onSearch = function() {
+            state.go('search', {'query': homeController.searchText });
         }
 
     }]);
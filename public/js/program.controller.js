@@ -1,5 +1,5 @@
 (function() {
-  angular.module('progressServer').controller('programController', ['$state', '$resource', function(state, resource) {
+  angular.module('progressServer').controller('programController', ['$scope', '$state', '$resource', function(scope, state, resource) {
     var programController = this;
     programController.value = "Loading program...";
     programController.sections = []
@@ -10,21 +10,50 @@
       section.open = !section.open;
     }
 
-    resource("/api/procedure/" + encodeURIComponent(state.params.name)).get(function(res) {
-      programController.name = state.params.name;
-      programController.fileReferences = res.file_references;
-      programController.sections = res.sections.map(section => {
-        section.open = false;
-        return section;
+    var load = () => {
+      resource("/api/procedure/" + encodeURIComponent(state.params.name)).get(function(res) {
+        programController.name = state.params.name;
+        programController.windowPreviewUrl = "/api/window_preview/" + encodeURIComponent(state.params.name) + "?t=" + Date.now();
+        programController.fileReferences = res.file_references;
+        programController.sections = res.sections.map(section => {
+          section.open = false;
+          return section;
+        });
+        programController.sections.forEach(section => {
+          if (section.type == "CreateWindow") {
+            programController.createWindowSection = section
+          }
+        });
+      }).$promise.catch(function(a) {
+        programController.sections = "Could not fetch program";
       });
-      programController.sections.forEach(section => {
-        if (section.type == "CreateWindow") {
-          programController.createWindowSection = section
+    };
+    load();
+
+    // Poll for changed files, and load the program again when it is one of them. Nothing
+    // changes when the server watches no sources, so then there is no polling.
+    var polling = true;
+    var changes = resource("/api/changes");
+    var poll = since => {
+      if (!polling) {
+        return;
+      }
+      changes.get(since === undefined ? {} : { since: since }).$promise.then(function(res) {
+        var changed = res.changes.some(change => change.path == state.params.name);
+        if (since !== undefined && (changed || res.missed)) {
+          load();
+        }
+        if (res.watching) {
+          setTimeout(() => poll(res.latest), 3000);
         }
+      }).catch(function() {
+        setTimeout(() => poll(since), 5000);
       });
-    }).$promise.catch(function(a) {
-      programController.sections = "Could not fetch program";
-    });
+    };
+    poll();
 
+    scope.$on('$destroy', () => {
+      polling = false;
+    });
   }]);
 }());
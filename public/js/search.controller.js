@@ -0,0 +1,18 @@
+(function() {
+  angular.module('progressServer').controller('searchController', ['$state', '$resource', function(state, resource) {
+    var searchController = this;
+
+    searchController.query = state.params.query;
+    searchController.results = [];
+    searchController.error = null;
+
+    resource('api/search').get({ q: searchController.query }, function(res) {
+      searchController.results = res.results.map(result => result.type == 'File'
+        ? { path: result.path, name: result.path, kind: 'File' }
+        : { path: result.symbol.path, name: result.symbol.name, kind: result.symbol.kind, scope: result.symbol.scope, row: result.symbol.position.row });
+    }, function(res) {
+      searchController.error = res.data.message;
+    });
+
+  }]);
+}());
@@ -0,0 +1,213 @@
+//! A stand-in for the file server that serves the sources in a local directory, for development
+//! and the integration tests. It speaks the protocol the progress server expects:
+//!
+//! - `file/<path>`: the contents of a file, with an ETag and Last-Modified. A matching
+//!   If-None-Match is answered with 304.
+//! - `find/<query>`: a JSON array of the paths of the files whose name contains the query,
+//!   ignoring case, sorted
+//! - `list/<path>`: a JSON array of the entries of a directory, `list/` for the root
+//!
+//! The address and port come from Rocket.toml or ROCKET_ADDRESS and ROCKET_PORT.
+
+#![feature(custom_derive)]
+#![feature(plugin)]
+#![plugin(rocket_codegen)]
+
+#[macro_use] extern crate serde_derive;
+extern crate docopt;
+extern crate hyper;
+extern crate rocket;
+extern crate rocket_contrib;
+
+use std::ascii::AsciiExt;
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::io::{Cursor, Read, Write, stderr};
+use std::path::{Path, PathBuf};
+use std::process::exit;
+use std::time::{Duration, UNIX_EPOCH};
+
+use docopt::Docopt;
+use hyper::header::HttpDate;
+use rocket::{Outcome, Request, State};
+use rocket::http::Status;
+use rocket::request::{self, FromRequest};
+use rocket::response::{self, Failure, Responder, Response};
+use rocket_contrib::JSON;
+
+const USAGE: &'static str = "
+Serve a directory of sources the way the file server does.
+
+Usage:
+  file_server [--codepage=<codepage>] <root>
+  file_server (-h | --help)
+
+Options:
+  -h --help              Show this screen.
+  --codepage=<codepage>  Send every file with this codepage in an X-Codepage header.
+";
+
+struct Root {
+    path: PathBuf,
+    codepage: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct DirEntry {
+    name: String,
+    path: String,
+    is_dir: bool,
+    size: u64,
+    modified: Option<u64>,
+}
+
+// The If-None-Match header of a request
+struct IfNoneMatch(Option<String>);
+
+impl<'a, 'r> FromRequest<'a, 'r> for IfNoneMatch {
+    type Error = ();
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<Self, ()> {
+        Outcome::Success(IfNoneMatch(request.headers().get_one("If-None-Match").map(String::from)))
+    }
+}
+
+enum FileRes {
+    Contents { contents: Vec<u8>, etag: String, last_modified: Option<String>, codepage: Option<String> },
+    NotModified { etag: String },
+}
+
+impl<'r> Responder<'r> for FileRes {
+    fn respond(self) -> response::Result<'r> {
+        match self {
+            FileRes::Contents { contents, etag, last_modified, codepage } => {
+                let mut response = Response::build();
+                response.raw_header("ETag", etag);
+                if let Some(last_modified) = last_modified {
+                    response.raw_header("Last-Modified", last_modified);
+                }
+                if let Some(codepage) = codepage {
+                    response.raw_header("X-Codepage", codepage);
+                }
+                response.sized_body(Cursor::new(contents)).ok()
+            },
+            FileRes::NotModified { etag } => Response::build()
+                .status(Status::NotModified)
+                .raw_header("ETag", etag)
+                .ok(),
+        }
+    }
+}
+
+// A missing file is a 404, anything else that goes wrong a 500
+fn failure(err: io::Error) -> Failure {
+    if err.kind() == io::ErrorKind::NotFound {
+        Failure(Status::NotFound)
+    } else {
+        Failure(Status::InternalServerError)
+    }
+}
+
+fn modified_secs(metadata: &fs::Metadata) -> Option<u64> {
+    metadata.modified().ok()
+        .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+        .map(|duration| duration.as_secs())
+}
+
+// The path relative to the root, with '/' separators
+fn relative_path(root: &Path, full_path: &Path) -> String {
+    let relative = full_path.strip_prefix(root).unwrap_or(full_path);
+    let parts: Vec<String> = relative.components()
+        .map(|component| component.as_os_str().to_string_lossy().into_owned())
+        .collect();
+    parts.join("/")
+}
+
+#[get("/file/<path..>")]
+fn file_route(path: PathBuf, if_none_match: IfNoneMatch, root: State<Root>) -> Result<FileRes, Failure> {
+    let full_path = root.path.join(path);
+    let metadata = fs::metadata(&full_path).map_err(failure)?;
+    if metadata.is_dir() {
+        return Err(Failure(Status::NotFound));
+    }
+    let modified = metadata.modified().map_err(failure)?;
+    let since_epoch = modified.duration_since(UNIX_EPOCH).unwrap_or(Duration::from_secs(0));
+    let etag = format!("\"{}-{}.{}\"", metadata.len(), since_epoch.as_secs(), since_epoch.subsec_nanos());
+    if if_none_match.0.as_ref() == Some(&etag) {
+        return Ok(FileRes::NotModified { etag });
+    }
+    let mut contents = Vec::new();
+    File::open(&full_path).and_then(|mut file| file.read_to_end(&mut contents)).map_err(failure)?;
+    Ok(FileRes::Contents {
+        contents,
+        etag,
+        last_modified: Some(HttpDate::from(modified).to_string()),
+        codepage: root.codepage.clone(),
+    })
+}
+
+fn find_in(root: &Path, dir: &Path, query: &str, results: &mut Vec<String>) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let path = entry.path();
+        if entry.file_type()?.is_dir() {
+            find_in(root, &path, query, results)?;
+        } else if entry.file_name().to_string_lossy().to_ascii_lowercase().contains(query) {
+            results.push(relative_path(root, &path));
+        }
+    }
+    Ok(())
+}
+
+#[get("/find/<query>")]
+fn find_route(query: String, root: State<Root>) -> Result<JSON<Vec<String>>, Failure> {
+    let mut results = Vec::new();
+    find_in(&root.path, &root.path, &query.to_ascii_lowercase(), &mut results).map_err(failure)?;
+    results.sort();
+    Ok(JSON(results))
+}
+
+fn list(root: &Path, dir: &Path) -> Result<JSON<Vec<DirEntry>>, Failure> {
+    let mut entries = Vec::new();
+    for entry in fs::read_dir(dir).map_err(failure)? {
+        let entry = entry.map_err(failure)?;
+        let metadata = entry.metadata().map_err(failure)?;
+        entries.push(DirEntry {
+            name: entry.file_name().to_string_lossy().into_owned(),
+            path: relative_path(root, &entry.path()),
+            is_dir: metadata.is_dir(),
+            size: if metadata.is_dir() { 0 } else { metadata.len() },
+            modified: modified_secs(&metadata),
+        });
+    }
+    entries.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(JSON(entries))
+}
+
+#[get("/list")]
+fn list_root_route(root: State<Root>) -> Result<JSON<Vec<DirEntry>>, Failure> {
+    list(&root.path, &root.path)
+}
+
+#[get("/list/<path..>")]
+fn list_route(path: PathBuf, root: State<Root>) -> Result<JSON<Vec<DirEntry>>, Failure> {
+    list(&root.path, &root.path.join(path))
+}
+
+fn main() {
+    let args = Docopt::new(USAGE).and_then(|docopt| docopt.parse()).unwrap_or_else(|err| err.exit());
+    let root = PathBuf::from(args.get_str("<root>"));
+    if !root.is_dir() {
+        let _ = writeln!(stderr(), "'{}' is not a directory", root.display());
+        exit(1);
+    }
+    let codepage = match args.get_str("--codepage") {
+        "" => None,
+        codepage => Some(codepage.to_string()),
+    };
+    rocket::ignite()
+        .manage(Root { path: root, codepage })
+        .mount("/", routes![file_route, find_route, list_root_route, list_route])
+        .launch();
+}
@@ -0,0 +1,91 @@
+use std::ascii::AsciiExt;
+use std::borrow::Cow;
+
+use encoding::{DecoderTrap, EncodingRef};
+use encoding::all::{ISO_8859_1, ISO_8859_15, UTF_8, WINDOWS_1250, WINDOWS_1251, WINDOWS_1252};
+use encoding::label::encoding_from_whatwg_label;
+
+use config::get_string;
+use error::{ProgressResult, Error};
+
+const UTF_8_BOM: &'static [u8] = b"\xEF\xBB\xBF";
+
+/// A source file decoded to a string, along with the codepage it was decoded from
+pub struct Decoded<'a> {
+    pub text: Cow<'a, str>,
+    pub codepage: String,
+}
+
+// The encoding for an OpenEdge codepage name like ISO8859-1 or 1252. Anything else is tried as a
+// web encoding label such as "latin1".
+fn encoding_for(codepage: &str) -> Option<EncodingRef> {
+    let upper = codepage.to_ascii_uppercase();
+    match &upper[..] {
+        "UTF-8" | "UTF8" => Some(UTF_8),
+        "ISO8859-1" | "ISO-8859-1" => Some(ISO_8859_1),
+        "ISO8859-15" | "ISO-8859-15" => Some(ISO_8859_15),
+        "1250" | "WINDOWS-1250" => Some(WINDOWS_1250),
+        "1251" | "WINDOWS-1251" => Some(WINDOWS_1251),
+        "1252" | "WINDOWS-1252" => Some(WINDOWS_1252),
+        _ => encoding_from_whatwg_label(codepage),
+    }
+}
+
+/// The codepage sources are in unless the file server says otherwise. Like OpenEdge this is
+/// -cpstream, falling back to -cpinternal and then UTF-8.
+pub fn default_codepage() -> ProgressResult<String> {
+    if let Some(codepage) = get_string("cpstream")? {
+        return Ok(codepage);
+    }
+    if let Some(codepage) = get_string("cpinternal")? {
+        return Ok(codepage);
+    }
+    Ok("UTF-8".to_string())
+}
+
+/// Decode a source file. A UTF-8 byte order mark wins over everything, then the codepage given for
+/// the file, then the configured default.
+pub fn decode<'a>(bytes: &'a [u8], file_codepage: Option<&str>) -> ProgressResult<Decoded<'a>> {
+    if bytes.starts_with(UTF_8_BOM) {
+        return Ok(Decoded {
+            text: String::from_utf8_lossy(&bytes[UTF_8_BOM.len()..]),
+            codepage: "UTF-8".to_string(),
+        });
+    }
+    let codepage = match file_codepage {
+        Some(codepage) => codepage.to_string(),
+        None => default_codepage()?,
+    };
+    decode_as(bytes, &codepage)
+}
+
+/// Decode the bytes from the given codepage
+pub fn decode_as<'a>(bytes: &'a [u8], codepage: &str) -> ProgressResult<Decoded<'a>> {
+    let encoding = encoding_for(codepage).ok_or(Error::new(format!("Unknown codepage '{}'", codepage)))?;
+    // UTF-8 is borrowed instead of copied when it is valid
+    let text = if encoding.name() == UTF_8.name() {
+        String::from_utf8_lossy(bytes)
+    } else {
+        Cow::Owned(encoding.decode(bytes, DecoderTrap::Replace).map_err(|err| Error::new(err.into_owned()))?)
+    };
+    Ok(Decoded { text, codepage: codepage.to_string() })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decode, decode_as};
+
+    #[test]
+    fn test_decode() {
+        let latin1 = b"MESSAGE \"Ol\xE9\".";
+        assert_eq!("MESSAGE \"Olé\".", decode_as(latin1, "ISO8859-1").unwrap().text);
+        assert_eq!("MESSAGE \"Olé\".", decode_as(latin1, "1252").unwrap().text);
+
+        let bom = b"\xEF\xBB\xBFMESSAGE \"Ol\xC3\xA9\".";
+        let decoded = decode(bom, Some("ISO8859-1")).unwrap();
+        assert_eq!("MESSAGE \"Olé\".", decoded.text);
+        assert_eq!("UTF-8", decoded.codepage);
+
+        assert!(decode_as(latin1, "not-a-codepage").is_err());
+    }
+}
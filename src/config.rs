@@ -0,0 +1,57 @@
+use rocket::config::{active, Value};
+
+use error::{ProgressResult, Error};
+
+/// Look up one of our own settings in the active Rocket.toml environment
+pub fn get_value(key: &str) -> ProgressResult<Option<&'static Value>> {
+    let config = active().ok_or(Error::new("No config file"))?;
+    for (extra_key, value) in config.extras() {
+        if extra_key == key {
+            return Ok(Some(value));
+        }
+    }
+    Ok(None)
+}
+
+pub fn get_string(key: &str) -> ProgressResult<Option<String>> {
+    match get_value(key)? {
+        Some(&Value::String(ref value)) => Ok(Some(value.clone())),
+        Some(_) => Err(Error::new(format!("{} is not a string in Rocket.toml", key))),
+        None => Ok(None),
+    }
+}
+
+pub fn get_integer(key: &str) -> ProgressResult<Option<i64>> {
+    match get_value(key)? {
+        Some(&Value::Integer(value)) => Ok(Some(value)),
+        Some(_) => Err(Error::new(format!("{} is not an integer in Rocket.toml", key))),
+        None => Ok(None),
+    }
+}
+
+pub fn get_bool(key: &str) -> ProgressResult<Option<bool>> {
+    match get_value(key)? {
+        Some(&Value::Boolean(value)) => Ok(Some(value)),
+        Some(_) => Err(Error::new(format!("{} is not true or false in Rocket.toml", key))),
+        None => Ok(None),
+    }
+}
+
+/// A setting that is either a list of strings or a single string
+pub fn get_string_list(key: &str) -> ProgressResult<Option<Vec<String>>> {
+    match get_value(key)? {
+        Some(&Value::Array(ref values)) => {
+            let mut strings = Vec::with_capacity(values.len());
+            for value in values {
+                match value {
+                    &Value::String(ref value) => strings.push(value.clone()),
+                    _ => return Err(Error::new(format!("{} is not a list of strings in Rocket.toml", key))),
+                }
+            }
+            Ok(Some(strings))
+        },
+        Some(&Value::String(ref value)) => Ok(Some(vec![value.clone()])),
+        Some(_) => Err(Error::new(format!("{} is not a list of strings in Rocket.toml", key))),
+        None => Ok(None),
+    }
+}
@@ -2,14 +2,21 @@ use std::convert::From;
 use std::fmt;
 use std::fmt::{Display, Formatter};
 use std::io;
+use std::io::Cursor;
 use std::io::{Write, stderr};
 use std::process::exit;
 
 use docopt;
+use git2;
 use hyper;
 use ini::ini;
+use notify;
+use rusqlite;
+use rocket::http::{ContentType, Status};
+use rocket::response::{self, Responder, Response};
 use serde_json;
 use url;
+use zip;
 use combine::primitives::{Consumed, ParseResult, ParseError, StreamOnce};
 
 #[derive(Debug)]
@@ -20,6 +27,10 @@ pub enum FromError {
     Docopt(docopt::Error),
     JSON(serde_json::Error),
     Url(url::ParseError),
+    Git(git2::Error),
+    Zip(zip::result::ZipError),
+    Sqlite(rusqlite::Error),
+    Notify(notify::Error),
 }
 
 #[derive(Debug)]
@@ -28,6 +39,14 @@ pub enum Error {
     FromErrorMessage(FromError, String),
     ParseError(String),
     General(String),
+    /// The file is not in the source backend
+    NotFound(String),
+    /// The file server failed or answered with an error
+    Upstream(String),
+    /// The file server did not answer in time
+    Timeout(String),
+    /// The query of a request is not valid
+    BadRequest(String),
 }
 
 impl Error {
@@ -63,6 +82,18 @@ impl From<serde_json::Error> for Error {
 impl From<url::ParseError> for Error {
     fn from(err: url::ParseError) -> Error { Error::FromError(FromError::Url(err)) }
 }
+impl From<git2::Error> for Error {
+    fn from(err: git2::Error) -> Error { Error::FromError(FromError::Git(err)) }
+}
+impl From<zip::result::ZipError> for Error {
+    fn from(err: zip::result::ZipError) -> Error { Error::FromError(FromError::Zip(err)) }
+}
+impl From<rusqlite::Error> for Error {
+    fn from(err: rusqlite::Error) -> Error { Error::FromError(FromError::Sqlite(err)) }
+}
+impl From<notify::Error> for Error {
+    fn from(err: notify::Error) -> Error { Error::FromError(FromError::Notify(err)) }
+}
 
 impl Display for FromError {
     fn fmt(&self, f: &mut Formatter) -> fmt::Result {
@@ -73,6 +104,10 @@ impl Display for FromError {
             &FromError::Docopt(ref err) => write!(f, "{}", err),
             &FromError::JSON(ref err) => write!(f, "{}", err),
             &FromError::Url(ref err) => write!(f, "{}", err),
+            &FromError::Git(ref err) => write!(f, "{}", err),
+            &FromError::Zip(ref err) => write!(f, "{}", err),
+            &FromError::Sqlite(ref err) => write!(f, "{}", err),
+            &FromError::Notify(ref err) => write!(f, "{}", err),
         }
     }
 }
@@ -84,10 +119,40 @@ impl Display for Error {
             &Error::FromErrorMessage(ref from_error, ref s) => write!(f, "{}: {}", s, from_error),
             &Error::ParseError(ref s) => write!(f, "Parse Error: {}", s),
             &Error::General(ref s) => write!(f, "Custom Error: {}", s),
+            &Error::NotFound(ref s) => write!(f, "Not Found: {}", s),
+            &Error::Upstream(ref s) => write!(f, "Upstream Error: {}", s),
+            &Error::Timeout(ref s) => write!(f, "Timeout: {}", s),
+            &Error::BadRequest(ref s) => write!(f, "Bad Request: {}", s),
         }
     }
 }
 
+#[derive(Serialize)]
+struct ErrorRes {
+    pub error: &'static str,
+    pub message: String,
+}
+
+// Errors are sent as JSON, with a status that tells a missing file from a file server that failed
+impl<'r> Responder<'r> for Error {
+    fn respond(self) -> response::Result<'r> {
+        let (status, error) = match self {
+            Error::NotFound(_) => (Status::NotFound, "not_found"),
+            Error::Upstream(_) => (Status::BadGateway, "upstream"),
+            Error::Timeout(_) => (Status::GatewayTimeout, "timeout"),
+            Error::ParseError(_) => (Status::UnprocessableEntity, "parse"),
+            Error::BadRequest(_) => (Status::BadRequest, "bad_request"),
+            _ => (Status::InternalServerError, "internal"),
+        };
+        let body = serde_json::to_string(&ErrorRes { error, message: self.to_string() }).unwrap_or(String::new());
+        Response::build()
+            .status(status)
+            .header(ContentType::JSON)
+            .sized_body(Cursor::new(body))
+            .ok()
+    }
+}
+
 pub fn from<O, I>(parse_result: ParseResult<O, I>) -> Result<O, Error> 
     where I: StreamOnce,
           <I as StreamOnce>::Range: fmt::Debug,
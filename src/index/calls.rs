@@ -0,0 +1,355 @@
+use std::ascii::AsciiExt;
+use std::collections::HashMap;
+
+use error::{ProgressResult, Error};
+use parser::FilePosition;
+use source::{Propath, SourceProvider};
+use util::escape_like;
+use super::{Index, Symbol, SymbolKind};
+
+/// A program, or a block of one
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Callable {
+    pub program: String,
+    /// The internal procedure that is run, or the procedure, function, method or trigger a RUN is
+    /// in. None is the whole program.
+    pub block: Option<String>,
+}
+
+/// How a RUN names what it runs
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CallKind {
+    /// A program on the PROPATH, like RUN ar/cust.p
+    Program,
+    /// An internal procedure of the same program, or RUN ... IN THIS-PROCEDURE
+    Internal,
+    /// RUN name IN handle: an internal procedure of the program run PERSISTENT SET the handle
+    InHandle,
+    /// RUN VALUE(expression), which is only known at run time
+    Dynamic,
+}
+
+/// One RUN statement
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Call {
+    pub caller: Callable,
+    /// What the RUN names, as written
+    pub target: String,
+    pub kind: CallKind,
+    /// What is run. None when that is unresolved: a dynamic RUN, a handle that is not set by
+    /// exactly one program run in the same file, or a program that is not on the PROPATH.
+    pub callee: Option<Callable>,
+    pub persistent: bool,
+    /// The handle RUN ... PERSISTENT SET sets
+    pub set_handle: Option<String>,
+    /// The handle of RUN ... IN
+    pub in_handle: Option<String>,
+    pub position: FilePosition,
+}
+
+// The options of a RUN, from the detail of its symbol
+#[derive(Default)]
+struct RunOptions {
+    dynamic: bool,
+    persistent: bool,
+    set_handle: Option<String>,
+    in_handle: Option<String>,
+}
+
+fn run_options(detail: Option<&str>) -> RunOptions {
+    let mut options = RunOptions::default();
+    let words: Vec<&str> = detail.unwrap_or("").split_whitespace().collect();
+    let mut i = 0;
+    while i < words.len() {
+        match words[i] {
+            "VALUE" => options.dynamic = true,
+            "PERSISTENT" => options.persistent = true,
+            "SET" | "IN" if i + 1 < words.len() => {
+                let handle = Some(words[i + 1].to_string());
+                if words[i] == "SET" { options.set_handle = handle } else { options.in_handle = handle }
+                i += 1;
+            },
+            _ => {},
+        }
+        i += 1;
+    }
+    options
+}
+
+// The name of a program without its directory and extension, which every RUN of it has
+fn program_stem(program: &str) -> &str {
+    let name = program.rsplit('/').next().unwrap_or(program);
+    match name.rfind('.') {
+        Some(dot) if dot > 0 => &name[..dot],
+        _ => name,
+    }
+}
+
+// What the resolver needs to know about the file of a RUN
+#[derive(Clone, Default)]
+struct CallerFile {
+    procedures: Vec<String>,
+    // Each handle set by RUN ... PERSISTENT SET, with the program run
+    handles: Vec<(String, String)>,
+}
+
+/// Works out what RUN statements run. Programs are looked up in the index and then on the PROPATH,
+/// which for the file server is a HEAD of `file/<path>`, and each is only looked up once.
+pub struct CallResolver<'a> {
+    index: &'a Index,
+    propath: &'a Propath,
+    programs: HashMap<String, Option<String>>,
+    files: HashMap<String, CallerFile>,
+}
+
+impl<'a> CallResolver<'a> {
+    pub fn new(index: &'a Index, propath: &'a Propath) -> Self {
+        CallResolver { index, propath, programs: HashMap::new(), files: HashMap::new() }
+    }
+
+    /// The path of the program `name` runs, if it is on the PROPATH. Like OpenEdge, a name
+    /// without an extension is a .p or .w, and a .r is run from its source.
+    pub fn program(&mut self, name: &str) -> ProgressResult<Option<String>> {
+        if let Some(path) = self.programs.get(name) {
+            return Ok(path.clone());
+        }
+        let path = name.replace("\\", "/");
+        let stem_end = path.rfind('.').and_then(|dot| if path[dot..].contains('/') { None } else { Some(dot) });
+        let candidates = match stem_end.map(|dot| path[dot + 1..].to_ascii_lowercase()) {
+            Some(ref extension) if extension == "r" => {
+                let stem = &path[..stem_end.unwrap()];
+                vec![format!("{}.p", stem), format!("{}.w", stem), path.clone()]
+            },
+            Some(_) => vec![path.clone()],
+            None => vec![format!("{}.p", path), format!("{}.w", path), path.clone()],
+        };
+        let mut found = None;
+        for candidate in candidates {
+            if self.index.fingerprint(&candidate)?.is_some() {
+                found = Some(candidate);
+                break;
+            }
+            match self.propath.stat(&candidate) {
+                Ok(stat) => if !stat.is_dir {
+                    found = Some(candidate);
+                    break;
+                },
+                Err(Error::NotFound(_)) => {},
+                Err(err) => return Err(err),
+            }
+        }
+        self.programs.insert(name.to_string(), found.clone());
+        Ok(found)
+    }
+
+    fn file(&mut self, path: &str) -> ProgressResult<CallerFile> {
+        if let Some(file) = self.files.get(path) {
+            return Ok(file.clone());
+        }
+        let mut file = CallerFile::default();
+        for symbol in self.index.symbols_in(path)? {
+            match symbol.kind {
+                SymbolKind::Procedure => file.procedures.push(symbol.name),
+                SymbolKind::Run => if let Some(handle) = run_options(symbol.detail.as_ref().map(String::as_str)).set_handle {
+                    file.handles.push((handle, symbol.name));
+                },
+                _ => {},
+            }
+        }
+        self.files.insert(path.to_string(), file.clone());
+        Ok(file)
+    }
+
+    // The internal procedure `name` of `program`, as it is defined when it is
+    fn procedure(&mut self, program: &str, name: &str) -> ProgressResult<String> {
+        let file = self.file(program)?;
+        Ok(file.procedures.into_iter().find(|procedure| procedure.eq_ignore_ascii_case(name)).unwrap_or(name.to_string()))
+    }
+
+    /// What `run`, the symbol of a RUN, runs
+    pub fn resolve(&mut self, run: &Symbol) -> ProgressResult<Call> {
+        let options = run_options(run.detail.as_ref().map(String::as_str));
+        let file = self.file(&run.path)?;
+        let (kind, callee) = if options.dynamic {
+            (CallKind::Dynamic, None)
+        } else if let Some(ref handle) = options.in_handle {
+            if handle.eq_ignore_ascii_case("THIS-PROCEDURE") {
+                let block = self.procedure(&run.path, &run.name)?;
+                (CallKind::Internal, Some(Callable { program: run.path.clone(), block: Some(block) }))
+            } else {
+                let mut programs = Vec::new();
+                for &(ref set, ref target) in &file.handles {
+                    if set.eq_ignore_ascii_case(handle) {
+                        if let Some(program) = self.program(target)? {
+                            if !programs.contains(&program) {
+                                programs.push(program);
+                            }
+                        }
+                    }
+                }
+                let callee = if programs.len() == 1 {
+                    let program = programs.remove(0);
+                    let block = self.procedure(&program, &run.name)?;
+                    Some(Callable { program, block: Some(block) })
+                } else {
+                    None
+                };
+                (CallKind::InHandle, callee)
+            }
+        } else if let Some(block) = file.procedures.iter().find(|procedure| procedure.eq_ignore_ascii_case(&run.name)) {
+            (CallKind::Internal, Some(Callable { program: run.path.clone(), block: Some(block.clone()) }))
+        } else {
+            (CallKind::Program, self.program(&run.name)?.map(|program| Callable { program, block: None }))
+        };
+        Ok(Call {
+            caller: Callable { program: run.path.clone(), block: run.scope.clone() },
+            target: run.name.clone(),
+            kind,
+            callee,
+            persistent: options.persistent,
+            set_handle: options.set_handle,
+            in_handle: options.in_handle,
+            position: run.position,
+        })
+    }
+
+    fn runs(&self, name_like: &str) -> ProgressResult<Vec<Symbol>> {
+        self.index.symbols_like(name_like, &[SymbolKind::Run], "%")
+    }
+}
+
+fn same_block(a: &Option<String>, b: &Option<String>) -> bool {
+    match (a.as_ref(), b.as_ref()) {
+        (Some(a), Some(b)) => a.eq_ignore_ascii_case(b),
+        (None, None) => true,
+        _ => false,
+    }
+}
+
+fn sort_calls(calls: &mut Vec<Call>) {
+    calls.sort_by(|a, b| (&a.caller.program, a.position.row, a.position.column).cmp(&(&b.caller.program, b.position.row, b.position.column)));
+}
+
+/// The RUN statements in the index that run `callee`, and how many others could run it but are
+/// unresolved
+pub fn callers(resolver: &mut CallResolver, callee: &Callable) -> ProgressResult<(Vec<Call>, usize)> {
+    // Every RUN of the program has its name in its target, and every RUN of an internal procedure
+    // is of that name
+    let name_like = match callee.block {
+        Some(ref block) => escape_like(block),
+        None => format!("%{}%", escape_like(program_stem(&callee.program))),
+    };
+    let mut calls = Vec::new();
+    let mut unresolved = resolver.runs("VALUE(%")?.len();
+    for run in resolver.runs(&name_like)? {
+        let call = resolver.resolve(&run)?;
+        match call.callee {
+            Some(ref found) if found.program == callee.program && same_block(&found.block, &callee.block) => {},
+            None if call.kind == CallKind::InHandle && callee.block.is_some() => {
+                unresolved += 1;
+                continue;
+            },
+            _ => continue,
+        }
+        calls.push(call);
+    }
+    sort_calls(&mut calls);
+    Ok((calls, unresolved))
+}
+
+/// The RUN statements in `caller`: a whole program, or one block of it
+pub fn callees(resolver: &mut CallResolver, caller: &Callable) -> ProgressResult<Vec<Call>> {
+    if resolver.index.fingerprint(&caller.program)?.is_none() {
+        return Err(Error::NotFound(format!("'{}' is not in the index", caller.program)));
+    }
+    let mut calls = Vec::new();
+    for symbol in resolver.index.symbols_in(&caller.program)? {
+        if symbol.kind == SymbolKind::Run && (caller.block.is_none() || same_block(&symbol.scope, &caller.block)) {
+            calls.push(resolver.resolve(&symbol)?);
+        }
+    }
+    sort_calls(&mut calls);
+    Ok(calls)
+}
+
+impl Callable {
+    /// A program and maybe one of its internal procedures from the parts of a path, like
+    /// ar/cust.p/validate. The program runs up to the first part with an extension, or is the
+    /// whole path when none has one.
+    pub fn from_parts(parts: &[String]) -> Option<Callable> {
+        if parts.is_empty() {
+            return None;
+        }
+        let end = parts.iter().position(|part| part.contains('.')).map_or(parts.len(), |i| i + 1);
+        let block = match parts.len() - end {
+            0 => None,
+            1 => Some(parts[end].clone()),
+            _ => return None,
+        };
+        Some(Callable { program: parts[..end].join("/"), block })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env::temp_dir;
+    use std::fs::{File, create_dir_all, remove_dir_all};
+    use std::io::Write;
+    use std::time::Duration;
+
+    use source::{ContentCache, HttpClient, Sources};
+    use super::super::Index;
+    use super::super::indexer::update_file;
+    use super::{CallKind, CallResolver, Callable, callees, callers};
+
+    fn callable(program: &str, block: Option<&str>) -> Callable {
+        Callable { program: program.to_string(), block: block.map(str::to_string) }
+    }
+
+    #[test]
+    fn test_calls() {
+        let root = temp_dir().join("progress_server_test_calls");
+        let _ = remove_dir_all(&root);
+        create_dir_all(root.join("src/ar")).unwrap();
+        File::create(root.join("src/main.p")).unwrap()
+            .write_all(b"RUN ar/cust.p PERSISTENT SET h.\nRUN validate IN h.\nRUN start.\nRUN VALUE(p).\nRUN missing.p.\n\
+                         PROCEDURE start:\n    RUN ar\\cust.r.\nEND PROCEDURE.\n").unwrap();
+        File::create(root.join("src/ar/cust.p")).unwrap().write_all(b"PROCEDURE Validate:\nEND PROCEDURE.\n").unwrap();
+        // Not indexed, but on the PROPATH
+        File::create(root.join("src/report.w")).unwrap();
+
+        let sources = Sources::new(vec![root.join("src").to_string_lossy().into_owned()], 1,
+                                   HttpClient::new(Duration::from_secs(1), Duration::from_secs(1), 0), ContentCache::new(0)).unwrap();
+        let propath = sources.propath(None).unwrap();
+        let index = Index::open(root.join("index.sqlite")).unwrap();
+        update_file(&index, &propath, "main.p").unwrap();
+        update_file(&index, &propath, "ar/cust.p").unwrap();
+        let mut resolver = CallResolver::new(&index, &propath);
+
+        let calls = callees(&mut resolver, &callable("main.p", None)).unwrap();
+        let found: Vec<(CallKind, Option<Callable>)> = calls.iter().map(|call| (call.kind, call.callee.clone())).collect();
+        assert_eq!(vec![
+            (CallKind::Program, Some(callable("ar/cust.p", None))),
+            (CallKind::InHandle, Some(callable("ar/cust.p", Some("Validate")))),
+            (CallKind::Internal, Some(callable("main.p", Some("start")))),
+            (CallKind::Dynamic, None),
+            (CallKind::Program, None),
+            (CallKind::Program, Some(callable("ar/cust.p", None))),
+        ], found);
+        assert_eq!(Some("h".to_string()), calls[0].set_handle);
+        assert_eq!(1, callees(&mut resolver, &callable("main.p", Some("START"))).unwrap().len());
+        assert!(callees(&mut resolver, &callable("nothing.p", None)).is_err());
+
+        let (calls, unresolved) = callers(&mut resolver, &callable("ar/cust.p", None)).unwrap();
+        assert_eq!(vec![(1, None), (7, Some("start".to_string()))],
+                   calls.iter().map(|call| (call.position.row, call.caller.block.clone())).collect::<Vec<_>>());
+        assert_eq!(1, unresolved);
+        assert_eq!(1, callers(&mut resolver, &callable("ar/cust.p", Some("validate"))).unwrap().0.len());
+        assert_eq!(Some("report.w".to_string()), resolver.program("report").unwrap());
+
+        assert_eq!(Some(callable("ar/cust.p", Some("validate"))), Callable::from_parts(&["ar".to_string(), "cust.p".to_string(), "validate".to_string()]));
+        assert_eq!(Some(callable("ar/cust", None)), Callable::from_parts(&["ar".to_string(), "cust".to_string()]));
+
+        remove_dir_all(&root).unwrap();
+    }
+}
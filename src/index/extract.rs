@@ -0,0 +1,418 @@
+use std::ascii::AsciiExt;
+
+use parser::{CodeBlockType, LineIndex, PreprocessorASTNode, PreprocessorAnalysisSection, Progress, Span, Token, TokenKind};
+use super::{Symbol, SymbolKind};
+
+// Words that can come between DEFINE and what is being defined
+const DEFINE_MODIFIERS: &'static [&'static str] = &[
+    "NEW", "GLOBAL", "SHARED", "PRIVATE", "PROTECTED", "PUBLIC", "STATIC", "INPUT", "OUTPUT",
+    "INPUT-OUTPUT", "RETURN",
+];
+
+// Words that can come between FIND and the record
+const FIND_QUALIFIERS: &'static [&'static str] = &["FIRST", "LAST", "NEXT", "PREV", "CURRENT"];
+
+fn is_any_keyword(token: Option<&Token>, keywords: &[&str]) -> bool {
+    token.map_or(false, |token| keywords.iter().any(|keyword| token.is_keyword(keyword)))
+}
+
+fn is_terminator(token: &Token, terminator: &str) -> bool {
+    token.kind == TokenKind::Terminator && token.text == terminator
+}
+
+struct Extractor<'a> {
+    path: &'a str,
+    source: &'a str,
+    lines: LineIndex,
+    symbols: Vec<Symbol>,
+}
+
+impl<'a> Extractor<'a> {
+    fn push(&mut self, kind: SymbolKind, name: String, scope: &Option<String>, detail: Option<String>, offset: usize) {
+        self.symbols.push(Symbol {
+            path: self.path.to_string(),
+            kind,
+            name,
+            scope: scope.clone(),
+            detail,
+            position: self.lines.position(offset),
+        });
+    }
+
+    // The target of the RUN whose tokens after the RUN keyword are `tokens`, and its options
+    fn run(&mut self, tokens: &[Token], scope: &Option<String>) {
+        let first = match tokens.first() {
+            Some(first) if first.kind != TokenKind::Terminator => first,
+            _ => return,
+        };
+        let mut details = Vec::new();
+        let mut end = first.span.end;
+        let mut rest = 1;
+        if first.is_keyword("VALUE") {
+            // RUN VALUE(expression): the target is only known at run time
+            let mut depth = 0;
+            for (i, token) in tokens.iter().enumerate().skip(1) {
+                if token.text == "(" {
+                    depth += 1;
+                } else if token.text == ")" {
+                    depth -= 1;
+                    if depth == 0 {
+                        end = token.span.end;
+                        rest = i + 1;
+                        break;
+                    }
+                }
+            }
+            details.push("VALUE".to_string());
+        } else {
+            // A path like ar/cust.p is several tokens with nothing between them
+            while rest < tokens.len() && tokens[rest].span.start == end
+                && tokens[rest].kind != TokenKind::Terminator && tokens[rest].text != "(" {
+                end = tokens[rest].span.end;
+                rest += 1;
+            }
+        }
+        let mut i = rest;
+        while i < tokens.len() {
+            if tokens[i].is_keyword("PERSISTENT") {
+                if tokens.get(i + 1).map_or(false, |token| token.is_keyword("SET")) && i + 2 < tokens.len()
+                    && tokens[i + 2].kind != TokenKind::Terminator {
+                    details.push(format!("PERSISTENT SET {}", tokens[i + 2].text));
+                    i += 2;
+                } else {
+                    details.push("PERSISTENT".to_string());
+                }
+            } else if tokens[i].is_keyword("IN") && i + 1 < tokens.len() {
+                details.push(format!("IN {}", tokens[i + 1].text));
+                i += 1;
+            }
+            i += 1;
+        }
+        let target = self.source[first.span.start..end].trim_matches(|c: char| c == '"' || c == '\'').to_string();
+        let detail = if details.is_empty() { None } else { Some(details.join(" ")) };
+        self.push(SymbolKind::Run, target, scope, detail, first.span.start);
+    }
+
+    // The tables a statement reads or changes: FIND, EACH, FIRST and LAST in FOR, OPEN QUERY and
+    // the like, CAN-FIND, CREATE, DELETE, and DEFINE BUFFER or QUERY ... FOR
+    fn tables(&mut self, tokens: &[Token], scope: &Option<String>) {
+        let first = match tokens.first() {
+            Some(first) => first,
+            None => return,
+        };
+        let is_define = first.is_keyword("DEFINE") || first.is_keyword("DEF");
+        let mut found: Vec<&Token> = Vec::new();
+        for (i, token) in tokens.iter().enumerate() {
+            let next = tokens.get(i + 1);
+            let table = if is_any_keyword(Some(token), &["EACH", "FIRST", "LAST"]) {
+                next
+            } else if token.is_keyword("FIND") {
+                if is_any_keyword(next, FIND_QUALIFIERS) { tokens.get(i + 2) } else { next }
+            } else if token.is_keyword("CAN-FIND") && next.map_or(false, |next| next.text == "(") {
+                tokens.get(i + 2)
+            } else if (i == 0 || is_any_keyword(tokens.get(i - 1), &["THEN", "ELSE"])) && (token.is_keyword("CREATE") || token.is_keyword("DELETE")) {
+                // CREATE BUTTON h and DELETE OBJECT h have a handle after what they make or delete
+                let ends = tokens.get(i + 2).map_or(true, |after| after.kind == TokenKind::Terminator
+                    || is_any_keyword(Some(after), &["USING", "NO-ERROR", "FOR", "VALIDATE"]));
+                if ends { next } else { None }
+            } else if is_define && token.is_keyword("FOR") {
+                next
+            } else {
+                None
+            };
+            if let Some(table) = table {
+                if table.kind == TokenKind::Identifier && !found.iter().any(|found| found.span.start == table.span.start) {
+                    found.push(table);
+                }
+            }
+        }
+        let statement = first.text.to_ascii_uppercase();
+        for table in found {
+            self.push(SymbolKind::TableUse, table.text.to_string(), scope, Some(statement.clone()), table.span.start);
+        }
+    }
+
+    // The calls in a statement of `functions`, the functions the file defines
+    fn function_calls(&mut self, tokens: &[Token], functions: &[String], scope: &Option<String>) {
+        for pair in tokens.windows(2) {
+            if pair[1].text != "(" || (pair[0].kind != TokenKind::Identifier && pair[0].kind != TokenKind::Keyword) {
+                continue;
+            }
+            if let Some(function) = functions.iter().find(|function| function.eq_ignore_ascii_case(pair[0].text)) {
+                self.push(SymbolKind::FunctionCall, function.clone(), scope, None, pair[0].span.start);
+            }
+        }
+    }
+
+    fn define(&mut self, tokens: &[Token], scope: &Option<String>) {
+        let mut i = 1;
+        while is_any_keyword(tokens.get(i), DEFINE_MODIFIERS) {
+            i += 1;
+        }
+        let definition_type = match tokens.get(i) {
+            Some(token) => token.text.to_ascii_uppercase(),
+            None => return,
+        };
+        let mut name = i + 1;
+        // DEFINE PARAMETER TABLE FOR tt and DEFINE PARAMETER BUFFER b FOR Customer
+        if is_any_keyword(tokens.get(name), &["TABLE", "DATASET"]) && is_any_keyword(tokens.get(name + 1), &["FOR"]) {
+            name += 2;
+        } else if definition_type == "PARAMETER" && is_any_keyword(tokens.get(name), &["BUFFER"]) {
+            name += 1;
+        }
+        let name = match tokens.get(name) {
+            Some(token) if token.kind == TokenKind::Identifier || token.kind == TokenKind::Keyword => token,
+            _ => return,
+        };
+        let (kind, detail) = match &definition_type[..] {
+            "VARIABLE" | "VAR" => (SymbolKind::Variable, None),
+            "TEMP-TABLE" | "WORK-TABLE" | "WORKFILE" => (SymbolKind::TempTable, None),
+            "BUFFER" => (SymbolKind::Buffer, None),
+            "PARAMETER" => (SymbolKind::Parameter, None),
+            _ => (SymbolKind::Definition, Some(definition_type.clone())),
+        };
+        self.push(kind, name.text.to_string(), scope, detail, name.span.start);
+    }
+}
+
+fn opens_block(tokens: &[Token]) -> bool {
+    tokens.last().map_or(false, |last| is_terminator(last, ":"))
+}
+
+// A label like `main-block:` before a DO, which is not a block of its own
+fn is_label(tokens: &[Token]) -> bool {
+    opens_block(tokens) && tokens.len() == 2 && tokens[0].kind == TokenKind::Identifier
+}
+
+// The procedure, function, class, method or trigger a statement starts, and where its name is
+fn block_symbol(source: &str, tokens: &[Token]) -> Option<(SymbolKind, String, usize)> {
+    let first = match tokens.first() {
+        Some(first) => first,
+        None => return None,
+    };
+    let opens_block = opens_block(tokens);
+    if opens_block && (first.is_keyword("PROCEDURE") || first.is_keyword("FUNCTION") || first.is_keyword("CLASS") || first.is_keyword("INTERFACE")) {
+        tokens.get(1).map(|name| {
+            let kind = if first.is_keyword("PROCEDURE") {
+                SymbolKind::Procedure
+            } else if first.is_keyword("FUNCTION") {
+                SymbolKind::Function
+            } else {
+                SymbolKind::Class
+            };
+            (kind, name.text.to_string(), name.span.start)
+        })
+    } else if opens_block && first.is_keyword("METHOD") {
+        // The name is right before the parameter list
+        tokens.iter().position(|token| token.text == "(")
+            .and_then(|open| if open > 0 { tokens.get(open - 1) } else { None })
+            .map(|name| (SymbolKind::Method, name.text.to_string(), name.span.start))
+    } else if first.is_keyword("ON") {
+        let end = tokens.iter().skip(1)
+            .position(|token| token.kind == TokenKind::Terminator || is_any_keyword(Some(token), &["DO", "PERSISTENT", "RUN", "ANYWHERE", "REVERT"]))
+            .map_or(tokens.len(), |end| end + 1);
+        if end > 1 {
+            Some((SymbolKind::Trigger, source[tokens[1].span.start..tokens[end - 1].span.end].to_string(), tokens[1].span.start))
+        } else {
+            None
+        }
+    } else {
+        None
+    }
+}
+
+/// A procedure, function, class, method or trigger block, from the statement that starts it to
+/// its END
+#[derive(Debug, Clone, PartialEq)]
+pub struct Scope {
+    pub kind: SymbolKind,
+    pub name: String,
+    pub span: Span,
+}
+
+/// Every named block of the statements, each before the blocks inside it. A block that never
+/// ends runs to the end of the source.
+pub fn scopes(source: &str, progress: &Progress) -> Vec<Scope> {
+    let mut scopes = Vec::new();
+    // The open blocks, with the scope each one is if it is named
+    let mut blocks: Vec<Option<usize>> = Vec::new();
+    for statement in &progress.statements {
+        let tokens = &statement.tokens;
+        if tokens.is_empty() || is_label(tokens) {
+            continue;
+        }
+        if tokens[0].is_keyword("END") {
+            if let Some(Some(i)) = blocks.pop() {
+                scopes[i].span.end = statement.span.end;
+            }
+        } else if opens_block(tokens) {
+            let named = block_symbol(source, tokens).map(|(kind, name, _)| {
+                scopes.push(Scope { kind, name, span: statement.span });
+                scopes.len() - 1
+            });
+            blocks.push(named);
+        }
+    }
+    for block in blocks {
+        if let Some(i) = block {
+            scopes[i].span.end = source.len();
+        }
+    }
+    scopes
+}
+
+/// The symbols of the file at `path`, from its preprocessor nodes and statements. Blocks are
+/// followed from the ':' that starts them to their END, so each symbol knows the procedure,
+/// function, method or trigger it is in.
+pub fn extract(path: &str, source: &str, nodes: &[PreprocessorASTNode], progress: &Progress) -> Vec<Symbol> {
+    let mut extractor = Extractor { path, source, lines: LineIndex::new(source), symbols: Vec::new() };
+    let mut blocks: Vec<Option<String>> = Vec::new();
+    // A call of any other function is a built-in one, or is only known at run time
+    let functions: Vec<String> = progress.statements.iter()
+        .filter_map(|statement| match block_symbol(source, &statement.tokens) {
+            Some((SymbolKind::Function, name, _)) => Some(name),
+            _ => None,
+        })
+        .collect();
+
+    for statement in &progress.statements {
+        let tokens = &statement.tokens;
+        let first = match tokens.first() {
+            Some(first) => first,
+            None => continue,
+        };
+        if is_label(tokens) {
+            continue;
+        }
+        let scope = blocks.iter().rev().filter_map(|block| block.clone()).next();
+
+        for (i, token) in tokens.iter().enumerate() {
+            if token.is_keyword("RUN") {
+                extractor.run(&tokens[i + 1..], &scope);
+            }
+        }
+        extractor.tables(tokens, &scope);
+        if !first.is_keyword("FUNCTION") {
+            extractor.function_calls(tokens, &functions, &scope);
+        }
+
+        let mut block_name = None;
+        if first.is_keyword("END") {
+            blocks.pop();
+            continue;
+        } else if first.is_keyword("DEFINE") || first.is_keyword("DEF") {
+            extractor.define(tokens, &scope);
+        } else if let Some((kind, name, offset)) = block_symbol(source, tokens) {
+            extractor.push(kind, name.clone(), &scope, None, offset);
+            block_name = Some(name);
+        }
+        if opens_block(tokens) {
+            blocks.push(block_name);
+        }
+    }
+
+    // A file that is not split into AppBuilder sections has no custom blocks
+    if let Ok(sections) = PreprocessorAnalysisSection::from(source, nodes) {
+        for section in sections {
+            if let PreprocessorAnalysisSection::CodeBlock { block_type: CodeBlockType::Custom { name, frame_name }, contents } = section {
+                extractor.push(SymbolKind::CustomBlock, name, &None, Some(frame_name), Span::of(source, contents).start);
+            }
+        }
+    }
+
+    for node in nodes {
+        if let Some(include_file) = node.include_file() {
+            let offset = node.span(source).start;
+            extractor.push(SymbolKind::Include, include_file.replace("\\", "/"), &None, None, offset);
+        }
+    }
+
+    let mut symbols = extractor.symbols;
+    symbols.sort_by(|a, b| (a.position.row, a.position.column).cmp(&(b.position.row, b.position.column)));
+    symbols
+}
+
+#[cfg(test)]
+mod tests {
+    use parser::{parse_progress, preprocess};
+    use super::{extract, scopes};
+    use super::super::SymbolKind;
+
+    #[test]
+    fn test_extract() {
+        let source = "{inc/customer.i}\n\
+                      DEFINE VARIABLE i AS INTEGER NO-UNDO.\n\
+                      DEFINE NEW SHARED TEMP-TABLE ttCustomer NO-UNDO FIELD Name AS CHARACTER.\n\
+                      DEFINE BUTTON btnOk LABEL \"OK\".\n\
+                      ON CHOOSE OF btnOk DO:\n    RUN ar/cust.p PERSISTENT SET h.\nEND.\n\
+                      PROCEDURE initialize:\n    DEFINE INPUT PARAMETER p AS INTEGER.\n    main: DO:\n        RUN VALUE(p) IN h.\n    END.\n    RUN done.\nEND PROCEDURE.\n\
+                      FUNCTION f RETURNS INTEGER FORWARD.\n\
+                      FUNCTION f RETURNS INTEGER ():\n    RETURN 1.\nEND FUNCTION.\n\
+                      i = f() + LENGTH(\"f\").\n";
+        let nodes = preprocess(source).unwrap();
+        let progress = parse_progress(source).unwrap();
+        let extracted = extract("w.p", source, &nodes, &progress);
+        let symbols: Vec<(SymbolKind, &str, Option<&str>, Option<&str>, u32)> = extracted.iter()
+            .map(|symbol| (symbol.kind, &symbol.name[..], symbol.scope.as_ref().map(String::as_str), symbol.detail.as_ref().map(String::as_str), symbol.position.row))
+            .collect();
+        assert_eq!(vec![
+            (SymbolKind::Include, "inc/customer.i", None, None, 1),
+            (SymbolKind::Variable, "i", None, None, 2),
+            (SymbolKind::TempTable, "ttCustomer", None, None, 3),
+            (SymbolKind::Definition, "btnOk", None, Some("BUTTON"), 4),
+            (SymbolKind::Trigger, "CHOOSE OF btnOk", None, None, 5),
+            (SymbolKind::Run, "ar/cust.p", Some("CHOOSE OF btnOk"), Some("PERSISTENT SET h"), 6),
+            (SymbolKind::Procedure, "initialize", None, None, 8),
+            (SymbolKind::Parameter, "p", Some("initialize"), None, 9),
+            (SymbolKind::Run, "VALUE(p)", Some("initialize"), Some("VALUE IN h"), 11),
+            (SymbolKind::Run, "done", Some("initialize"), None, 13),
+            (SymbolKind::Function, "f", None, None, 16),
+            (SymbolKind::FunctionCall, "f", None, None, 19),
+        ], symbols);
+
+        let scopes: Vec<(&str, &str)> = scopes(source, &progress).iter()
+            .map(|scope| (&scope.name[..], source[scope.span.start..scope.span.end].lines().last().unwrap()))
+            .collect();
+        assert_eq!(vec![("CHOOSE OF btnOk", "END."), ("initialize", "END PROCEDURE."), ("f", "END FUNCTION.")], scopes);
+    }
+
+    #[test]
+    fn test_extract_tables() {
+        let source = "FIND FIRST Customer NO-LOCK.\n\
+                      FOR EACH Order OF Customer, EACH OrderLine OF Order:\n    DELETE OrderLine.\nEND.\n\
+                      IF CAN-FIND(Item WHERE Item.ItemNum = 1) THEN CREATE Invoice.\n\
+                      CREATE BUTTON hButton.\n\
+                      DEFINE BUFFER bCustomer FOR Customer.\n";
+        let nodes = preprocess(source).unwrap();
+        let progress = parse_progress(source).unwrap();
+        let extracted = extract("t.p", source, &nodes, &progress);
+        let tables: Vec<(&str, Option<&str>, u32)> = extracted.iter()
+            .filter(|symbol| symbol.kind == SymbolKind::TableUse)
+            .map(|symbol| (&symbol.name[..], symbol.detail.as_ref().map(String::as_str), symbol.position.row))
+            .collect();
+        assert_eq!(vec![
+            ("Customer", Some("FIND"), 1),
+            ("Order", Some("FOR"), 2),
+            ("OrderLine", Some("FOR"), 2),
+            ("OrderLine", Some("DELETE"), 3),
+            ("Item", Some("IF"), 5),
+            ("Invoice", Some("IF"), 5),
+            ("Customer", Some("DEFINE"), 7),
+        ], tables);
+    }
+
+    #[test]
+    fn test_extract_custom_blocks() {
+        let source = "&ANALYZE-SUSPEND _UIB-CODE-BLOCK _CUSTOM _DEFINITIONS wWin\n\
+                      DEFINE VARIABLE i AS INTEGER.\n\
+                      &ANALYZE-RESUME\n";
+        let nodes = preprocess(source).unwrap();
+        let progress = parse_progress(source).unwrap();
+        let extracted = extract("w.w", source, &nodes, &progress);
+        let blocks: Vec<(&str, Option<&str>)> = extracted.iter()
+            .filter(|symbol| symbol.kind == SymbolKind::CustomBlock)
+            .map(|symbol| (&symbol.name[..], symbol.detail.as_ref().map(String::as_str)))
+            .collect();
+        assert_eq!(vec![("_DEFINITIONS", Some("wWin"))], blocks);
+    }
+}
@@ -0,0 +1,547 @@
+use std::ascii::AsciiExt;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt::Write;
+
+use url::form_urlencoded;
+
+use error::{ProgressResult, Error};
+use source::{FileKind, Propath};
+use util::escape_xml;
+use super::{CallResolver, Index, SymbolKind};
+
+// How far from the program a subgraph reaches when no depth is given
+const DEFAULT_DEPTH: usize = 1;
+
+/// Which relationships between programs a graph is of
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GraphKind {
+    /// RUN statements and calls of functions
+    Calls,
+    /// Include files
+    Includes,
+}
+
+impl GraphKind {
+    pub fn from_name(name: &str) -> Option<GraphKind> {
+        match &name.to_ascii_lowercase()[..] {
+            "calls" => Some(GraphKind::Calls),
+            "includes" => Some(GraphKind::Includes),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match *self {
+            GraphKind::Calls => "calls",
+            GraphKind::Includes => "includes",
+        }
+    }
+}
+
+/// What a graph is written as
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GraphFormat {
+    /// Graphviz
+    Dot,
+    GraphML,
+}
+
+impl GraphFormat {
+    pub fn from_name(name: &str) -> Option<GraphFormat> {
+        match &name.to_ascii_lowercase()[..] {
+            "dot" | "gv" => Some(GraphFormat::Dot),
+            "graphml" => Some(GraphFormat::GraphML),
+            _ => None,
+        }
+    }
+}
+
+/// What a node of a graph is
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NodeKind {
+    /// A .p
+    Program,
+    /// A .w
+    Window,
+    /// A .i
+    Include,
+    /// A .cls
+    Class,
+    /// A file of any other kind
+    File,
+    /// An internal procedure
+    Procedure,
+    Function,
+    Method,
+    Trigger,
+    /// What a RUN or include names but is not on the PROPATH, or is only known at run time
+    Unresolved,
+}
+
+impl NodeKind {
+    fn of_file(path: &str) -> NodeKind {
+        match FileKind::of(path) {
+            Some(FileKind::Procedure) => NodeKind::Program,
+            Some(FileKind::Window) => NodeKind::Window,
+            Some(FileKind::Include) => NodeKind::Include,
+            Some(FileKind::Class) => NodeKind::Class,
+            _ => NodeKind::File,
+        }
+    }
+
+    fn of_block(kind: SymbolKind) -> NodeKind {
+        match kind {
+            SymbolKind::Function => NodeKind::Function,
+            SymbolKind::Method => NodeKind::Method,
+            SymbolKind::Trigger => NodeKind::Trigger,
+            _ => NodeKind::Procedure,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match *self {
+            NodeKind::Program => "program",
+            NodeKind::Window => "window",
+            NodeKind::Include => "include",
+            NodeKind::Class => "class",
+            NodeKind::File => "file",
+            NodeKind::Procedure => "procedure",
+            NodeKind::Function => "function",
+            NodeKind::Method => "method",
+            NodeKind::Trigger => "trigger",
+            NodeKind::Unresolved => "unresolved",
+        }
+    }
+}
+
+/// How the start of an edge uses its end
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum EdgeKind {
+    Run,
+    /// RUN ... PERSISTENT
+    Persistent,
+    FunctionCall,
+    Include,
+}
+
+impl EdgeKind {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            EdgeKind::Run => "RUN",
+            EdgeKind::Persistent => "PERSISTENT",
+            EdgeKind::FunctionCall => "FUNCTION-CALL",
+            EdgeKind::Include => "INCLUDE",
+        }
+    }
+}
+
+/// A file, a block of one, or something unresolved
+#[derive(Debug, Clone, PartialEq)]
+pub struct GraphNode {
+    /// The path, `<path>#<block>` for a block, or `?<target>` for something unresolved
+    pub id: String,
+    pub kind: NodeKind,
+    /// The file the node is or is in. None when it is unresolved.
+    pub path: Option<String>,
+    pub block: Option<String>,
+}
+
+impl GraphNode {
+    fn label(&self) -> &str {
+        match (self.block.as_ref(), self.path.as_ref()) {
+            (Some(block), _) => block.as_str(),
+            (None, Some(path)) => path.as_str(),
+            (None, None) => &self.id[1..],
+        }
+    }
+
+    // The program the node belongs to, which subgraphs are measured between
+    fn program(&self) -> &str {
+        self.path.as_ref().map_or(&self.id[..], String::as_str)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct GraphEdge {
+    pub from: String,
+    pub to: String,
+    pub kind: EdgeKind,
+}
+
+/// The programs, blocks and include files of the index and how they use each other. Each
+/// relationship is one edge however many statements there are of it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Graph {
+    pub kind: GraphKind,
+    pub nodes: Vec<GraphNode>,
+    pub edges: Vec<GraphEdge>,
+}
+
+// Collects the nodes and edges of a graph without repeating any
+struct GraphBuilder {
+    graph: Graph,
+    nodes: HashSet<String>,
+    edges: HashSet<(String, String, EdgeKind)>,
+}
+
+impl GraphBuilder {
+    fn new(kind: GraphKind) -> Self {
+        GraphBuilder { graph: Graph { kind, nodes: Vec::new(), edges: Vec::new() }, nodes: HashSet::new(), edges: HashSet::new() }
+    }
+
+    fn node(&mut self, node: GraphNode) -> String {
+        let id = node.id.clone();
+        if self.nodes.insert(id.clone()) {
+            self.graph.nodes.push(node);
+        }
+        id
+    }
+
+    fn file(&mut self, path: &str) -> String {
+        self.node(GraphNode { id: path.to_string(), kind: NodeKind::of_file(path), path: Some(path.to_string()), block: None })
+    }
+
+    fn block(&mut self, path: &str, block: &str, kind: NodeKind) -> String {
+        self.file(path);
+        self.node(GraphNode { id: format!("{}#{}", path, block), kind, path: Some(path.to_string()), block: Some(block.to_string()) })
+    }
+
+    fn unresolved(&mut self, target: &str) -> String {
+        self.node(GraphNode { id: format!("?{}", target), kind: NodeKind::Unresolved, path: None, block: None })
+    }
+
+    fn edge(&mut self, from: String, to: String, kind: EdgeKind) {
+        if self.edges.insert((from.clone(), to.clone(), kind)) {
+            self.graph.edges.push(GraphEdge { from, to, kind });
+        }
+    }
+
+    fn finish(mut self) -> Graph {
+        // Each file is followed by its blocks
+        self.graph.nodes.sort_by(|a, b| (a.program(), a.block.is_some(), &a.id).cmp(&(b.program(), b.block.is_some(), &b.id)));
+        self.graph.edges.sort_by(|a, b| (&a.from, &a.to, a.kind.name()).cmp(&(&b.from, &b.to, b.kind.name())));
+        self.graph
+    }
+}
+
+// The node of `block` of `path`, a block the file defines, or of the file itself
+fn caller(graph: &mut GraphBuilder, blocks: &HashMap<String, SymbolKind>, path: &str, block: &Option<String>) -> String {
+    match *block {
+        Some(ref block) => {
+            let kind = blocks.get(&block.to_ascii_lowercase()).cloned().unwrap_or(SymbolKind::Procedure);
+            graph.block(path, block, NodeKind::of_block(kind))
+        },
+        None => graph.file(path),
+    }
+}
+
+/// The RUN statements and function calls of every file in the index, between the blocks they are
+/// in and the programs and blocks they run. RUN targets are resolved like /callers resolves them.
+pub fn call_graph(index: &Index, propath: &Propath) -> ProgressResult<Graph> {
+    let mut resolver = CallResolver::new(index, propath);
+    let mut graph = GraphBuilder::new(GraphKind::Calls);
+    for path in index.files()? {
+        let symbols = index.symbols_in(&path)?;
+        let blocks: HashMap<String, SymbolKind> = symbols.iter()
+            .filter(|symbol| [SymbolKind::Procedure, SymbolKind::Function, SymbolKind::Method, SymbolKind::Trigger].contains(&symbol.kind))
+            .map(|symbol| (symbol.name.to_ascii_lowercase(), symbol.kind))
+            .collect();
+        for symbol in &symbols {
+            match symbol.kind {
+                SymbolKind::Run => {
+                    let call = resolver.resolve(symbol)?;
+                    let from = caller(&mut graph, &blocks, &path, &call.caller.block);
+                    let to = match call.callee {
+                        Some(ref callee) => match callee.block {
+                            Some(ref block) => graph.block(&callee.program, block, NodeKind::Procedure),
+                            None => graph.file(&callee.program),
+                        },
+                        None => graph.unresolved(&call.target),
+                    };
+                    graph.edge(from, to, if call.persistent { EdgeKind::Persistent } else { EdgeKind::Run });
+                },
+                SymbolKind::FunctionCall => {
+                    let from = caller(&mut graph, &blocks, &path, &symbol.scope);
+                    let to = graph.block(&path, &symbol.name, NodeKind::Function);
+                    graph.edge(from, to, EdgeKind::FunctionCall);
+                },
+                _ => {},
+            }
+        }
+    }
+    Ok(graph.finish())
+}
+
+/// The include files of every file in the index. They are looked up like programs with an
+/// extension are.
+pub fn include_graph(index: &Index, propath: &Propath) -> ProgressResult<Graph> {
+    let mut resolver = CallResolver::new(index, propath);
+    let mut graph = GraphBuilder::new(GraphKind::Includes);
+    for path in index.files()? {
+        for symbol in index.symbols_in(&path)? {
+            if symbol.kind != SymbolKind::Include {
+                continue;
+            }
+            let from = graph.file(&path);
+            let to = match resolver.program(&symbol.name)? {
+                Some(include) => graph.file(&include),
+                None => graph.unresolved(&symbol.name),
+            };
+            graph.edge(from, to, EdgeKind::Include);
+        }
+    }
+    Ok(graph.finish())
+}
+
+/// Which graph a request wants, from `format`, `around` and `depth` in its query string
+#[derive(Debug, Clone, PartialEq)]
+pub struct GraphQuery {
+    pub format: GraphFormat,
+    /// The program to give the subgraph around, instead of the whole graph
+    pub around: Option<String>,
+    /// How many programs away from `around` the subgraph reaches
+    pub depth: Option<usize>,
+}
+
+impl Default for GraphQuery {
+    fn default() -> Self {
+        GraphQuery { format: GraphFormat::Dot, around: None, depth: None }
+    }
+}
+
+impl GraphQuery {
+    /// The graph a request wants, from the query string of it
+    pub fn from_query(query: &str) -> ProgressResult<Self> {
+        let mut graph = GraphQuery::default();
+        for (key, value) in form_urlencoded::parse(query.as_bytes()) {
+            match &key[..] {
+                "format" => match GraphFormat::from_name(&value) {
+                    Some(format) => graph.format = format,
+                    None => return Err(Error::BadRequest(format!("Unknown graph format '{}'", value))),
+                },
+                "around" => graph.around = Some(value.into_owned()),
+                "depth" => match value.parse() {
+                    Ok(depth) => graph.depth = Some(depth),
+                    Err(_) => return Err(Error::BadRequest("depth is not a number of programs".to_string())),
+                },
+                _ => {},
+            }
+        }
+        Ok(graph)
+    }
+}
+
+/// The graph of `kind`, or the subgraph of it `query` asks for
+pub fn build_graph(index: &Index, propath: &Propath, kind: GraphKind, query: &GraphQuery) -> ProgressResult<Graph> {
+    let graph = match kind {
+        GraphKind::Calls => call_graph(index, propath)?,
+        GraphKind::Includes => include_graph(index, propath)?,
+    };
+    match query.around {
+        Some(ref name) => {
+            let program = CallResolver::new(index, propath).program(name)?
+                .ok_or_else(|| Error::NotFound(format!("'{}' is not on the PROPATH", name)))?;
+            Ok(graph.around(&program, query.depth.unwrap_or(DEFAULT_DEPTH)))
+        },
+        None => Ok(graph),
+    }
+}
+
+// A string in DOT, with quotes
+fn dot_string(text: &str) -> String {
+    format!("\"{}\"", text.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+impl Graph {
+    /// The nodes of the programs at most `depth` edges from `program`, following edges both
+    /// ways, and the edges between them. Every block of a program is as far away as it is.
+    pub fn around(&self, program: &str, depth: usize) -> Graph {
+        let programs: HashMap<&str, &str> = self.nodes.iter().map(|node| (&node.id[..], node.program())).collect();
+        let mut neighbours: HashMap<&str, Vec<&str>> = HashMap::new();
+        for edge in &self.edges {
+            let (from, to) = (programs[&edge.from[..]], programs[&edge.to[..]]);
+            neighbours.entry(from).or_insert_with(Vec::new).push(to);
+            neighbours.entry(to).or_insert_with(Vec::new).push(from);
+        }
+        let mut distances: HashMap<&str, usize> = HashMap::new();
+        distances.insert(program, 0);
+        let mut queue = VecDeque::new();
+        queue.push_back(program);
+        while let Some(next) = queue.pop_front() {
+            let distance = distances[next];
+            if distance == depth {
+                continue;
+            }
+            for &neighbour in neighbours.get(next).map_or(&[][..], |found| &found[..]) {
+                if !distances.contains_key(neighbour) {
+                    distances.insert(neighbour, distance + 1);
+                    queue.push_back(neighbour);
+                }
+            }
+        }
+        let kept = |id: &str| distances.contains_key(programs[id]);
+        Graph {
+            kind: self.kind,
+            nodes: self.nodes.iter().filter(|node| kept(&node.id[..])).cloned().collect(),
+            edges: self.edges.iter().filter(|edge| kept(&edge.from[..]) && kept(&edge.to[..])).cloned().collect(),
+        }
+    }
+
+    pub fn render(&self, format: GraphFormat) -> String {
+        match format {
+            GraphFormat::Dot => self.to_dot(),
+            GraphFormat::GraphML => self.to_graphml(),
+        }
+    }
+
+    /// The graph in Graphviz DOT. The blocks of a file are drawn in a cluster with it.
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::new();
+        let _ = write!(dot, "digraph {} {{\n    rankdir=LR;\n    node [shape=box];\n", self.kind.name());
+        let mut i = 0;
+        let mut cluster = 0;
+        while i < self.nodes.len() {
+            let program = self.nodes[i].program();
+            let end = i + self.nodes[i..].iter().position(|node| node.program() != program).unwrap_or(self.nodes.len() - i);
+            let indent = if end - i > 1 {
+                let _ = write!(dot, "    subgraph cluster_{} {{\n        label={};\n", cluster, dot_string(program));
+                cluster += 1;
+                "        "
+            } else {
+                "    "
+            };
+            for node in &self.nodes[i..end] {
+                let _ = write!(dot, "{}{} [label={}, kind={}", indent, dot_string(&node.id), dot_string(node.label()), dot_string(node.kind.name()));
+                if let Some(ref path) = node.path {
+                    let _ = write!(dot, ", path={}", dot_string(path));
+                }
+                match node.kind {
+                    NodeKind::Procedure | NodeKind::Function | NodeKind::Method | NodeKind::Trigger => dot.push_str(", shape=ellipse"),
+                    NodeKind::Unresolved => dot.push_str(", style=dashed"),
+                    _ => {},
+                }
+                dot.push_str("];\n");
+            }
+            if end - i > 1 {
+                dot.push_str("    }\n");
+            }
+            i = end;
+        }
+        for edge in &self.edges {
+            let _ = write!(dot, "    {} -> {} [label={}, type={}];\n",
+                           dot_string(&edge.from), dot_string(&edge.to), dot_string(edge.kind.name()), dot_string(edge.kind.name()));
+        }
+        dot.push_str("}\n");
+        dot
+    }
+
+    /// The graph in GraphML, with the kind, path and block of each node and the type of each edge
+    pub fn to_graphml(&self) -> String {
+        let mut xml = String::new();
+        xml.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+        xml.push_str("<graphml xmlns=\"http://graphml.graphdrawing.org/xmlns\">\n");
+        for &(key, target) in &[("kind", "node"), ("path", "node"), ("block", "node"), ("type", "edge")] {
+            let _ = write!(xml, "  <key id=\"{0}\" for=\"{1}\" attr.name=\"{0}\" attr.type=\"string\"/>\n", key, target);
+        }
+        let _ = write!(xml, "  <graph id=\"{}\" edgedefault=\"directed\">\n", self.kind.name());
+        for node in &self.nodes {
+            let _ = write!(xml, "    <node id=\"{}\"><data key=\"kind\">{}</data>", escape_xml(&node.id), node.kind.name());
+            if let Some(ref path) = node.path {
+                let _ = write!(xml, "<data key=\"path\">{}</data>", escape_xml(path));
+            }
+            if let Some(ref block) = node.block {
+                let _ = write!(xml, "<data key=\"block\">{}</data>", escape_xml(block));
+            }
+            xml.push_str("</node>\n");
+        }
+        for edge in &self.edges {
+            let _ = write!(xml, "    <edge source=\"{}\" target=\"{}\"><data key=\"type\">{}</data></edge>\n",
+                           escape_xml(&edge.from), escape_xml(&edge.to), edge.kind.name());
+        }
+        xml.push_str("  </graph>\n</graphml>\n");
+        xml
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env::temp_dir;
+    use std::fs::{File, create_dir_all, remove_dir_all};
+    use std::io::Write;
+    use std::time::Duration;
+
+    use source::{ContentCache, HttpClient, Sources};
+    use super::super::Index;
+    use super::super::indexer::update_file;
+    use super::{EdgeKind, GraphFormat, GraphKind, GraphQuery, NodeKind, build_graph, call_graph, include_graph};
+
+    #[test]
+    fn test_graphs() {
+        let root = temp_dir().join("progress_server_test_graphs");
+        let _ = remove_dir_all(&root);
+        create_dir_all(root.join("src/inc")).unwrap();
+        File::create(root.join("src/main.p")).unwrap()
+            .write_all(b"{inc/defs.i}\n{inc/missing.i}\nRUN ar.p PERSISTENT SET h.\nRUN VALUE(p).\n\
+                         PROCEDURE start:\n    i = total(1).\nEND PROCEDURE.\n\
+                         FUNCTION total RETURNS INTEGER (n AS INTEGER):\n    RUN report.p.\nEND FUNCTION.\n").unwrap();
+        File::create(root.join("src/ar.p")).unwrap().write_all(b"RUN report.p.\n").unwrap();
+        File::create(root.join("src/report.p")).unwrap().write_all(b"{inc/defs.i}\n").unwrap();
+        File::create(root.join("src/inc/defs.i")).unwrap();
+
+        let sources = Sources::new(vec![root.join("src").to_string_lossy().into_owned()], 1,
+                                   HttpClient::new(Duration::from_secs(1), Duration::from_secs(1), 0), ContentCache::new(0)).unwrap();
+        let propath = sources.propath(None).unwrap();
+        let index = Index::open(root.join("index.sqlite")).unwrap();
+        for path in &["main.p", "ar.p", "report.p", "inc/defs.i"] {
+            update_file(&index, &propath, path).unwrap();
+        }
+
+        let calls = call_graph(&index, &propath).unwrap();
+        let nodes: Vec<(&str, NodeKind)> = calls.nodes.iter().map(|node| (&node.id[..], node.kind)).collect();
+        assert_eq!(vec![
+            ("?VALUE(p)", NodeKind::Unresolved),
+            ("ar.p", NodeKind::Program),
+            ("main.p", NodeKind::Program),
+            ("main.p#start", NodeKind::Procedure),
+            ("main.p#total", NodeKind::Function),
+            ("report.p", NodeKind::Program),
+        ], nodes);
+        let edges: Vec<(&str, &str, EdgeKind)> = calls.edges.iter().map(|edge| (&edge.from[..], &edge.to[..], edge.kind)).collect();
+        assert_eq!(vec![
+            ("ar.p", "report.p", EdgeKind::Run),
+            ("main.p", "?VALUE(p)", EdgeKind::Run),
+            ("main.p", "ar.p", EdgeKind::Persistent),
+            ("main.p#start", "main.p#total", EdgeKind::FunctionCall),
+            ("main.p#total", "report.p", EdgeKind::Run),
+        ], edges);
+
+        let includes = include_graph(&index, &propath).unwrap();
+        let edges: Vec<(&str, &str)> = includes.edges.iter().map(|edge| (&edge.from[..], &edge.to[..])).collect();
+        assert_eq!(vec![("main.p", "?inc/missing.i"), ("main.p", "inc/defs.i"), ("report.p", "inc/defs.i")], edges);
+
+        // ar.p is next to main.p and report.p, and the blocks of main.p come with it
+        let query = GraphQuery { around: Some("ar".to_string()), depth: Some(1), ..GraphQuery::default() };
+        let around = build_graph(&index, &propath, GraphKind::Calls, &query).unwrap();
+        assert_eq!(vec!["ar.p", "main.p", "main.p#start", "main.p#total", "report.p"],
+                   around.nodes.iter().map(|node| &node.id[..]).collect::<Vec<_>>());
+        assert_eq!(4, around.edges.len());
+        let query = GraphQuery { around: Some("report.p".to_string()), depth: Some(0), ..GraphQuery::default() };
+        assert_eq!(0, build_graph(&index, &propath, GraphKind::Calls, &query).unwrap().edges.len());
+        let query = GraphQuery { around: Some("nowhere.p".to_string()), ..GraphQuery::default() };
+        assert!(build_graph(&index, &propath, GraphKind::Calls, &query).is_err());
+
+        let query = GraphQuery::from_query("format=GraphML&around=ar.p&depth=2").unwrap();
+        assert_eq!(GraphQuery { format: GraphFormat::GraphML, around: Some("ar.p".to_string()), depth: Some(2) }, query);
+        assert!(GraphQuery::from_query("format=svg").is_err());
+        assert!(GraphQuery::from_query("depth=far").is_err());
+
+        let dot = includes.to_dot();
+        assert!(dot.starts_with("digraph includes {\n"));
+        assert!(dot.contains("    \"main.p\" -> \"inc/defs.i\" [label=\"INCLUDE\", type=\"INCLUDE\"];\n"));
+        let graphml = calls.to_graphml();
+        assert!(graphml.contains("<node id=\"main.p#total\"><data key=\"kind\">function</data><data key=\"path\">main.p</data><data key=\"block\">total</data></node>"));
+        assert!(graphml.contains("<edge source=\"main.p\" target=\"ar.p\"><data key=\"type\">PERSISTENT</data></edge>"));
+
+        remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,334 @@
+use std::collections::HashSet;
+use std::panic::{AssertUnwindSafe, catch_unwind};
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::{RecvTimeoutError, Sender, channel};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use codepage::decode;
+use config::{get_bool, get_integer};
+use error::{ProgressResult, Error};
+use parser::{parse_progress, preprocess};
+use source::{DirEntry, FileKind, Propath, SourceProvider, Sources};
+use util::parallel_map;
+use super::{Index, IndexedFile, extract, trigrams};
+use super::watch::{ChangeFeed, watch};
+
+const DEFAULT_INDEX_INTERVAL_SECS: i64 = 600;
+
+/// How the background indexer is doing, for the admin route
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct IndexStatus {
+    /// "starting", "crawling" or "idle"
+    pub state: String,
+    /// How many crawls finished since the server started
+    pub crawls: u64,
+    /// The source files found by the current or last crawl
+    pub files_found: u64,
+    /// How many of those were looked at so far
+    pub files_done: u64,
+    /// How many of those had changed and were parsed again
+    pub files_changed: u64,
+    /// How many of those could not be read or parsed
+    pub files_failed: u64,
+    /// When the current or last crawl started and finished, in seconds since the unix epoch
+    pub started: Option<u64>,
+    pub finished: Option<u64>,
+    pub last_error: Option<String>,
+    /// What is in the index right now
+    pub indexed_files: u64,
+    pub indexed_symbols: u64,
+}
+
+/// Crawls the PROPATH in the background and keeps the symbols of every source file in an `Index`.
+/// Files whose size and modification time did not change since they were indexed are skipped, so
+/// after a restart only what changed is parsed again.
+pub struct Indexer {
+    index: Arc<Index>,
+    status: Arc<Mutex<IndexStatus>>,
+    refresh: Mutex<Sender<()>>,
+    changes: Arc<ChangeFeed>,
+}
+
+impl Indexer {
+    /// Crawl now, then every `interval` and whenever `refresh` is called
+    pub fn start(sources: Sources, index: Index, interval: Option<Duration>) -> Self {
+        let index = Arc::new(index);
+        let status = Arc::new(Mutex::new(IndexStatus {
+            state: "starting".to_string(),
+            crawls: 0,
+            files_found: 0,
+            files_done: 0,
+            files_changed: 0,
+            files_failed: 0,
+            started: None,
+            finished: None,
+            last_error: None,
+            indexed_files: 0,
+            indexed_symbols: 0,
+        }));
+        let (sender, receiver) = channel();
+        {
+            let index = index.clone();
+            let status = status.clone();
+            thread::spawn(move || loop {
+                // A crawl that panics is reported like one that failed, and the next one still runs
+                let error = match catch_unwind(AssertUnwindSafe(|| crawl(&sources, &index, &status))) {
+                    Ok(result) => result.err().map(|err| err.to_string()),
+                    Err(_) => Some("The crawl stopped on an internal error".to_string()),
+                };
+                if let Some(error) = error {
+                    status.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).last_error = Some(error);
+                }
+                {
+                    let mut status = status.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+                    status.state = "idle".to_string();
+                    status.crawls += 1;
+                    status.finished = Some(now());
+                }
+                // Refreshes asked for during the crawl are covered by the next one
+                while receiver.try_recv().is_ok() {}
+                let wait = match interval {
+                    Some(interval) => receiver.recv_timeout(interval),
+                    None => receiver.recv().map_err(|_| RecvTimeoutError::Disconnected),
+                };
+                if let Err(RecvTimeoutError::Disconnected) = wait {
+                    break;
+                }
+            });
+        }
+        Indexer { index, status, refresh: Mutex::new(sender), changes: Arc::new(ChangeFeed::new()) }
+    }
+
+    /// Holds `index_path`, `index_interval_secs` and `watch_sources` from Rocket.toml. An
+    /// interval of 0 only crawls at startup and when asked to.
+    pub fn from_config(sources: &Sources) -> ProgressResult<Self> {
+        let interval = get_integer("index_interval_secs")?.unwrap_or(DEFAULT_INDEX_INTERVAL_SECS);
+        if interval < 0 {
+            return Err(Error::new("index_interval_secs can not be negative"));
+        }
+        let interval = if interval == 0 { None } else { Some(Duration::from_secs(interval as u64)) };
+        let indexer = Indexer::start(sources.clone(), Index::from_config()?, interval);
+        if get_bool("watch_sources")?.unwrap_or(true) {
+            indexer.watch(sources)?;
+        }
+        Ok(indexer)
+    }
+
+    /// Keep the index up to date with the local directories on the PROPATH as they change
+    pub fn watch(&self, sources: &Sources) -> ProgressResult<()> {
+        let refresh = self.refresh.lock().unwrap().clone();
+        watch(sources.clone(), self.index.clone(), self.changes.clone(), refresh)
+    }
+
+    pub fn index(&self) -> &Index {
+        &self.index
+    }
+
+    /// The files that changed while the sources were watched
+    pub fn changes(&self) -> &ChangeFeed {
+        &self.changes
+    }
+
+    pub fn status(&self) -> ProgressResult<IndexStatus> {
+        let mut status = self.status.lock().unwrap().clone();
+        let (files, symbols) = self.index.counts()?;
+        status.indexed_files = files;
+        status.indexed_symbols = symbols;
+        Ok(status)
+    }
+
+    /// Crawl again as soon as the current crawl is done
+    pub fn refresh(&self) {
+        let _ = self.refresh.lock().unwrap().send(());
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|duration| duration.as_secs()).unwrap_or(0)
+}
+
+/// Whether the indexer parses files like `path`
+pub fn is_indexed(path: &str) -> bool {
+    match FileKind::of(path) {
+        Some(FileKind::Procedure) | Some(FileKind::Window) | Some(FileKind::Include) | Some(FileKind::Class) => true,
+        _ => false,
+    }
+}
+
+// What a file looked like when it was indexed. Without a modification time the file is always
+// parsed again.
+fn fingerprint(size: u64, modified: Option<u64>) -> Option<String> {
+    modified.map(|modified| format!("{}-{}", size, modified))
+}
+
+fn walk(propath: &Propath, path: &str, files: &mut Vec<DirEntry>) -> ProgressResult<()> {
+    for entry in propath.list(path)? {
+        if entry.is_dir {
+            walk(propath, &entry.path, files)?;
+        } else if is_indexed(&entry.path) {
+            files.push(entry);
+        }
+    }
+    Ok(())
+}
+
+/// Read the file, and find its trigrams and its symbols. A file that can not be parsed is still
+/// searchable by its text, so the parse error is kept rather than returned. So is a parse that
+/// panics, which is a bug in the parser that should not stop the rest of the crawl.
+pub fn index_file(source: &SourceProvider, path: &str) -> ProgressResult<IndexedFile> {
+    let file = source.get_file(path)?;
+    let decoded = decode(&file.contents, file.codepage.as_ref().map(String::as_str))?;
+    let text = &decoded.text;
+    let symbols = catch_unwind(AssertUnwindSafe(|| preprocess(text).and_then(|nodes| {
+        let progress = parse_progress(text)?;
+        Ok(extract(path, text, &nodes, &progress))
+    }))).unwrap_or_else(|_| Err(Error::ParseError("The parser stopped on an internal error".to_string())));
+    let trigrams = trigrams(text);
+    Ok(match symbols {
+        Ok(symbols) => IndexedFile { symbols, trigrams, error: None },
+        Err(err) => IndexedFile { symbols: Vec::new(), trigrams, error: Some(err.to_string()) },
+    })
+}
+
+// What is kept for a file that could not be indexed
+fn failed(err: &Error) -> IndexedFile {
+    IndexedFile { error: Some(err.to_string()), ..IndexedFile::default() }
+}
+
+/// Index the file at `path` again, or drop it from the index when it is no longer on the PROPATH
+pub fn update_file(index: &Index, propath: &Propath, path: &str) -> ProgressResult<()> {
+    let stat = match propath.stat(path) {
+        Ok(stat) => stat,
+        Err(Error::NotFound(_)) => return index.remove_file(path),
+        Err(err) => return Err(err),
+    };
+    if stat.is_dir || !is_indexed(path) {
+        return Ok(());
+    }
+    let fingerprint = fingerprint(stat.size, stat.modified).unwrap_or(String::new());
+    match index_file(propath, path) {
+        Ok(file) => index.replace_file(path, &fingerprint, &file),
+        Err(err) => if is_read_error(&err) {
+            Err(err)
+        } else {
+            index.replace_file(path, &fingerprint, &failed(&err))
+        },
+    }
+}
+
+// Whether the file could not be read, rather than not parsed. Those keep what was indexed before.
+fn is_read_error(err: &Error) -> bool {
+    match *err {
+        Error::NotFound(_) | Error::Upstream(_) | Error::Timeout(_) | Error::FromError(_) => true,
+        _ => false,
+    }
+}
+
+fn crawl(sources: &Sources, index: &Index, status: &Mutex<IndexStatus>) -> ProgressResult<()> {
+    {
+        let mut status = status.lock().unwrap();
+        status.state = "crawling".to_string();
+        status.files_found = 0;
+        status.files_done = 0;
+        status.files_changed = 0;
+        status.files_failed = 0;
+        status.started = Some(now());
+        status.finished = None;
+        status.last_error = None;
+    }
+    let propath = sources.propath(None)?;
+    let mut files = Vec::new();
+    walk(&propath, "", &mut files)?;
+    status.lock().unwrap().files_found = files.len() as u64;
+
+    let parallelism = sources.parallelism();
+    for chunk in files.chunks(parallelism * 4) {
+        // None for the files that did not change
+        let results = parallel_map(chunk, parallelism, |entry| -> ProgressResult<Option<IndexedFile>> {
+            let fingerprint = fingerprint(entry.size, entry.modified);
+            if fingerprint.is_some() && index.fingerprint(&entry.path)? == fingerprint {
+                return Ok(None);
+            }
+            index_file(&propath, &entry.path).map(Some)
+        });
+        let mut status = status.lock().unwrap();
+        for (entry, result) in chunk.iter().zip(results) {
+            let fingerprint = fingerprint(entry.size, entry.modified).unwrap_or(String::new());
+            status.files_done += 1;
+            match result {
+                Ok(None) => {},
+                Ok(Some(file)) => {
+                    status.files_changed += 1;
+                    if let Some(ref error) = file.error {
+                        status.files_failed += 1;
+                        status.last_error = Some(format!("{}: {}", entry.path, error));
+                    }
+                    index.replace_file(&entry.path, &fingerprint, &file)?;
+                },
+                Err(err) => {
+                    status.files_failed += 1;
+                    if !is_read_error(&err) {
+                        status.files_changed += 1;
+                        index.replace_file(&entry.path, &fingerprint, &failed(&err))?;
+                    }
+                    status.last_error = Some(format!("{}: {}", entry.path, err));
+                },
+            }
+        }
+    }
+
+    // Forget the files that are gone
+    let found: HashSet<&str> = files.iter().map(|entry| &entry.path[..]).collect();
+    for path in index.files()? {
+        if !found.contains(&path[..]) {
+            index.remove_file(&path)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env::temp_dir;
+    use std::fs::{File, create_dir_all, remove_dir_all};
+    use std::io::Write;
+    use std::sync::Mutex;
+    use std::time::Duration;
+
+    use source::{ContentCache, HttpClient, Sources};
+    use super::super::Index;
+    use super::{IndexStatus, crawl};
+
+    #[test]
+    fn test_crawl() {
+        let root = temp_dir().join("progress_server_test_crawl");
+        let _ = remove_dir_all(&root);
+        create_dir_all(root.join("src/inc")).unwrap();
+        File::create(root.join("src/a.p")).unwrap().write_all(b"{inc/b.i}\nRUN c.p.\n").unwrap();
+        File::create(root.join("src/inc/b.i")).unwrap().write_all(b"DEFINE VARIABLE i AS INTEGER.\n").unwrap();
+        File::create(root.join("src/notes.txt")).unwrap().write_all(b"RUN x.p.").unwrap();
+
+        let sources = Sources::new(vec![root.join("src").to_string_lossy().into_owned()], 2,
+                                   HttpClient::new(Duration::from_secs(1), Duration::from_secs(1), 0), ContentCache::new(0)).unwrap();
+        let index = Index::open(root.join("index.sqlite")).unwrap();
+        let status = Mutex::new(IndexStatus {
+            state: String::new(), crawls: 0, files_found: 0, files_done: 0, files_changed: 0, files_failed: 0,
+            started: None, finished: None, last_error: None, indexed_files: 0, indexed_symbols: 0,
+        });
+
+        crawl(&sources, &index, &status).unwrap();
+        assert_eq!((2, 2), { let status = status.lock().unwrap(); (status.files_found, status.files_changed) });
+        assert_eq!(vec!["a.p".to_string(), "inc/b.i".to_string()], index.files().unwrap());
+        assert_eq!(2, index.symbols_in("a.p").unwrap().len());
+
+        // Only what changed is parsed again, and deleted files are dropped
+        crawl(&sources, &index, &status).unwrap();
+        assert_eq!(0, status.lock().unwrap().files_changed);
+        ::std::fs::remove_file(root.join("src/inc/b.i")).unwrap();
+        crawl(&sources, &index, &status).unwrap();
+        assert_eq!(vec!["a.p".to_string()], index.files().unwrap());
+
+        remove_dir_all(&root).unwrap();
+    }
+}
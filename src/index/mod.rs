@@ -0,0 +1,361 @@
+mod calls;
+mod extract;
+mod graph;
+mod indexer;
+mod query;
+mod search;
+mod structure;
+mod text;
+mod watch;
+
+use std::ascii::AsciiExt;
+use std::path::Path;
+use std::sync::Mutex;
+
+use rusqlite::Connection;
+use rusqlite::types::ToSql;
+
+use config::get_string;
+use error::{ProgressResult, Error};
+use parser::FilePosition;
+use util::{escape_like, glob_to_like};
+
+pub use self::calls::{Call, CallKind, CallResolver, Callable, callees, callers};
+pub use self::extract::extract;
+pub use self::graph::{EdgeKind, Graph, GraphEdge, GraphFormat, GraphKind, GraphNode, GraphQuery, NodeKind, build_graph, call_graph, include_graph};
+pub use self::indexer::{IndexStatus, Indexer};
+pub use self::query::{SearchQuery, SearchResult};
+pub use self::search::{MatchMode, MatchQuality, SymbolMatch, SymbolQuery};
+pub use self::structure::{Pattern, StructureHit, StructureQuery, search_structure};
+pub use self::text::{TextHit, TextQuery, search_text, trigrams};
+pub use self::watch::{ChangeFeed, Changes, Since};
+
+/// What a symbol in the index is
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SymbolKind {
+    Procedure,
+    Function,
+    /// An ON statement; the name is its event and widget, like "CHOOSE OF btnOk"
+    Trigger,
+    Class,
+    Method,
+    Variable,
+    TempTable,
+    Buffer,
+    Parameter,
+    /// Any other DEFINE, like a FRAME, BUTTON or QUERY. The detail says which.
+    Definition,
+    /// An AppBuilder _CUSTOM code block, like _DEFINITIONS or _MAIN-BLOCK. The detail is its frame.
+    CustomBlock,
+    /// A file pulled in with {...}
+    Include,
+    /// The target of a RUN statement
+    Run,
+    /// A call of a function the same file defines
+    FunctionCall,
+    /// A table, temp-table or buffer a statement reads or changes. The detail is the first word
+    /// of the statement, like FIND or FOR.
+    TableUse,
+}
+
+const SYMBOL_KINDS: &'static [SymbolKind] = &[
+    SymbolKind::Procedure,
+    SymbolKind::Function,
+    SymbolKind::Trigger,
+    SymbolKind::Class,
+    SymbolKind::Method,
+    SymbolKind::Variable,
+    SymbolKind::TempTable,
+    SymbolKind::Buffer,
+    SymbolKind::Parameter,
+    SymbolKind::Definition,
+    SymbolKind::CustomBlock,
+    SymbolKind::Include,
+    SymbolKind::Run,
+    SymbolKind::FunctionCall,
+    SymbolKind::TableUse,
+];
+
+impl SymbolKind {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            SymbolKind::Procedure => "Procedure",
+            SymbolKind::Function => "Function",
+            SymbolKind::Trigger => "Trigger",
+            SymbolKind::Class => "Class",
+            SymbolKind::Method => "Method",
+            SymbolKind::Variable => "Variable",
+            SymbolKind::TempTable => "TempTable",
+            SymbolKind::Buffer => "Buffer",
+            SymbolKind::Parameter => "Parameter",
+            SymbolKind::Definition => "Definition",
+            SymbolKind::CustomBlock => "CustomBlock",
+            SymbolKind::Include => "Include",
+            SymbolKind::Run => "Run",
+            SymbolKind::FunctionCall => "FunctionCall",
+            SymbolKind::TableUse => "TableUse",
+        }
+    }
+
+    /// The kind called `name`, ignoring case
+    pub fn from_name(name: &str) -> Option<SymbolKind> {
+        SYMBOL_KINDS.iter().find(|kind| kind.name().eq_ignore_ascii_case(name)).cloned()
+    }
+}
+
+/// Something found in a source file
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Symbol {
+    pub path: String,
+    pub kind: SymbolKind,
+    pub name: String,
+    /// The procedure, function, method or trigger the symbol is in
+    pub scope: Option<String>,
+    /// More about the symbol, like the type of a definition or the options of a RUN
+    pub detail: Option<String>,
+    pub position: FilePosition,
+}
+
+fn has_wildcards(text: &str) -> bool {
+    text.contains('*') || text.contains('?')
+}
+
+// The LIKE pattern for the files a search is limited to: a glob, or a directory or file prefix
+// when it has no wildcards
+fn path_like(path: Option<&str>) -> String {
+    match path {
+        Some(path) if has_wildcards(path) => glob_to_like(path),
+        Some(path) => format!("{}%", escape_like(path)),
+        None => "%".to_string(),
+    }
+}
+
+const DEFAULT_INDEX_PATH: &'static str = "progress_index.sqlite";
+
+// Bumped whenever what is extracted from a file changes, so every file is indexed again
+const INDEX_VERSION: i64 = 6;
+
+/// Everything the index keeps about one file
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct IndexedFile {
+    pub symbols: Vec<Symbol>,
+    /// The trigrams of its text, from `trigrams`
+    pub trigrams: Vec<u32>,
+    /// Why it could not be parsed, if it could not
+    pub error: Option<String>,
+}
+
+/// The symbols and trigrams of every indexed file, kept in an SQLite database so they survive
+/// restarts
+pub struct Index {
+    conn: Mutex<Connection>,
+}
+
+impl Index {
+    /// Open the index at `index_path` in Rocket.toml
+    pub fn from_config() -> ProgressResult<Self> {
+        Index::open(get_string("index_path")?.unwrap_or(DEFAULT_INDEX_PATH.to_string()))
+    }
+
+    /// Open the index at `path`, creating it when it does not exist
+    pub fn open<P: AsRef<Path>>(path: P) -> ProgressResult<Self> {
+        let conn = Connection::open(path)?;
+        conn.execute_batch("
+            CREATE TABLE IF NOT EXISTS files (
+                path TEXT PRIMARY KEY,
+                fingerprint TEXT NOT NULL,
+                error TEXT
+            );
+            CREATE TABLE IF NOT EXISTS symbols (
+                path TEXT NOT NULL,
+                kind TEXT NOT NULL,
+                name TEXT NOT NULL,
+                scope TEXT,
+                detail TEXT,
+                row INTEGER NOT NULL,
+                col INTEGER NOT NULL
+            );
+            CREATE INDEX IF NOT EXISTS symbols_by_path ON symbols (path);
+            CREATE INDEX IF NOT EXISTS symbols_by_name ON symbols (name COLLATE NOCASE);
+            CREATE TABLE IF NOT EXISTS trigrams (
+                trigram INTEGER NOT NULL,
+                path TEXT NOT NULL
+            );
+            CREATE INDEX IF NOT EXISTS trigrams_by_trigram ON trigrams (trigram);
+            CREATE INDEX IF NOT EXISTS trigrams_by_path ON trigrams (path);
+        ")?;
+        let version: i64 = conn.query_row("PRAGMA user_version", &[], |row| row.get(0))?;
+        if version < INDEX_VERSION {
+            conn.execute_batch(&format!("DELETE FROM symbols; DELETE FROM trigrams; DELETE FROM files; PRAGMA user_version = {};", INDEX_VERSION))?;
+        }
+        Ok(Index { conn: Mutex::new(conn) })
+    }
+
+    /// What the file looked like when it was indexed, to tell whether it has to be indexed again
+    pub fn fingerprint(&self, path: &str) -> ProgressResult<Option<String>> {
+        let conn = self.conn.lock().unwrap();
+        let mut statement = conn.prepare("SELECT fingerprint FROM files WHERE path = ?1")?;
+        let mut rows = statement.query_map(&[&path], |row| row.get(0))?;
+        match rows.next() {
+            Some(fingerprint) => Ok(Some(fingerprint?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Replace everything known about the file
+    pub fn replace_file(&self, path: &str, fingerprint: &str, file: &IndexedFile) -> ProgressResult<()> {
+        let mut conn = self.conn.lock().unwrap();
+        let transaction = conn.transaction()?;
+        transaction.execute("DELETE FROM symbols WHERE path = ?1", &[&path])?;
+        transaction.execute("DELETE FROM trigrams WHERE path = ?1", &[&path])?;
+        transaction.execute("INSERT OR REPLACE INTO files (path, fingerprint, error) VALUES (?1, ?2, ?3)", &[&path, &fingerprint, &file.error])?;
+        for symbol in &file.symbols {
+            transaction.execute(
+                "INSERT INTO symbols (path, kind, name, scope, detail, row, col) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+                &[&path, &symbol.kind.name(), &symbol.name, &symbol.scope, &symbol.detail, &(symbol.position.row as i64), &(symbol.position.column as i64)])?;
+        }
+        {
+            let mut insert = transaction.prepare("INSERT INTO trigrams (trigram, path) VALUES (?1, ?2)")?;
+            for &trigram in &file.trigrams {
+                insert.execute(&[&(trigram as i64), &path])?;
+            }
+        }
+        transaction.commit()?;
+        Ok(())
+    }
+
+    pub fn remove_file(&self, path: &str) -> ProgressResult<()> {
+        let mut conn = self.conn.lock().unwrap();
+        let transaction = conn.transaction()?;
+        transaction.execute("DELETE FROM symbols WHERE path = ?1", &[&path])?;
+        transaction.execute("DELETE FROM trigrams WHERE path = ?1", &[&path])?;
+        transaction.execute("DELETE FROM files WHERE path = ?1", &[&path])?;
+        transaction.commit()?;
+        Ok(())
+    }
+
+    /// Every indexed path
+    pub fn files(&self) -> ProgressResult<Vec<String>> {
+        let conn = self.conn.lock().unwrap();
+        let mut statement = conn.prepare("SELECT path FROM files ORDER BY path")?;
+        let rows = statement.query_map(&[], |row| row.get(0))?;
+        let mut files = Vec::new();
+        for path in rows {
+            files.push(path?);
+        }
+        Ok(files)
+    }
+
+    /// The symbols of one file, in the order they are in the file
+    pub fn symbols_in(&self, path: &str) -> ProgressResult<Vec<Symbol>> {
+        self.query("SELECT path, kind, name, scope, detail, row, col FROM symbols WHERE path = ?1 ORDER BY row, col", &[&path])
+    }
+
+    /// The symbols of the given kinds, or of any kind when there are none, whose name and path are
+    /// LIKE the patterns, ignoring case. A backslash escapes '%' and '_' in both patterns.
+    pub fn symbols_like(&self, name: &str, kinds: &[SymbolKind], path: &str) -> ProgressResult<Vec<Symbol>> {
+        let mut sql = "SELECT path, kind, name, scope, detail, row, col FROM symbols WHERE name LIKE ?1 ESCAPE '\\' AND path LIKE ?2 ESCAPE '\\'".to_string();
+        if !kinds.is_empty() {
+            // Kind names are all plain words, so they can go straight into the query
+            let kinds: Vec<String> = kinds.iter().map(|kind| format!("'{}'", kind.name())).collect();
+            sql.push_str(&format!(" AND kind IN ({})", kinds.join(", ")));
+        }
+        self.query(&sql, &[&name, &path])
+    }
+
+    /// The indexed files whose path is LIKE `path` and whose text has every one of `trigrams`.
+    /// With no trigrams that is every file at `path`.
+    pub fn files_with_trigrams(&self, trigrams: &[u32], path: &str) -> ProgressResult<Vec<String>> {
+        let sql = if trigrams.is_empty() {
+            "SELECT path FROM files WHERE path LIKE ?1 ESCAPE '\\' ORDER BY path".to_string()
+        } else {
+            let trigrams: Vec<String> = trigrams.iter().map(|trigram| trigram.to_string()).collect();
+            format!("SELECT path FROM trigrams WHERE trigram IN ({}) AND path LIKE ?1 ESCAPE '\\' GROUP BY path HAVING COUNT(*) = {} ORDER BY path",
+                    trigrams.join(", "), trigrams.len())
+        };
+        let conn = self.conn.lock().unwrap();
+        let mut statement = conn.prepare(&sql)?;
+        let rows = statement.query_map(&[&path], |row| row.get(0))?;
+        let mut files = Vec::new();
+        for path in rows {
+            files.push(path?);
+        }
+        Ok(files)
+    }
+
+    /// How many files and symbols are indexed
+    pub fn counts(&self) -> ProgressResult<(u64, u64)> {
+        let conn = self.conn.lock().unwrap();
+        let files: i64 = conn.query_row("SELECT COUNT(*) FROM files", &[], |row| row.get(0))?;
+        let symbols: i64 = conn.query_row("SELECT COUNT(*) FROM symbols", &[], |row| row.get(0))?;
+        Ok((files as u64, symbols as u64))
+    }
+
+    fn query(&self, sql: &str, parameters: &[&ToSql]) -> ProgressResult<Vec<Symbol>> {
+        let conn = self.conn.lock().unwrap();
+        let mut statement = conn.prepare(sql)?;
+        let rows = statement.query_map(parameters, |row| {
+            let kind: String = row.get(1);
+            let row_number: i64 = row.get(5);
+            let column: i64 = row.get(6);
+            (kind, Symbol {
+                path: row.get(0),
+                kind: SymbolKind::Definition,
+                name: row.get(2),
+                scope: row.get(3),
+                detail: row.get(4),
+                position: FilePosition { row: row_number as u32, column: column as u32 },
+            })
+        })?;
+        let mut symbols = Vec::new();
+        for row in rows {
+            let (kind, mut symbol) = row?;
+            symbol.kind = SymbolKind::from_name(&kind).ok_or(Error::new(format!("Unknown symbol kind '{}' in the index", kind)))?;
+            symbols.push(symbol);
+        }
+        Ok(symbols)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env::temp_dir;
+    use std::fs::remove_file;
+
+    use parser::FilePosition;
+    use super::{Index, IndexedFile, Symbol, SymbolKind, trigrams};
+
+    #[test]
+    fn test_index() {
+        let path = temp_dir().join("progress_server_test_index.sqlite");
+        let _ = remove_file(&path);
+        let symbol = Symbol {
+            path: "a.p".to_string(),
+            kind: SymbolKind::Run,
+            name: "b.p".to_string(),
+            scope: Some("main".to_string()),
+            detail: None,
+            position: FilePosition { row: 3, column: 5 },
+        };
+        {
+            let index = Index::open(&path).unwrap();
+            index.replace_file("a.p", "8-1", &IndexedFile { symbols: vec![symbol.clone()], trigrams: trigrams("RUN b.p."), error: None }).unwrap();
+            index.replace_file("c.p", "0-1", &IndexedFile { error: Some("Could not parse".to_string()), ..IndexedFile::default() }).unwrap();
+        }
+
+        // Everything is still there after opening it again
+        let index = Index::open(&path).unwrap();
+        assert_eq!(Some("8-1".to_string()), index.fingerprint("a.p").unwrap());
+        assert_eq!(vec![symbol], index.symbols_in("a.p").unwrap());
+        assert_eq!((2, 1), index.counts().unwrap());
+        assert_eq!(vec!["a.p".to_string()], index.files_with_trigrams(&trigrams("b.P"), "%").unwrap());
+        assert_eq!(vec!["a.p".to_string(), "c.p".to_string()], index.files_with_trigrams(&[], "%").unwrap());
+
+        index.remove_file("a.p").unwrap();
+        assert_eq!(vec!["c.p".to_string()], index.files().unwrap());
+        assert!(index.files_with_trigrams(&trigrams("b.p"), "%").unwrap().is_empty());
+        assert_eq!(None, index.fingerprint("a.p").unwrap());
+
+        remove_file(&path).unwrap();
+    }
+}
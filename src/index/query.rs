@@ -0,0 +1,355 @@
+use std::ascii::AsciiExt;
+use std::collections::{HashMap, HashSet};
+use std::usize;
+
+use url::form_urlencoded;
+
+use error::{ProgressResult, Error};
+use page::Sortable;
+use util::{glob_match, glob_to_like};
+use super::{Index, MatchQuality, Symbol, SymbolKind, SymbolQuery, path_like};
+
+// The kinds of symbol that have code in them, which calls: and uses-table: look inside
+const BLOCK_KINDS: &'static [SymbolKind] = &[
+    SymbolKind::Procedure,
+    SymbolKind::Function,
+    SymbolKind::Trigger,
+    SymbolKind::Class,
+    SymbolKind::Method,
+];
+
+/// One result of a structured search
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "type")]
+pub enum SearchResult {
+    /// How well the name matched, when a name was asked for
+    Symbol { symbol: Symbol, quality: Option<MatchQuality> },
+    /// A whole file, for `kind:file` and for what calls: and uses-table: find outside of every
+    /// block
+    File { path: String, quality: Option<MatchQuality> },
+}
+
+/// A file is named by its file name
+impl Sortable for SearchResult {
+    fn sort_name(&self) -> &str {
+        match *self {
+            SearchResult::Symbol { ref symbol, .. } => &symbol.name,
+            SearchResult::File { ref path, .. } => file_name(path),
+        }
+    }
+
+    fn sort_path(&self) -> &str {
+        match *self {
+            SearchResult::Symbol { ref symbol, .. } => &symbol.path,
+            SearchResult::File { ref path, .. } => path,
+        }
+    }
+
+    fn sort_position(&self) -> (u32, u32) {
+        match *self {
+            SearchResult::Symbol { ref symbol, .. } => (symbol.position.row, symbol.position.column),
+            SearchResult::File { .. } => (0, 0),
+        }
+    }
+}
+
+/// A structured search like `kind:function name:get* file:ar/*.w calls:validate-customer
+/// uses-table:Customer`. Words without a key are the name.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct SearchQuery {
+    /// Matched like the `q` of a symbol search
+    pub name: Option<String>,
+    /// Only symbols of these kinds, or of any kind when empty and `files` is not set
+    pub kinds: Vec<SymbolKind>,
+    /// Whether files were asked for with `kind:file`
+    pub files: bool,
+    /// Only in files matching this glob, or under this path when it has no wildcards
+    pub file: Option<String>,
+    /// Only symbols in a procedure, function, method or trigger whose name matches this glob
+    pub scope: Option<String>,
+    /// Only blocks that RUN something matching each of these globs. A glob matches the whole
+    /// target or its name without directory and extension.
+    pub calls: Vec<String>,
+    /// Only blocks that use a table matching each of these globs
+    pub uses_tables: Vec<String>,
+}
+
+// Split the query into its words, each with the key before its ':' if it has one. Double quotes
+// keep spaces and colons in a value, like name:"CHOOSE OF btnOk".
+fn terms(text: &str) -> ProgressResult<Vec<(Option<String>, String)>> {
+    let mut terms = Vec::new();
+    let mut chars = text.chars().peekable();
+    loop {
+        while chars.peek().map_or(false, |c| c.is_whitespace()) {
+            chars.next();
+        }
+        if chars.peek().is_none() {
+            return Ok(terms);
+        }
+        let mut key = None;
+        let mut value = String::new();
+        let mut quoted = false;
+        let mut had_quote = false;
+        while let Some(&c) = chars.peek() {
+            if !quoted && c.is_whitespace() {
+                break;
+            }
+            chars.next();
+            if c == '"' {
+                quoted = !quoted;
+                had_quote = true;
+            } else if c == ':' && key.is_none() && !had_quote && !value.is_empty() {
+                key = Some(value.to_ascii_lowercase());
+                value.clear();
+            } else {
+                value.push(c);
+            }
+        }
+        if quoted {
+            return Err(Error::BadRequest("A quote in the search is never closed".to_string()));
+        }
+        if let Some(ref key) = key {
+            if value.is_empty() {
+                return Err(Error::BadRequest(format!("Nothing to search for after {}:", key)));
+            }
+        }
+        terms.push((key, value));
+    }
+}
+
+fn file_name(path: &str) -> &str {
+    path.rsplit('/').next().unwrap_or(path)
+}
+
+// Whether a RUN target or a table matches `pattern`
+fn target_matches(pattern: &str, target: &str) -> bool {
+    let name = file_name(target);
+    let name = match name.rfind('.') {
+        Some(dot) if dot > 0 => &name[..dot],
+        _ => name,
+    };
+    glob_match(pattern, target) || glob_match(pattern, name)
+}
+
+impl SearchQuery {
+    pub fn parse(text: &str) -> ProgressResult<Self> {
+        let mut query = SearchQuery::default();
+        let mut words = Vec::new();
+        for (key, value) in terms(text)? {
+            match key.as_ref().map(String::as_str) {
+                None => words.push(value),
+                Some("name") => words.push(value),
+                Some("kind") => for kind in value.split(',').filter(|kind| !kind.is_empty()) {
+                    if kind.eq_ignore_ascii_case("file") {
+                        query.files = true;
+                    } else {
+                        // temp-table is TempTable
+                        match SymbolKind::from_name(&kind.replace("-", "")) {
+                            Some(kind) => query.kinds.push(kind),
+                            None => return Err(Error::BadRequest(format!("Unknown kind '{}'", kind))),
+                        }
+                    }
+                },
+                Some("file") => query.file = Some(value),
+                Some("in") => query.scope = Some(value),
+                Some("calls") => query.calls.push(value),
+                Some("uses-table") => query.uses_tables.push(value),
+                Some(key) => return Err(Error::BadRequest(format!("Unknown search key '{}:'", key))),
+            }
+        }
+        if !words.is_empty() {
+            query.name = Some(words.join(" "));
+        }
+        if query == SearchQuery::default() {
+            return Err(Error::BadRequest("Nothing to search for".to_string()));
+        }
+        Ok(query)
+    }
+
+    fn name_query(&self) -> Option<SymbolQuery> {
+        self.name.as_ref().map(|name| SymbolQuery::new(name.clone()))
+    }
+
+    fn wants_files(&self) -> bool {
+        self.files || self.kinds.is_empty()
+    }
+
+    fn wants_symbol(&self, symbol: &Symbol) -> bool {
+        (self.kinds.is_empty() || self.kinds.contains(&symbol.kind))
+            && self.scope.as_ref().map_or(true, |scope| symbol.scope.as_ref().map_or(false, |name| glob_match(scope, name)))
+    }
+
+    // The blocks, by file and name, and the code outside of every block, by file alone, that
+    // satisfy every calls: and uses-table:. None when there are neither.
+    fn places(&self, index: &Index) -> ProgressResult<Option<HashSet<(String, Option<String>)>>> {
+        let path = path_like(self.file.as_ref().map(String::as_str));
+        let filters = self.calls.iter().map(|pattern| (SymbolKind::Run, pattern))
+            .chain(self.uses_tables.iter().map(|pattern| (SymbolKind::TableUse, pattern)));
+        let mut places: Option<HashSet<(String, Option<String>)>> = None;
+        for (kind, pattern) in filters {
+            let found: HashSet<(String, Option<String>)> = index.symbols_like(&format!("%{}%", glob_to_like(pattern)), &[kind], &path)?
+                .into_iter()
+                .filter(|symbol| target_matches(pattern, &symbol.name))
+                .map(|symbol| (symbol.path, symbol.scope))
+                .collect();
+            places = Some(match places {
+                Some(places) => places.intersection(&found).cloned().collect(),
+                None => found,
+            });
+        }
+        Ok(places)
+    }
+
+    /// The symbols and files matching every part of the query. Results are ranked by how well
+    /// their name matched, like a symbol search, and at most `limit` are given.
+    pub fn search(&self, index: &Index, limit: usize) -> ProgressResult<Vec<SearchResult>> {
+        let name = self.name_query();
+        let quality = |name_of: &str| name.as_ref().map_or(Some(None), |name| name.quality(name_of).map(Some));
+        let mut results = Vec::new();
+
+        match self.places(index)? {
+            None => {
+                if self.wants_files() && self.scope.is_none() {
+                    for path in index.files_with_trigrams(&[], &path_like(self.file.as_ref().map(String::as_str)))? {
+                        let found = quality(file_name(&path));
+                        if let Some(quality) = found {
+                            results.push(((quality, path.clone(), 0), SearchResult::File { path, quality }));
+                        }
+                    }
+                }
+                if !self.files || !self.kinds.is_empty() {
+                    let mut symbol_query = name.clone().unwrap_or(SymbolQuery::new("*"));
+                    symbol_query.kinds = self.kinds.clone();
+                    symbol_query.path = self.file.clone();
+                    for (i, found) in index.search(&symbol_query, usize::MAX)?.into_iter().enumerate() {
+                        if self.wants_symbol(&found.symbol) {
+                            let quality = self.name.as_ref().map(|_| found.quality);
+                            // Keep the order of the symbol search among the symbols
+                            results.push(((quality, String::new(), i + 1), SearchResult::Symbol { symbol: found.symbol, quality }));
+                        }
+                    }
+                }
+            },
+            Some(places) => {
+                let mut symbols: HashMap<String, Vec<Symbol>> = HashMap::new();
+                for (path, scope) in places {
+                    let scope = match scope {
+                        Some(scope) => scope,
+                        None => {
+                            if self.wants_files() && self.scope.is_none() {
+                                let found = quality(file_name(&path));
+                                if let Some(quality) = found {
+                                    results.push(((quality, path.clone(), 0), SearchResult::File { path, quality }));
+                                }
+                            }
+                            continue;
+                        },
+                    };
+                    if !symbols.contains_key(&path) {
+                        let in_file = index.symbols_in(&path)?;
+                        symbols.insert(path.clone(), in_file);
+                    }
+                    for symbol in &symbols[&path] {
+                        if !BLOCK_KINDS.contains(&symbol.kind) || symbol.name != scope || !self.wants_symbol(symbol) {
+                            continue;
+                        }
+                        if let Some(quality) = quality(&symbol.name) {
+                            let key = (quality, path.clone(), symbol.position.row as usize);
+                            results.push((key, SearchResult::Symbol { symbol: symbol.clone(), quality }));
+                        }
+                    }
+                }
+            },
+        }
+        results.sort_by(|&(ref a, _), &(ref b, _)| a.cmp(b));
+        Ok(results.into_iter().take(limit).map(|(_, result)| result).collect())
+    }
+}
+
+impl SearchQuery {
+    /// A structured search from the `q` of the query string of a request
+    pub fn from_query(query: &str) -> ProgressResult<Self> {
+        let text = form_urlencoded::parse(query.as_bytes())
+            .find(|&(ref key, _)| key == "q")
+            .map(|(_, value)| value.into_owned());
+        SearchQuery::parse(&text.unwrap_or(String::new()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env::temp_dir;
+    use std::fs::remove_file;
+
+    use error::Error;
+    use parser::FilePosition;
+    use super::super::{Index, IndexedFile, Symbol, SymbolKind};
+    use super::{SearchQuery, SearchResult};
+
+    fn symbol(kind: SymbolKind, name: &str, scope: Option<&str>, row: u32) -> Symbol {
+        Symbol {
+            path: "ar/wCust.w".to_string(),
+            kind,
+            name: name.to_string(),
+            scope: scope.map(str::to_string),
+            detail: None,
+            position: FilePosition { row, column: 1 },
+        }
+    }
+
+    #[test]
+    fn test_parse() {
+        let query = SearchQuery::parse(r#"kind:function,temp-table name:get* file:ar/*.w in:"CHOOSE OF btnOk" calls:validate-customer uses-table:Customer"#).unwrap();
+        assert_eq!(SearchQuery {
+            name: Some("get*".to_string()),
+            kinds: vec![SymbolKind::Function, SymbolKind::TempTable],
+            files: false,
+            file: Some("ar/*.w".to_string()),
+            scope: Some("CHOOSE OF btnOk".to_string()),
+            calls: vec!["validate-customer".to_string()],
+            uses_tables: vec!["Customer".to_string()],
+        }, query);
+        assert_eq!(Some("initialize customer".to_string()), SearchQuery::parse("initialize customer").unwrap().name);
+        assert!(SearchQuery::parse("owner:me").is_err());
+        assert!(SearchQuery::parse("kind:widget").is_err());
+        assert!(SearchQuery::parse("name:\"get").is_err());
+        assert!(SearchQuery::parse("  ").is_err());
+        assert_eq!(Some("get*".to_string()), SearchQuery::from_query("q=name%3Aget*&limit=5").unwrap().name);
+        match SearchQuery::from_query("q=owner%3Ame") {
+            Err(Error::BadRequest(message)) => assert_eq!("Unknown search key 'owner:'", message),
+            result => panic!("{:?}", result),
+        }
+        assert!(SearchQuery::from_query("").is_err());
+    }
+
+    #[test]
+    fn test_search() {
+        let path = temp_dir().join("progress_server_test_query.sqlite");
+        let _ = remove_file(&path);
+        let index = Index::open(&path).unwrap();
+        index.replace_file("ar/wCust.w", "1-1", &IndexedFile { symbols: vec![
+            symbol(SymbolKind::Run, "ar/validate-customer.p", None, 1),
+            symbol(SymbolKind::Procedure, "get-customer", None, 2),
+            symbol(SymbolKind::TableUse, "Customer", Some("get-customer"), 3),
+            symbol(SymbolKind::Run, "validate-customer", Some("get-customer"), 4),
+            symbol(SymbolKind::Procedure, "get-order", None, 6),
+            symbol(SymbolKind::TableUse, "Order", Some("get-order"), 7),
+            symbol(SymbolKind::Variable, "i", Some("get-order"), 8),
+        ], ..IndexedFile::default() }).unwrap();
+
+        let names = |query: &str| -> Vec<String> {
+            SearchQuery::parse(query).unwrap().search(&index, 10).unwrap().into_iter().map(|result| match result {
+                SearchResult::Symbol { symbol, .. } => symbol.name,
+                SearchResult::File { path, .. } => path,
+            }).collect()
+        };
+        assert_eq!(vec!["get-order", "get-customer"], names("kind:procedure get*"));
+        assert_eq!(vec!["ar/wCust.w", "get-customer"], names("calls:validate-customer"));
+        assert_eq!(vec!["get-customer"], names("kind:procedure calls:validate-customer uses-table:customer"));
+        assert!(names("calls:validate-customer uses-table:Order").is_empty());
+        assert_eq!(vec!["i"], names("in:get-order kind:variable"));
+        assert_eq!(vec!["ar/wCust.w"], names("kind:file wcust"));
+
+        remove_file(&path).unwrap();
+    }
+}
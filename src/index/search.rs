@@ -0,0 +1,255 @@
+use std::ascii::AsciiExt;
+
+use url::form_urlencoded;
+
+use error::{ProgressResult, Error};
+use page::Sortable;
+use util::{escape_like, glob_match, glob_to_like};
+use super::{Index, Symbol, SymbolKind, has_wildcards, path_like};
+
+/// How the name in a symbol search is matched
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MatchMode {
+    /// Names that start with the query
+    Prefix,
+    /// Names that match the query, where '*' is any run of characters and '?' is any one
+    Glob,
+    /// Names that have the characters of the query in order, like "icust" for initialize-customer
+    Fuzzy,
+}
+
+/// How well a name matched, best first
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum MatchQuality {
+    Exact,
+    Prefix,
+    Glob,
+    Substring,
+    Fuzzy,
+}
+
+/// A search of the index by symbol name. Every match ignores case.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SymbolQuery {
+    pub text: String,
+    pub mode: MatchMode,
+    /// Only symbols of these kinds, or of any kind when empty
+    pub kinds: Vec<SymbolKind>,
+    /// Only symbols in files matching this glob, or under this path when it has no wildcards
+    pub path: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SymbolMatch {
+    pub symbol: Symbol,
+    pub quality: MatchQuality,
+}
+
+impl Sortable for SymbolMatch {
+    fn sort_name(&self) -> &str {
+        &self.symbol.name
+    }
+
+    fn sort_path(&self) -> &str {
+        &self.symbol.path
+    }
+
+    fn sort_position(&self) -> (u32, u32) {
+        (self.symbol.position.row, self.symbol.position.column)
+    }
+}
+
+// How many characters the query skips over in `name`, when `name` has the characters of the
+// query in order
+fn fuzzy_gaps(query: &str, name: &str) -> Option<usize> {
+    let mut query = query.chars().peekable();
+    let mut started = false;
+    let mut gaps = 0;
+    for c in name.chars() {
+        match query.peek() {
+            None => break,
+            Some(&wanted) if wanted == c => {
+                query.next();
+                started = true;
+            },
+            Some(_) => if started {
+                gaps += 1;
+            },
+        }
+    }
+    if query.peek().is_none() { Some(gaps) } else { None }
+}
+
+impl SymbolQuery {
+    /// A search for `text` by glob when it has wildcards, and fuzzily otherwise
+    pub fn new<S: Into<String>>(text: S) -> Self {
+        let text = text.into();
+        let mode = if has_wildcards(&text) { MatchMode::Glob } else { MatchMode::Fuzzy };
+        SymbolQuery { text, mode, kinds: Vec::new(), path: None }
+    }
+
+    // A LIKE pattern for the names, which every match matches and few others do
+    fn name_like(&self) -> String {
+        match self.mode {
+            MatchMode::Prefix => format!("{}%", escape_like(&self.text)),
+            MatchMode::Glob => glob_to_like(&self.text),
+            MatchMode::Fuzzy => {
+                let mut like = "%".to_string();
+                for c in self.text.chars() {
+                    like.push_str(&escape_like(&c.to_string()));
+                    like.push('%');
+                }
+                like
+            },
+        }
+    }
+
+    /// How well `name` matches, if it does
+    pub fn quality(&self, name: &str) -> Option<MatchQuality> {
+        self.rank(name).map(|(quality, _)| quality)
+    }
+
+    // How well `name` matches, and how many characters a fuzzy match skipped
+    fn rank(&self, name: &str) -> Option<(MatchQuality, usize)> {
+        let query = self.text.to_ascii_lowercase();
+        let name = name.to_ascii_lowercase();
+        if name == query {
+            return Some((MatchQuality::Exact, 0));
+        }
+        match self.mode {
+            MatchMode::Glob => if glob_match(&query, &name) { Some((MatchQuality::Glob, 0)) } else { None },
+            _ if name.starts_with(&query) => Some((MatchQuality::Prefix, 0)),
+            MatchMode::Prefix => None,
+            MatchMode::Fuzzy if name.contains(&query) => Some((MatchQuality::Substring, 0)),
+            MatchMode::Fuzzy => fuzzy_gaps(&query, &name).map(|gaps| (MatchQuality::Fuzzy, gaps)),
+        }
+    }
+}
+
+impl Index {
+    /// The symbols matching `query`, best matches first: exact names, then prefixes, glob
+    /// matches, substrings, and fuzzy matches with the fewest characters skipped. Ties go to the
+    /// shorter name. At most `limit` are returned.
+    pub fn search(&self, query: &SymbolQuery, limit: usize) -> ProgressResult<Vec<SymbolMatch>> {
+        let mut matches: Vec<((MatchQuality, usize, usize, String), SymbolMatch)> = Vec::new();
+        for symbol in self.symbols_like(&query.name_like(), &query.kinds, &path_like(query.path.as_ref().map(String::as_str)))? {
+            if let Some((quality, gaps)) = query.rank(&symbol.name) {
+                let key = (quality, gaps, symbol.name.len(), symbol.name.to_ascii_lowercase());
+                matches.push((key, SymbolMatch { symbol, quality }));
+            }
+        }
+        matches.sort_by(|&(ref a_key, ref a), &(ref b_key, ref b)| {
+            (a_key, &a.symbol.path, a.symbol.position.row, a.symbol.position.column)
+                .cmp(&(b_key, &b.symbol.path, b.symbol.position.row, b.symbol.position.column))
+        });
+        Ok(matches.into_iter().take(limit).map(|(_, symbol_match)| symbol_match).collect())
+    }
+}
+
+impl SymbolQuery {
+    /// A symbol search from the query string of a request: `q` is the name, `match` is "prefix",
+    /// "glob" or "fuzzy", `kind` is a comma separated list of kinds, and `path` limits the files
+    pub fn from_query(query: &str) -> ProgressResult<Self> {
+        let mut text = None;
+        let mut mode = None;
+        let mut kinds = Vec::new();
+        let mut path = None;
+        for (key, value) in form_urlencoded::parse(query.as_bytes()) {
+            match &key[..] {
+                "q" => text = Some(value.into_owned()),
+                "match" => mode = match &value.to_ascii_lowercase()[..] {
+                    "prefix" => Some(MatchMode::Prefix),
+                    "glob" => Some(MatchMode::Glob),
+                    "fuzzy" => Some(MatchMode::Fuzzy),
+                    _ => return Err(Error::BadRequest(format!("Unknown match '{}'", value))),
+                },
+                "kind" => for name in value.split(',').map(str::trim).filter(|name| !name.is_empty()) {
+                    match SymbolKind::from_name(name) {
+                        Some(kind) => kinds.push(kind),
+                        None => return Err(Error::BadRequest(format!("Unknown kind '{}'", name))),
+                    }
+                },
+                "path" => path = Some(value.into_owned()),
+                _ => {},
+            }
+        }
+        let mut query = match text {
+            Some(text) => SymbolQuery::new(text),
+            None => return Err(Error::BadRequest("Nothing to search for".to_string())),
+        };
+        if let Some(mode) = mode {
+            query.mode = mode;
+        }
+        query.kinds = kinds;
+        query.path = path;
+        Ok(query)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env::temp_dir;
+    use std::fs::remove_file;
+
+    use parser::FilePosition;
+    use super::super::{Index, IndexedFile, Symbol, SymbolKind};
+    use super::{MatchMode, MatchQuality, SymbolQuery};
+
+    fn symbol(path: &str, kind: SymbolKind, name: &str) -> Symbol {
+        Symbol {
+            path: path.to_string(),
+            kind,
+            name: name.to_string(),
+            scope: None,
+            detail: None,
+            position: FilePosition { row: 1, column: 1 },
+        }
+    }
+
+    #[test]
+    fn test_search() {
+        let path = temp_dir().join("progress_server_test_search.sqlite");
+        let _ = remove_file(&path);
+        let index = Index::open(&path).unwrap();
+        index.replace_file("wWin.w", "1-1", &IndexedFile { symbols: vec![
+            symbol("wWin.w", SymbolKind::Procedure, "initialize-customer"),
+            symbol("wWin.w", SymbolKind::CustomBlock, "_DEFINITIONS"),
+            symbol("wWin.w", SymbolKind::Variable, "cust_name"),
+        ], ..IndexedFile::default() }).unwrap();
+        index.replace_file("ar/customer.p", "1-1", &IndexedFile { symbols: vec![
+            symbol("ar/customer.p", SymbolKind::TempTable, "ttCustomer"),
+            symbol("ar/customer.p", SymbolKind::Procedure, "customer"),
+        ], ..IndexedFile::default() }).unwrap();
+
+        let search = |query: &SymbolQuery| -> Vec<(String, MatchQuality)> {
+            index.search(query, 10).unwrap().into_iter().map(|found| (found.symbol.name, found.quality)).collect()
+        };
+        assert_eq!(vec![
+            ("customer".to_string(), MatchQuality::Exact),
+            ("ttCustomer".to_string(), MatchQuality::Substring),
+            ("initialize-customer".to_string(), MatchQuality::Substring),
+        ], search(&SymbolQuery::new("CUSTOMER")));
+        assert_eq!(vec![("initialize-customer".to_string(), MatchQuality::Fuzzy)], search(&SymbolQuery::new("icust")));
+        assert_eq!(vec![("_DEFINITIONS".to_string(), MatchQuality::Glob)], search(&SymbolQuery::new("_def*")));
+        // '_' is not a LIKE wildcard
+        assert_eq!(vec![("cust_name".to_string(), MatchQuality::Prefix)], search(&SymbolQuery::new("cust_")));
+
+        let mut query = SymbolQuery::new("cust");
+        query.mode = MatchMode::Prefix;
+        assert_eq!(vec![
+            ("customer".to_string(), MatchQuality::Prefix),
+            ("cust_name".to_string(), MatchQuality::Prefix),
+        ], search(&query));
+        query.kinds = vec![SymbolKind::Variable];
+        assert_eq!(vec![("cust_name".to_string(), MatchQuality::Prefix)], search(&query));
+        query.kinds = Vec::new();
+        query.path = Some("ar/".to_string());
+        assert_eq!(vec![("customer".to_string(), MatchQuality::Prefix)], search(&query));
+        assert_eq!(query, SymbolQuery::from_query("q=cust&match=Prefix&path=ar%2F").unwrap());
+        for invalid in &["match=prefix", "q=cust&match=exact", "q=cust&kind=widget"] {
+            assert!(SymbolQuery::from_query(invalid).is_err(), "{}", invalid);
+        }
+
+        remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,423 @@
+use std::ascii::AsciiExt;
+use std::collections::{BTreeMap, HashSet};
+
+use url::form_urlencoded;
+
+use codepage::decode;
+use error::{ProgressResult, Error};
+use parse_cache::{ContentKey, ParseCache};
+use parser::{FilePosition, LineIndex, Statement, Token, TokenKind, tokenize};
+use page::{Page, PageQuery};
+use source::{Propath, SourceProvider};
+use util::{glob_match, parallel_map};
+use super::{Index, path_like};
+use super::extract::scopes;
+use super::text::literal_trigrams;
+
+#[derive(Debug, Clone, PartialEq)]
+enum Element {
+    /// A word or punctuation, matched ignoring case
+    Literal(String),
+    /// `$NAME`, which matches an expression: a run of tokens with balanced brackets and no ','
+    /// outside of them. `$_` is never bound, and a glob in braces like `$PROG{*.p}` limits what
+    /// it matches.
+    Metavariable { name: Option<String>, glob: Option<String> },
+    /// `...`, which matches any run of tokens, even none
+    Ellipsis,
+}
+
+/// A statement in ABL syntax with metavariables, like `FIND ... $TABLE ...` or
+/// `RUN $PROG{*.p} ($A, $B, $C)`. It matches a whole statement, leaving out the '.' or ':' that
+/// ends it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Pattern {
+    elements: Vec<Element>,
+}
+
+// The token at `i`, when it follows the one before it without any space
+fn adjacent<'t, 'a>(tokens: &'t [Token<'a>], i: usize) -> Option<&'t Token<'a>> {
+    tokens.get(i).and_then(|token| if i > 0 && tokens[i - 1].span.end == token.span.start { Some(token) } else { None })
+}
+
+// The lengths of the runs at the start of `tokens` a metavariable can match, shortest first
+fn expression_lengths(tokens: &[Token]) -> Vec<usize> {
+    let mut lengths = Vec::new();
+    let mut depth = 0;
+    for (i, token) in tokens.iter().enumerate() {
+        match token.text {
+            "(" | "[" => depth += 1,
+            ")" | "]" => if depth == 0 {
+                break;
+            } else {
+                depth -= 1;
+            },
+            "," if depth == 0 => break,
+            _ => {},
+        }
+        if depth == 0 {
+            lengths.push(i + 1);
+        }
+    }
+    lengths
+}
+
+fn same_tokens(a: &[Token], b: &[Token]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).all(|(a, b)| a.text.eq_ignore_ascii_case(b.text))
+}
+
+// The text of the tokens, with whatever is between them
+fn tokens_text<'a>(source: &'a str, tokens: &[Token]) -> &'a str {
+    match (tokens.first(), tokens.last()) {
+        (Some(first), Some(last)) => &source[first.span.start..last.span.end],
+        _ => "",
+    }
+}
+
+// Matches the tokens of a statement against the elements of a pattern. `failed` has every
+// position the rest of the pattern did not match from, with what was bound to the metavariables
+// the rest uses again, so `...` and metavariables never try the same position twice.
+struct Matcher<'e, 't, 'a: 't> {
+    source: &'t str,
+    elements: &'e [Element],
+    tokens: &'t [Token<'a>],
+    /// Each named metavariable, and the range of tokens it matched
+    bindings: Vec<(&'e str, usize, usize)>,
+    failed: HashSet<(usize, usize, Vec<(usize, usize)>)>,
+}
+
+impl<'e, 't, 'a: 't> Matcher<'e, 't, 'a> {
+    // What is bound to the metavariables from `element` on
+    fn later_bindings(&self, element: usize) -> Vec<(usize, usize)> {
+        self.bindings.iter()
+            .filter(|&&(name, _, _)| self.elements[element..].iter().any(|other| match *other {
+                Element::Metavariable { name: Some(ref other), .. } => other == name,
+                _ => false,
+            }))
+            .map(|&(_, start, end)| (start, end))
+            .collect()
+    }
+
+    // Whether the tokens from `token` on match the elements from `element` on
+    fn match_from(&mut self, element: usize, token: usize) -> bool {
+        let elements = self.elements;
+        let tokens = self.tokens;
+        let current = match elements.get(element) {
+            Some(current) => current,
+            None => return token == tokens.len(),
+        };
+        let state = (element, token, self.later_bindings(element));
+        if self.failed.contains(&state) {
+            return false;
+        }
+        let matched = match *current {
+            Element::Literal(ref text) => {
+                tokens.get(token).map_or(false, |found| found.text.eq_ignore_ascii_case(text))
+                    && self.match_from(element + 1, token + 1)
+            },
+            Element::Ellipsis => (token..tokens.len() + 1).any(|next| self.match_from(element + 1, next)),
+            Element::Metavariable { ref name, ref glob } => {
+                let mut matched = false;
+                for length in expression_lengths(&tokens[token..]) {
+                    let end = token + length;
+                    if glob.as_ref().map_or(false, |glob| !glob_match(glob, tokens_text(self.source, &tokens[token..end]))) {
+                        continue;
+                    }
+                    let name: &'e str = match *name {
+                        Some(ref name) => name,
+                        None => if self.match_from(element + 1, end) {
+                            matched = true;
+                            break;
+                        } else {
+                            continue;
+                        },
+                    };
+                    // A metavariable used twice matches the same thing both times
+                    let bound = self.bindings.iter().find(|&&(bound, _, _)| bound == name).map(|&(_, start, end)| (start, end));
+                    if let Some((start, bound_end)) = bound {
+                        if same_tokens(&tokens[start..bound_end], &tokens[token..end]) && self.match_from(element + 1, end) {
+                            matched = true;
+                            break;
+                        }
+                        continue;
+                    }
+                    self.bindings.push((name, token, end));
+                    if self.match_from(element + 1, end) {
+                        matched = true;
+                        break;
+                    }
+                    self.bindings.pop();
+                }
+                matched
+            },
+        };
+        if !matched {
+            self.failed.insert(state);
+        }
+        matched
+    }
+}
+
+impl Pattern {
+    pub fn parse(text: &str) -> ProgressResult<Pattern> {
+        let tokens = tokenize(text);
+        let mut elements = Vec::new();
+        let mut i = 0;
+        while i < tokens.len() {
+            let token = &tokens[i];
+            if token.text == "$" {
+                let name = match adjacent(&tokens, i + 1) {
+                    Some(name) if name.kind == TokenKind::Identifier || name.kind == TokenKind::Keyword => name.text,
+                    _ => return Err(Error::BadRequest("A '$' has to start a metavariable like $TABLE".to_string())),
+                };
+                i += 2;
+                let glob = match adjacent(&tokens, i) {
+                    Some(glob) if glob.kind == TokenKind::IncludeReference => {
+                        i += 1;
+                        Some(glob.text[1..glob.text.len() - 1].trim().to_string())
+                    },
+                    _ => None,
+                };
+                let name = if name == "_" { None } else { Some(name.to_string()) };
+                elements.push(Element::Metavariable { name, glob });
+                continue;
+            }
+            if token.text == "." && adjacent(&tokens, i + 1).map_or(false, |dot| dot.text == ".")
+                && adjacent(&tokens, i + 2).map_or(false, |dot| dot.text == ".") {
+                // `... ...` matches the same as `...`
+                if elements.last() != Some(&Element::Ellipsis) {
+                    elements.push(Element::Ellipsis);
+                }
+                i += 3;
+                continue;
+            }
+            if token.kind == TokenKind::Terminator {
+                if tokens[i + 1..].iter().any(|token| token.kind.is_significant()) {
+                    return Err(Error::BadRequest("A pattern is a single statement".to_string()));
+                }
+            } else if token.kind.is_significant() {
+                elements.push(Element::Literal(token.text.to_string()));
+            }
+            i += 1;
+        }
+        if elements.is_empty() {
+            return Err(Error::BadRequest("Nothing to search for".to_string()));
+        }
+        Ok(Pattern { elements })
+    }
+
+    /// What each named metavariable matched, when `statement` of `source` matches
+    pub fn matches(&self, source: &str, statement: &Statement) -> Option<BTreeMap<String, String>> {
+        let mut tokens = &statement.tokens[..];
+        if tokens.last().map_or(false, |token| token.kind == TokenKind::Terminator) {
+            tokens = &tokens[..tokens.len() - 1];
+        }
+        let mut matcher = Matcher { source, elements: &self.elements, tokens, bindings: Vec::new(), failed: HashSet::new() };
+        if matcher.match_from(0, 0) {
+            Some(matcher.bindings.iter()
+                .map(|&(name, start, end)| (name.to_string(), tokens_text(source, &tokens[start..end]).to_string()))
+                .collect())
+        } else {
+            None
+        }
+    }
+
+    // The trigrams of the words every match has
+    fn required_trigrams(&self) -> Vec<u32> {
+        let mut trigrams: Vec<u32> = self.elements.iter()
+            .flat_map(|element| match *element {
+                Element::Literal(ref text) => literal_trigrams(text),
+                _ => Vec::new(),
+            })
+            .collect();
+        trigrams.sort();
+        trigrams.dedup();
+        trigrams
+    }
+}
+
+/// A structural search of the indexed files
+#[derive(Debug, Clone, PartialEq)]
+pub struct StructureQuery {
+    pub pattern: String,
+    /// Patterns a statement must not match, like `... NO-LOCK ...`
+    pub not: Vec<String>,
+    /// Only files matching this glob, or under this path when it has no wildcards
+    pub path: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct StructureHit {
+    pub path: String,
+    /// Where the statement starts and ends. Columns are in bytes.
+    pub position: FilePosition,
+    pub end: FilePosition,
+    pub text: String,
+    /// What each named metavariable matched
+    pub bindings: BTreeMap<String, String>,
+    /// The innermost procedure, function, method or trigger the statement is in
+    pub scope: Option<String>,
+}
+
+// The statements of the file matching `pattern` and none of `not`. A file that can not be parsed
+// has none.
+fn search_file(path: &str, text: &str, key: &ContentKey, pattern: &Pattern, not: &[Pattern], parses: &ParseCache) -> Vec<StructureHit> {
+    let statements = match parses.statements(key, text) {
+        Ok(statements) => statements,
+        Err(_) => return Vec::new(),
+    };
+    let progress = statements.progress(text);
+    let found: Vec<(&Statement, BTreeMap<String, String>)> = progress.statements.iter()
+        .filter_map(|statement| pattern.matches(text, statement).map(|bindings| (statement, bindings)))
+        .filter(|&(statement, _)| not.iter().all(|not| not.matches(text, statement).is_none()))
+        .collect();
+    if found.is_empty() {
+        return Vec::new();
+    }
+
+    let lines = LineIndex::new(text);
+    let scopes = scopes(text, &progress);
+    found.into_iter().map(|(statement, bindings)| StructureHit {
+        path: path.to_string(),
+        position: lines.position(statement.span.start),
+        end: lines.position(statement.span.end),
+        text: statement.text(text).to_string(),
+        bindings,
+        scope: scopes.iter().rev()
+            .find(|scope| scope.span.start <= statement.span.start && statement.span.start < scope.span.end)
+            .map(|scope| scope.name.clone()),
+    }).collect()
+}
+
+/// Find the statements of the indexed files matching `query`, in path order, reading
+/// `parallelism` files at a time. Only the files that have every trigram of the words of the
+/// pattern are read, and only until the page is full. Pages have at most `max` hits.
+pub fn search_structure(index: &Index, propath: &Propath, parses: &ParseCache, query: &StructureQuery, parallelism: usize, page: &PageQuery, max: usize) -> ProgressResult<Page<StructureHit>> {
+    let pattern = Pattern::parse(&query.pattern)?;
+    let mut not = Vec::new();
+    for text in &query.not {
+        not.push(Pattern::parse(text)?);
+    }
+    let files = index.files_with_trigrams(&pattern.required_trigrams(), &path_like(query.path.as_ref().map(String::as_str)))?;
+    page.page_files(&files, parallelism.max(1) * 4, max, |chunk| {
+        let found = parallel_map(chunk, parallelism, |path| -> ProgressResult<Vec<StructureHit>> {
+            let file = propath.get_file(path)?;
+            let decoded = decode(&file.contents, file.codepage.as_ref().map(String::as_str))?;
+            let key = ContentKey::new(&file.contents, &decoded.codepage);
+            Ok(search_file(path, &decoded.text, &key, &pattern, &not, parses))
+        });
+        found.into_iter().map(|result| match result {
+            // The index can still have a file that was just deleted
+            Err(Error::NotFound(_)) => Ok(Vec::new()),
+            result => result,
+        }).collect()
+    })
+}
+
+impl StructureQuery {
+    /// A structural search from the query string of a request: `q` is the pattern, each `not` is
+    /// a pattern the statements must not match, and `path` limits the files
+    pub fn from_query(text: &str) -> ProgressResult<Self> {
+        let mut query = StructureQuery { pattern: String::new(), not: Vec::new(), path: None };
+        for (key, value) in form_urlencoded::parse(text.as_bytes()) {
+            match &key[..] {
+                "q" => query.pattern = value.into_owned(),
+                "not" => query.not.push(value.into_owned()),
+                "path" => query.path = Some(value.into_owned()),
+                _ => {},
+            }
+        }
+        if query.pattern.trim().is_empty() {
+            return Err(Error::BadRequest("Nothing to search for".to_string()));
+        }
+        Ok(query)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env::temp_dir;
+    use std::fs::{File, create_dir_all, remove_dir_all};
+    use std::io::Write;
+    use std::time::Duration;
+
+    use page::PageQuery;
+    use parse_cache::ParseCache;
+    use parser::parse_progress;
+    use source::{ContentCache, HttpClient, Sources};
+    use super::super::Index;
+    use super::super::indexer::update_file;
+    use super::{Pattern, StructureQuery, search_structure};
+
+    // What each statement of `source` that matches `pattern` bound, as "NAME=text" pairs
+    fn matches(pattern: &str, source: &str) -> Vec<Vec<String>> {
+        let pattern = Pattern::parse(pattern).unwrap();
+        parse_progress(source).unwrap().statements.iter()
+            .filter_map(|statement| pattern.matches(source, statement))
+            .map(|bindings| bindings.into_iter().map(|(name, text)| format!("{}={}", name, text)).collect())
+            .collect()
+    }
+
+    #[test]
+    fn test_pattern() {
+        let source = "FIND FIRST Customer NO-LOCK.\nfind customer where customer.num = 1.\nRUN a.p (INPUT x, OUTPUT y, f(1, 2)).\nRUN b.w (x, y).\nRUN c.p (x, y, z, 4) NO-ERROR.\nASSIGN x = x.\nASSIGN x = y.\n";
+        assert_eq!(2, matches("FIND ... Customer ...", source).len());
+        assert_eq!(vec![vec!["TABLE=customer".to_string()]], matches("find $TABLE where ...", source));
+        assert_eq!(vec![vec!["A=INPUT x".to_string(), "C=f(1, 2)".to_string(), "PROG=a.p".to_string()]],
+                   matches("RUN $PROG{*.p} ($A, $_, $C)", source));
+        assert_eq!(1, matches("RUN $PROG{*.p} ($A, $B, $C) ...", source).len());
+        assert_eq!(vec![vec!["X=x".to_string()]], matches("ASSIGN $X = $X.", source));
+
+        assert!(Pattern::parse("$").is_err());
+        assert!(Pattern::parse("FIND Customer. DISPLAY Customer.").is_err());
+        assert!(Pattern::parse(" . ").is_err());
+
+        assert_eq!(Pattern::parse("FIND ... Customer").unwrap(), Pattern::parse("FIND ... ... ... Customer").unwrap());
+        // Every way of placing the words before the last one is tried at most once
+        let words = vec!["a"; 80].join(" ");
+        let source = format!("DISPLAY {}.\n", words);
+        assert!(matches("DISPLAY ... a ... a ... a ... a ... a ... a ... a ... a ... a ... a ... b", &source).is_empty());
+        assert!(matches("DISPLAY $_ $_ $_ $_ $_ $_ $_ $_ $_ $_ ... b", &source).is_empty());
+        assert_eq!(1, matches("DISPLAY ... a ... a ... a ... a ... a ... a ... a ... a ... a ... a", &source).len());
+    }
+
+    #[test]
+    fn test_search_structure() {
+        let root = temp_dir().join("progress_server_test_search_structure");
+        let _ = remove_dir_all(&root);
+        create_dir_all(root.join("src")).unwrap();
+        File::create(root.join("src/a.p")).unwrap()
+            .write_all(b"PROCEDURE find-customer:\n    FIND FIRST Customer\n        WHERE Customer.CustNum = 1.\n    FIND FIRST Customer NO-LOCK.\nEND PROCEDURE.\n").unwrap();
+        File::create(root.join("src/b.p")).unwrap().write_all(b"FIND Item EXCLUSIVE-LOCK.\n").unwrap();
+
+        let sources = Sources::new(vec![root.join("src").to_string_lossy().into_owned()], 2,
+                                   HttpClient::new(Duration::from_secs(1), Duration::from_secs(1), 0), ContentCache::new(0)).unwrap();
+        let propath = sources.propath(None).unwrap();
+        let index = Index::open(root.join("index.sqlite")).unwrap();
+        update_file(&index, &propath, "a.p").unwrap();
+        update_file(&index, &propath, "b.p").unwrap();
+        let parses = ParseCache::new(4);
+
+        let mut query = StructureQuery { pattern: "FIND ... $TABLE{customer} ...".to_string(), not: vec!["... NO-LOCK ...".to_string()], path: None };
+        let hits = search_structure(&index, &propath, &parses, &query, 2, &PageQuery::default(), 10).unwrap().results;
+        assert_eq!(1, hits.len());
+        assert_eq!(("a.p", 2, 5, 3), (&hits[0].path[..], hits[0].position.row, hits[0].position.column, hits[0].end.row));
+        assert_eq!(Some(&"Customer".to_string()), hits[0].bindings.get("TABLE"));
+        assert_eq!(Some("find-customer".to_string()), hits[0].scope);
+
+        query.pattern = "FIND $TABLE EXCLUSIVE-LOCK".to_string();
+        query.not = Vec::new();
+        let hits = search_structure(&index, &propath, &parses, &query, 2, &PageQuery::default(), 10).unwrap().results;
+        assert_eq!(vec!["b.p"], hits.iter().map(|hit| &hit.path[..]).collect::<Vec<&str>>());
+        assert_eq!(Some(&"Item".to_string()), hits[0].bindings.get("TABLE"));
+
+        query.pattern = "FIND $".to_string();
+        assert!(search_structure(&index, &propath, &parses, &query, 2, &PageQuery::default(), 10).is_err());
+
+        let query = StructureQuery::from_query("q=FIND+...&not=...+NO-LOCK+...&not=...+NO-WAIT+...&path=a.p").unwrap();
+        assert_eq!(vec!["... NO-LOCK ...", "... NO-WAIT ..."], query.not);
+        assert_eq!((&query.pattern[..], Some("a.p".to_string())), ("FIND ...", query.path.clone()));
+        assert!(StructureQuery::from_query("q=+&path=a.p").is_err());
+
+        remove_dir_all(&root).unwrap();
+    }
+}
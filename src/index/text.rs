@@ -0,0 +1,326 @@
+use std::ascii::AsciiExt;
+
+use regex::{Regex, escape};
+use url::form_urlencoded;
+
+use codepage::decode;
+use error::{ProgressResult, Error};
+use parse_cache::{ContentKey, ParseCache};
+use parser::FilePosition;
+use page::{Page, PageQuery};
+use source::{Propath, SourceProvider};
+use util::{parallel_map, parse_flag};
+use super::{Index, path_like};
+use super::extract::scopes;
+
+// The most lines of context a hit can have on each side
+const MAX_CONTEXT_LINES: usize = 10;
+
+fn trigram(bytes: &[u8]) -> u32 {
+    (bytes[0].to_ascii_lowercase() as u32) << 16 | (bytes[1].to_ascii_lowercase() as u32) << 8 | bytes[2].to_ascii_lowercase() as u32
+}
+
+/// The distinct trigrams of the bytes of `text`, with ASCII letters folded to lower case, in order
+pub fn trigrams(text: &str) -> Vec<u32> {
+    let mut trigrams: Vec<u32> = text.as_bytes().windows(3).map(trigram).collect();
+    trigrams.sort();
+    trigrams.dedup();
+    trigrams
+}
+
+// The trigrams a file has to have when it holds `literal`. Those with anything but ASCII are left
+// out, since a case-insensitive match can find them in another case.
+pub fn literal_trigrams(literal: &str) -> Vec<u32> {
+    literal.as_bytes().windows(3)
+        .filter(|bytes| bytes.iter().all(|byte| byte.is_ascii()))
+        .map(trigram)
+        .collect()
+}
+
+// Runs of literal text that every match of the regex has. Anything that is not plainly required,
+// like alternatives, groups and what comes before a '?' or '*', is left out, so a regex can have
+// none.
+fn regex_literals(pattern: &str) -> Vec<String> {
+    // Flags like (?x) change what the text means, and any part of an alternation can match
+    if pattern.contains('|') || pattern.contains("(?") {
+        return Vec::new();
+    }
+    let mut literals = Vec::new();
+    let mut current = String::new();
+    // Text after an escape like \x or \d is not literal until the run ends
+    let mut poisoned = false;
+    let mut depth = 0;
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        let literal = match c {
+            '\\' => match chars.next() {
+                Some(escaped) if escaped.is_ascii() && !escaped.is_alphanumeric() => Some(escaped),
+                _ => {
+                    poisoned = true;
+                    None
+                },
+            },
+            '[' => {
+                // A class is any one of its characters
+                let mut first = true;
+                while let Some(c) = chars.next() {
+                    if c == '\\' {
+                        chars.next();
+                    } else if c == ']' && !first {
+                        break;
+                    }
+                    first = c == '^' && first;
+                }
+                None
+            },
+            '(' => {
+                depth += 1;
+                None
+            },
+            ')' => {
+                depth -= 1;
+                None
+            },
+            '*' | '?' | '{' => {
+                // What came before is optional
+                current.pop();
+                if c == '{' {
+                    while let Some(c) = chars.next() {
+                        if c == '}' {
+                            break;
+                        }
+                    }
+                }
+                None
+            },
+            '.' | '^' | '$' | '+' => None,
+            c if c.is_ascii() => Some(c),
+            _ => None,
+        };
+        match literal {
+            Some(c) => if depth == 0 && !poisoned {
+                current.push(c);
+            },
+            None => {
+                if current.len() >= 3 {
+                    literals.push(current.clone());
+                }
+                current.clear();
+                poisoned = poisoned && c == '\\';
+            },
+        }
+    }
+    if current.len() >= 3 {
+        literals.push(current);
+    }
+    literals
+}
+
+/// A grep of the indexed files. Matches are found one line at a time.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TextQuery {
+    pub pattern: String,
+    /// Whether `pattern` is a regex rather than literal text
+    pub regex: bool,
+    pub case_sensitive: bool,
+    /// Only files matching this glob, or under this path when it has no wildcards
+    pub path: Option<String>,
+    /// How many lines before and after each hit to give
+    pub context: usize,
+}
+
+impl TextQuery {
+    /// A case-insensitive search for the literal text `pattern`
+    pub fn new<S: Into<String>>(pattern: S) -> Self {
+        TextQuery { pattern: pattern.into(), regex: false, case_sensitive: false, path: None, context: 0 }
+    }
+
+    /// A text search from the query string of a request: `q` is the text, `regex` and
+    /// `case_sensitive` are flags, `path` limits the files and `context` is how many lines around
+    /// each hit to give
+    pub fn from_query(text: &str) -> ProgressResult<Self> {
+        let mut query = TextQuery::new("");
+        let mut has_pattern = false;
+        for (key, value) in form_urlencoded::parse(text.as_bytes()) {
+            match &key[..] {
+                "q" => {
+                    query.pattern = value.into_owned();
+                    has_pattern = true;
+                },
+                "regex" | "case_sensitive" => match parse_flag(&value) {
+                    Some(flag) => if key == "regex" { query.regex = flag } else { query.case_sensitive = flag },
+                    None => return Err(Error::BadRequest(format!("{} is not true or false", key))),
+                },
+                "path" => query.path = Some(value.into_owned()),
+                "context" => match value.parse() {
+                    Ok(context) => query.context = context,
+                    Err(_) => return Err(Error::BadRequest("context is not a number of lines".to_string())),
+                },
+                _ => {},
+            }
+        }
+        if !has_pattern || query.pattern.is_empty() {
+            return Err(Error::BadRequest("Nothing to search for".to_string()));
+        }
+        Ok(query)
+    }
+
+    fn matcher(&self) -> ProgressResult<Regex> {
+        let pattern = if self.regex { self.pattern.clone() } else { escape(&self.pattern) };
+        let pattern = if self.case_sensitive { pattern } else { format!("(?i){}", pattern) };
+        Regex::new(&pattern).map_err(|err| Error::BadRequest(format!("Invalid regex '{}': {}", self.pattern, err)))
+    }
+
+    // The trigrams of the text every match has
+    fn required_trigrams(&self) -> Vec<u32> {
+        let literals = if self.regex { regex_literals(&self.pattern) } else { vec![self.pattern.clone()] };
+        let mut trigrams: Vec<u32> = literals.iter().flat_map(|literal| literal_trigrams(literal)).collect();
+        trigrams.sort();
+        trigrams.dedup();
+        trigrams
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct TextHit {
+    pub path: String,
+    /// Where the match starts. The column is in bytes.
+    pub position: FilePosition,
+    /// The line with the match, and where in it the match starts and ends, in bytes
+    pub line: String,
+    pub start: usize,
+    pub end: usize,
+    pub before: Vec<String>,
+    pub after: Vec<String>,
+    /// The AppBuilder section the match is in, like "_PROCEDURE initialize"
+    pub section: Option<String>,
+    /// The innermost procedure, function, method or trigger the match is in
+    pub scope: Option<String>,
+}
+
+// The first match on each line of the file. Only a file with a match is parsed, to find where
+// each match is; one that can not be parsed still gives its matches.
+fn search_file(path: &str, text: &str, key: &ContentKey, matcher: &Regex, context: usize, parses: &ParseCache) -> Vec<TextHit> {
+    let mut lines = Vec::new();
+    let mut offset = 0;
+    for line in text.split('\n') {
+        lines.push((offset, line.trim_right_matches('\r')));
+        offset += line.len() + 1;
+    }
+    let found: Vec<(usize, usize, usize)> = lines.iter().enumerate()
+        .filter_map(|(i, &(_, line))| matcher.find(line).map(|found| (i, found.start(), found.end())))
+        .collect();
+    if found.is_empty() {
+        return Vec::new();
+    }
+
+    let preprocessed = parses.preprocessed(key, text).ok();
+    let scopes = parses.statements(key, text)
+        .map(|statements| scopes(text, &statements.progress(text)))
+        .unwrap_or(Vec::new());
+    found.into_iter().map(|(i, start, end)| {
+        let (line_offset, line) = lines[i];
+        let offset = line_offset + start;
+        TextHit {
+            path: path.to_string(),
+            position: FilePosition { row: i as u32 + 1, column: start as u32 + 1 },
+            line: line.to_string(),
+            start,
+            end,
+            before: lines[i.saturating_sub(context)..i].iter().map(|&(_, line)| line.to_string()).collect(),
+            after: lines[i + 1..(i + 1 + context).min(lines.len())].iter().map(|&(_, line)| line.to_string()).collect(),
+            section: preprocessed.as_ref().and_then(|preprocessed| preprocessed.section_at(offset)).and_then(|section| section.label()),
+            scope: scopes.iter().rev()
+                .find(|scope| scope.span.start <= offset && offset < scope.span.end)
+                .map(|scope| scope.name.clone()),
+        }
+    }).collect()
+}
+
+/// Grep the indexed files for `query`, in path order, reading `parallelism` files at a time. Only
+/// the files that have every trigram of the text a match needs are read, and only until the page
+/// is full. Pages have at most `max` hits.
+pub fn search_text(index: &Index, propath: &Propath, parses: &ParseCache, query: &TextQuery, parallelism: usize, page: &PageQuery, max: usize) -> ProgressResult<Page<TextHit>> {
+    let matcher = query.matcher()?;
+    let context = query.context.min(MAX_CONTEXT_LINES);
+    let files = index.files_with_trigrams(&query.required_trigrams(), &path_like(query.path.as_ref().map(String::as_str)))?;
+    page.page_files(&files, parallelism.max(1) * 4, max, |chunk| {
+        let found = parallel_map(chunk, parallelism, |path| -> ProgressResult<Vec<TextHit>> {
+            let file = propath.get_file(path)?;
+            let decoded = decode(&file.contents, file.codepage.as_ref().map(String::as_str))?;
+            let key = ContentKey::new(&file.contents, &decoded.codepage);
+            Ok(search_file(path, &decoded.text, &key, &matcher, context, parses))
+        });
+        found.into_iter().map(|result| match result {
+            // The index can still have a file that was just deleted
+            Err(Error::NotFound(_)) => Ok(Vec::new()),
+            result => result,
+        }).collect()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env::temp_dir;
+    use std::fs::{File, create_dir_all, remove_dir_all};
+    use std::io::Write;
+    use std::time::Duration;
+
+    use page::PageQuery;
+    use parse_cache::ParseCache;
+    use source::{ContentCache, HttpClient, Sources};
+    use super::super::Index;
+    use super::super::indexer::update_file;
+    use super::{TextQuery, regex_literals, search_text};
+
+    #[test]
+    fn test_regex_literals() {
+        assert_eq!(vec!["RUN ".to_string(), ".p(".to_string()], regex_literals(r"RUN [a-z]+\.p\("));
+        assert_eq!(vec!["customer".to_string()], regex_literals(r"^customers?$"));
+        assert_eq!(vec!["init".to_string(), "cust".to_string()], regex_literals(r"init\w*cust"));
+        assert!(regex_literals(r"a|b").is_empty());
+        assert!(regex_literals(r"\x41bc").is_empty());
+    }
+
+    #[test]
+    fn test_search_text() {
+        let root = temp_dir().join("progress_server_test_search_text");
+        let _ = remove_dir_all(&root);
+        create_dir_all(root.join("src")).unwrap();
+        File::create(root.join("src/a.p")).unwrap()
+            .write_all(b"DEFINE VARIABLE i AS INTEGER.\nPROCEDURE find-customer:\n    FIND FIRST Customer.\nEND PROCEDURE.\n").unwrap();
+        File::create(root.join("src/b.p")).unwrap().write_all(b"DISPLAY \"no customers here\".\n").unwrap();
+
+        let sources = Sources::new(vec![root.join("src").to_string_lossy().into_owned()], 2,
+                                   HttpClient::new(Duration::from_secs(1), Duration::from_secs(1), 0), ContentCache::new(0)).unwrap();
+        let propath = sources.propath(None).unwrap();
+        let index = Index::open(root.join("index.sqlite")).unwrap();
+        update_file(&index, &propath, "a.p").unwrap();
+        update_file(&index, &propath, "b.p").unwrap();
+        let parses = ParseCache::new(4);
+
+        let mut query = TextQuery::new("find first");
+        query.context = 1;
+        let hits = search_text(&index, &propath, &parses, &query, 2, &PageQuery::default(), 10).unwrap().results;
+        assert_eq!(1, hits.len());
+        assert_eq!(("a.p", 3, 5), (&hits[0].path[..], hits[0].position.row, hits[0].position.column));
+        assert_eq!((vec!["PROCEDURE find-customer:".to_string()], vec!["END PROCEDURE.".to_string()]), (hits[0].before.clone(), hits[0].after.clone()));
+        assert_eq!(Some("find-customer".to_string()), hits[0].scope);
+
+        query = TextQuery::new(r"customers?\b");
+        query.regex = true;
+        query.case_sensitive = true;
+        let hits = search_text(&index, &propath, &parses, &query, 2, &PageQuery::default(), 10).unwrap().results;
+        assert_eq!(vec!["a.p", "b.p"], hits.iter().map(|hit| &hit.path[..]).collect::<Vec<&str>>());
+        assert_eq!(("customer", None), (&hits[0].line[hits[0].start..hits[0].end], hits[1].scope.clone()));
+
+        query.path = Some("b*".to_string());
+        assert_eq!(1, search_text(&index, &propath, &parses, &query, 2, &PageQuery::default(), 10).unwrap().results.len());
+        assert_eq!(query, TextQuery::from_query("q=customers%3F%5Cb&regex=true&case_sensitive=yes&path=b*").unwrap());
+        for invalid in &["regex=true", "q=", "q=x&regex=maybe", "q=x&context=all"] {
+            assert!(TextQuery::from_query(invalid).is_err(), "{}", invalid);
+        }
+
+        remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,266 @@
+use std::collections::VecDeque;
+use std::fs;
+use std::io::{Write, stderr};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{Sender, channel};
+use std::thread;
+use std::time::Duration;
+
+use notify::{DebouncedEvent, RecommendedWatcher, RecursiveMode, Watcher};
+use rocket::Outcome;
+use rocket::request::{self, FromRequest, Request};
+use url::form_urlencoded;
+
+use error::{ProgressResult, Error};
+use source::{SourceProvider, Sources};
+use super::Index;
+use super::indexer::{is_indexed, update_file};
+
+// How long file system events are collected before they are handled, so saving a file once is
+// one change
+const DEBOUNCE_MS: u64 = 500;
+// How many changes are kept for clients that poll
+const KEPT_CHANGES: usize = 1000;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Change {
+    pub seq: u64,
+    pub path: String,
+    /// The file is no longer on the PROPATH
+    pub removed: bool,
+}
+
+/// The answer to a poll for changes
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Changes {
+    /// Poll with this as `since` for the changes after these
+    pub latest: u64,
+    pub changes: Vec<Change>,
+    /// Some changes after `since` were already forgotten, or the server restarted, so anything
+    /// shown may be stale
+    pub missed: bool,
+    /// Whether any sources are watched. When none are there will never be changes to poll for.
+    pub watching: bool,
+}
+
+struct FeedState {
+    latest: u64,
+    changes: VecDeque<Change>,
+}
+
+/// The files that changed, numbered in the order they changed, for clients to poll
+pub struct ChangeFeed {
+    state: Mutex<FeedState>,
+    watching: AtomicBool,
+}
+
+impl ChangeFeed {
+    pub fn new() -> Self {
+        ChangeFeed {
+            state: Mutex::new(FeedState { latest: 0, changes: VecDeque::new() }),
+            watching: AtomicBool::new(false),
+        }
+    }
+
+    fn set_watching(&self) {
+        self.watching.store(true, Ordering::SeqCst);
+    }
+
+    pub fn publish(&self, path: String, removed: bool) {
+        let mut state = self.state.lock().unwrap();
+        state.latest += 1;
+        let seq = state.latest;
+        state.changes.push_back(Change { seq, path, removed });
+        if state.changes.len() > KEPT_CHANGES {
+            state.changes.pop_front();
+        }
+    }
+
+    /// The changes after `since`, without waiting. Without `since` the answer only says where to
+    /// start from.
+    pub fn poll(&self, since: Option<u64>) -> Changes {
+        let watching = self.watching.load(Ordering::SeqCst);
+        let state = self.state.lock().unwrap();
+        let since = match since {
+            Some(since) => since,
+            None => return Changes { latest: state.latest, changes: Vec::new(), missed: false, watching },
+        };
+        let missed = since > state.latest || state.changes.front().map_or(false, |first| first.seq > since + 1);
+        Changes {
+            latest: state.latest,
+            changes: state.changes.iter().filter(|change| change.seq > since).cloned().collect(),
+            missed,
+            watching,
+        }
+    }
+}
+
+/// The `since` of a poll for changes, from its query
+pub struct Since(pub Option<u64>);
+
+impl<'a, 'r> FromRequest<'a, 'r> for Since {
+    type Error = ();
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<Self, ()> {
+        let since = request.uri().query().and_then(|query| {
+            form_urlencoded::parse(query.as_bytes())
+                .find(|&(ref key, _)| key == "since")
+                .and_then(|(_, value)| value.parse().ok())
+        });
+        Outcome::Success(Since(since))
+    }
+}
+
+// The path of `full_path` on the PROPATH, from the first of `roots` it is in
+fn propath_path(roots: &[PathBuf], full_path: &Path) -> Option<String> {
+    roots.iter()
+        .filter_map(|root| full_path.strip_prefix(root).ok())
+        .next()
+        .map(|relative| {
+            let parts: Vec<String> = relative.components()
+                .map(|component| component.as_os_str().to_string_lossy().into_owned())
+                .collect();
+            parts.join("/")
+        })
+}
+
+// Bring the caches and the index up to date with a path that changed, and tell the clients
+fn changed(sources: &Sources, index: &Index, changes: &ChangeFeed, refresh: &Sender<()>, full_path: &Path, path: String) -> ProgressResult<()> {
+    sources.invalidate(&path);
+    let propath = sources.propath(None)?;
+    if is_indexed(&path) {
+        update_file(index, &propath, &path)?;
+    } else if full_path.is_dir() || !full_path.exists() {
+        // A whole directory came or went, which is easiest to pick up with a crawl
+        let _ = refresh.send(());
+    }
+    let removed = match propath.stat(&path) {
+        Err(Error::NotFound(_)) => true,
+        _ => false,
+    };
+    changes.publish(path, removed);
+    Ok(())
+}
+
+/// Watch the local directories on the PROPATH. Files that change are dropped from the content
+/// cache, indexed again and published to `changes`. Parses are cached by content, so the parse of
+/// a changed file is never reused.
+pub fn watch(sources: Sources, index: Arc<Index>, changes: Arc<ChangeFeed>, refresh: Sender<()>) -> ProgressResult<()> {
+    let roots = sources.local_roots();
+    if roots.is_empty() {
+        return Ok(());
+    }
+    let (sender, receiver) = channel();
+    let mut watcher: RecommendedWatcher = Watcher::new(sender, Duration::from_millis(DEBOUNCE_MS))?;
+    let mut watched = Vec::with_capacity(roots.len());
+    for root in roots {
+        let root = fs::canonicalize(&root)
+            .map_err(|err| Error::new(format!("Can not watch '{}': {}", root.display(), err)))?;
+        watcher.watch(&root, RecursiveMode::Recursive)?;
+        watched.push(root);
+    }
+    changes.set_watching();
+    thread::spawn(move || {
+        // The watcher stops when it is dropped, so it lives as long as this thread
+        let _watcher = watcher;
+        for event in receiver {
+            let full_paths = match event {
+                DebouncedEvent::Create(path) | DebouncedEvent::Write(path) | DebouncedEvent::Remove(path) => vec![path],
+                DebouncedEvent::Rename(from, to) => vec![from, to],
+                DebouncedEvent::Rescan => {
+                    let _ = refresh.send(());
+                    Vec::new()
+                },
+                _ => Vec::new(),
+            };
+            for full_path in full_paths {
+                if let Some(path) = propath_path(&watched, &full_path) {
+                    if let Err(err) = changed(&sources, &index, &changes, &refresh, &full_path, path) {
+                        let _ = writeln!(stderr(), "Could not update the index for '{}': {}", full_path.display(), err);
+                    }
+                }
+            }
+        }
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env::temp_dir;
+    use std::fs::{File, create_dir_all, remove_dir_all, remove_file};
+    use std::io::Write;
+    use std::sync::Arc;
+    use std::sync::mpsc::channel;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    use source::{ContentCache, HttpClient, Sources};
+    use super::super::Index;
+    use super::{ChangeFeed, Changes, watch};
+
+    // Poll until there are changes after `since`, for up to 10 seconds
+    fn poll_until_changed(changes: &ChangeFeed, since: u64) -> Changes {
+        let started = Instant::now();
+        loop {
+            let polled = changes.poll(Some(since));
+            if polled.latest != since || started.elapsed() > Duration::from_secs(10) {
+                return polled;
+            }
+            thread::sleep(Duration::from_millis(20));
+        }
+    }
+
+    #[test]
+    fn test_change_feed() {
+        let changes = Arc::new(ChangeFeed::new());
+        assert_eq!((0, false), (changes.poll(None).latest, changes.poll(None).watching));
+        {
+            let changes = changes.clone();
+            thread::spawn(move || {
+                thread::sleep(Duration::from_millis(50));
+                changes.publish("a.p".to_string(), false);
+            });
+        }
+        let polled = poll_until_changed(&changes, 0);
+        assert_eq!((1, false), (polled.latest, polled.missed));
+        assert_eq!("a.p", polled.changes[0].path);
+        assert!(changes.poll(Some(1)).changes.is_empty());
+        assert!(changes.poll(Some(5)).missed);
+    }
+
+    #[test]
+    fn test_watch() {
+        let root = temp_dir().join("progress_server_test_watch");
+        let _ = remove_dir_all(&root);
+        create_dir_all(root.join("src")).unwrap();
+        let sources = Sources::new(vec![root.join("src").to_string_lossy().into_owned()], 1,
+                                   HttpClient::new(Duration::from_secs(1), Duration::from_secs(1), 0), ContentCache::new(1024)).unwrap();
+        let index = Arc::new(Index::open(root.join("index.sqlite")).unwrap());
+        let changes = Arc::new(ChangeFeed::new());
+        let (refresh, _refreshes) = channel();
+        watch(sources, index.clone(), changes.clone(), refresh).unwrap();
+        assert!(changes.poll(None).watching);
+
+        File::create(root.join("src/a.p")).unwrap().write_all(b"RUN b.p.\n").unwrap();
+        let polled = poll_until_changed(&changes, 0);
+        assert_eq!("a.p", polled.changes[0].path);
+        assert_eq!(1, index.symbols_in("a.p").unwrap().len());
+
+        remove_file(root.join("src/a.p")).unwrap();
+        let mut latest = polled.latest;
+        loop {
+            let polled = poll_until_changed(&changes, latest);
+            assert!(polled.latest > latest, "the removal was not seen");
+            if polled.changes.iter().any(|change| change.path == "a.p" && change.removed) {
+                break;
+            }
+            latest = polled.latest;
+        }
+        assert!(index.files().unwrap().is_empty());
+
+        remove_dir_all(&root).unwrap();
+    }
+}
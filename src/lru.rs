@@ -0,0 +1,130 @@
+use std::collections::{BTreeMap, HashMap};
+use std::hash::Hash;
+
+/// How a cache is doing, for the admin routes. `size` and `capacity` are in whatever the cache
+/// limits: bytes for fetched files, entries for parses.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct CacheStats {
+    pub entries: usize,
+    pub size: usize,
+    pub capacity: usize,
+    pub hits: u64,
+    pub misses: u64,
+    pub hit_rate: f64,
+}
+
+impl CacheStats {
+    pub fn new(entries: usize, size: usize, capacity: usize, hits: u64, misses: u64) -> Self {
+        let lookups = hits + misses;
+        let hit_rate = if lookups == 0 { 0.0 } else { hits as f64 / lookups as f64 };
+        CacheStats { entries, size, capacity, hits, misses, hit_rate }
+    }
+}
+
+struct LruEntry<V> {
+    value: V,
+    weight: usize,
+    last_used: u64,
+}
+
+/// A map that holds up to `capacity` total weight of values, dropping the least recently used
+/// values to make room for new ones
+pub struct Lru<K, V> {
+    capacity: usize,
+    entries: HashMap<K, LruEntry<V>>,
+    // The keys by when they were last used, oldest first
+    by_use: BTreeMap<u64, K>,
+    weight: usize,
+    clock: u64,
+}
+
+impl<K: Hash + Eq + Clone, V> Lru<K, V> {
+    pub fn new(capacity: usize) -> Self {
+        Lru {
+            capacity,
+            entries: HashMap::new(),
+            by_use: BTreeMap::new(),
+            weight: 0,
+            clock: 0,
+        }
+    }
+
+    pub fn get(&mut self, key: &K) -> Option<&V> {
+        self.clock += 1;
+        let clock = self.clock;
+        let last_used = match self.entries.get_mut(key) {
+            Some(entry) => {
+                let last_used = entry.last_used;
+                entry.last_used = clock;
+                last_used
+            },
+            None => return None,
+        };
+        self.by_use.remove(&last_used);
+        self.by_use.insert(clock, key.clone());
+        self.entries.get(key).map(|entry| &entry.value)
+    }
+
+    /// Add the value, unless it is heavier than the whole cache
+    pub fn insert(&mut self, key: K, value: V, weight: usize) {
+        self.remove(&key);
+        if weight > self.capacity {
+            return;
+        }
+        while self.weight + weight > self.capacity {
+            let oldest = match self.by_use.keys().next() {
+                Some(&oldest) => oldest,
+                None => break,
+            };
+            if let Some(oldest_key) = self.by_use.remove(&oldest) {
+                self.remove(&oldest_key);
+            }
+        }
+        self.clock += 1;
+        self.weight += weight;
+        self.by_use.insert(self.clock, key.clone());
+        self.entries.insert(key, LruEntry { value, weight, last_used: self.clock });
+    }
+
+    pub fn remove(&mut self, key: &K) -> Option<V> {
+        self.entries.remove(key).map(|entry| {
+            self.weight -= entry.weight;
+            self.by_use.remove(&entry.last_used);
+            entry.value
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// The total weight of the values
+    pub fn weight(&self) -> usize {
+        self.weight
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Lru;
+
+    #[test]
+    fn test_lru() {
+        let mut lru = Lru::new(3);
+        lru.insert("a", 1, 1);
+        lru.insert("b", 2, 1);
+        lru.insert("c", 3, 1);
+        assert_eq!(Some(&1), lru.get(&"a"));
+        lru.insert("d", 4, 2);
+        assert_eq!(None, lru.get(&"b"));
+        assert_eq!(None, lru.get(&"c"));
+        assert_eq!(Some(&1), lru.get(&"a"));
+        assert_eq!(3, lru.weight());
+        lru.insert("e", 5, 4);
+        assert_eq!(2, lru.len());
+    }
+}
@@ -2,57 +2,108 @@
 #![feature(plugin)]
 #![feature(field_init_shorthand)]
 #![feature(conservative_impl_trait)]
+#![cfg_attr(test, feature(test))]
 #![plugin(rocket_codegen)]
 #![allow(dead_code)]
 
 #[macro_use] extern crate serde_derive;
 extern crate combine;
+extern crate crossbeam;
 extern crate docopt;
+extern crate encoding;
+extern crate flate2;
+extern crate git2;
 extern crate hyper;
 extern crate ini;
+extern crate notify;
 extern crate regex;
 extern crate rocket;
 extern crate rocket_contrib;
+extern crate rusqlite;
 extern crate serde;
 extern crate serde_json;
+extern crate tar;
 extern crate url;
+extern crate zip;
+#[cfg(test)] extern crate test;
 
+use std::env::set_var;
+use std::fs::File;
+use std::io::{Write, stderr, stdout};
 use std::path::{Path, PathBuf};
+use std::process::exit;
 
+use docopt::{ArgvMap, Docopt};
 use regex::Regex;
-use rocket::Rocket;
+use rocket::{Rocket, State};
+use rocket::http::ContentType;
 use rocket::response::NamedFile;
+use rocket::response::content::Content;
 use rocket_contrib::JSON;
-use combine::Parser;
-use combine::primitives::from_iter;
 
 
+mod codepage;
+mod config;
 mod error;
+mod index;
+mod lru;
+mod page;
+mod parse_cache;
 mod parser;
 mod util;
-mod file_server_api;
+mod source;
+mod window_preview;
 
 use error::{Error, ProgressResult, from};
 use parser::{
-    CodeBlockType,
     FilePosition,
     PreprocessorAnalysisSection,
     Progress,
-    preprocessed_progress,
-    progress,
+    WindowLayout,
 };
-use util::u8_ref_to_string;
-use file_server_api::{get_procedure_contents, find_procedure};
+use codepage::{Decoded, decode};
+use index::{Call, CallResolver, Callable, Changes, GraphFormat, GraphKind, GraphQuery, Index, IndexStatus, Indexer, SearchQuery, SearchResult, Since, StructureHit,
+            StructureQuery, SymbolMatch, SymbolQuery, TextHit, TextQuery, build_graph, callees, callers, search_structure, search_text};
+use lru::CacheStats;
+use page::{Page, PageQuery, SortOrder};
+use parse_cache::{ContentKey, ParseCache, ParseCacheStats};
+use source::{DirEntry, FileKind, Resolution, Revision, SourceProvider, Sources};
+use util::QueryString;
+use window_preview::render_svg;
+
+const USAGE: &'static str = "
+Serve OpenEdge sources, or export a graph of them from the index the server keeps.
+
+Usage:
+  progress_server
+  progress_server graph <kind> [--format=<format>] [--around=<program>] [--depth=<depth>] [--output=<file>]
+  progress_server (-h | --help)
+
+Options:
+  -h --help            Show this screen.
+  <kind>               calls or includes.
+  --format=<format>    dot or graphml [default: dot].
+  --around=<program>   Only export the programs this close to one program.
+  --depth=<depth>      How many programs away from --around to go [default: 1].
+  --output=<file>      Write the graph to a file instead of standard output.
+";
+
+// How many results a page of a search of names gives at most, and by default
+const SEARCH_PAGE_SIZE: usize = 200;
+// How many hits a page of a search of the text of files gives at most, and by default
+const TEXT_SEARCH_PAGE_SIZE: usize = 500;
 
 #[derive(Serialize, Deserialize)]
 struct ProcedureRes {
+    pub encoding: String,
     pub sections: Vec<PreprocessorAnalysisSection>,
     // pub contents: String,
     pub file_references: Vec<String>,
 }
-#[derive(Serialize, Deserialize)]
-struct ProcedureParseRes {
-    pub parse: Progress
+#[derive(Serialize)]
+struct ProcedureParseRes<'a> {
+    pub encoding: String,
+    pub parse: Progress<'a>
 }
 #[derive(Serialize, Deserialize)]
 struct InnerProcedureRes {
@@ -62,17 +113,37 @@ struct InnerProcedureRes {
     //pub arguments: Vec<ProgressArguments>,
 }
 #[derive(Serialize, Deserialize)]
-struct ProcedureSearchRes {
-    pub results: Vec<String>
-}
-#[derive(Serialize, Deserialize)]
-struct InnerProcedureSearchRes {
-    pub results: Vec<(String, String)>
+struct CallsRes {
+    pub calls: Vec<Call>,
+    /// RUN statements that are unresolved, any of which could also be one of the calls
+    pub unresolved: usize,
 }
 #[derive(Serialize, Deserialize)]
 struct AnalysisSectionsRes {
+    pub encoding: String,
     pub sections: Vec<PreprocessorAnalysisSection>
 }
+#[derive(Serialize, Deserialize)]
+struct TreeEntryRes {
+    pub name: String,
+    pub path: String,
+    /// "file" or "directory"
+    #[serde(rename = "type")]
+    pub entry_type: String,
+    pub size: u64,
+    pub modified: Option<u64>,
+    pub kind: Option<FileKind>,
+}
+#[derive(Serialize, Deserialize)]
+struct TreeRes {
+    pub path: String,
+    pub entries: Vec<TreeEntryRes>,
+}
+#[derive(Serialize, Deserialize)]
+struct CacheStatsRes {
+    pub content: CacheStats,
+    pub parse: ParseCacheStats,
+}
 
 
 #[get("/<path..>")]
@@ -93,87 +164,292 @@ fn static_html_index() -> ProgressResult<NamedFile> {
 
 // Return the given program's contents
 #[get("/procedure/<procedure>")]
-fn get_procedure_route(procedure: String) -> ProgressResult<JSON<ProcedureRes>> {
-    let file_contents = get_procedure_contents(&procedure)?;
-    let file_contents_str: &str = &u8_ref_to_string(&file_contents);
-    let parse = from(preprocessed_progress().parse_stream(file_contents_str))?;
-    let sections = PreprocessorAnalysisSection::from(parse)?;
-
-    //let file_references_regex = Regex::new(r"[-\w/\\]+?\.[pwi]").unwrap();
-    //let file_references = file_references_regex.find_iter(&file_contents).map(|each_match| String::from(each_match.as_str()).replace("\\", "/")).collect();
+fn get_procedure_route(procedure: String, rev: Revision, sources: State<Sources>, parses: State<ParseCache>) -> ProgressResult<JSON<ProcedureRes>> {
+    let file = sources.get_procedure_contents(&procedure, rev.get())?;
+    let Decoded { text, codepage } = decode(&file.contents, file.codepage.as_ref().map(String::as_str))?;
+    let file_contents_str: &str = &text;
+    let preprocessed = parses.preprocessed(&ContentKey::new(&file.contents, &codepage), file_contents_str)?;
+    let parse = preprocessed.nodes(file_contents_str);
+
+    // The include files the procedure uses that are on the PROPATH
+    let propath = sources.propath(rev.get())?;
+    let mut file_references = Vec::new();
+    for include_file in parse.iter().filter_map(|node| node.include_file()) {
+        let include_file = include_file.replace("\\", "/");
+        if !file_references.contains(&include_file) && propath.resolve(&include_file).is_ok() {
+            file_references.push(include_file);
+        }
+    }
     Ok(JSON(ProcedureRes {
-        sections,
-        file_references: vec![]
+        encoding: codepage,
+        sections: preprocessed.sections.clone(),
+        file_references
     }))
 }
 
 #[get("/procedure_parse/<procedure>")]
-fn get_procedure_parse_route(procedure: String) -> ProgressResult<JSON<ProcedureParseRes>> {
-    let file_contents = get_procedure_contents(&procedure)?;
-    let file_contents_str: &str = &u8_ref_to_string(&file_contents);
-    let preprocessor_parse = from(preprocessed_progress().parse_stream(file_contents_str))?;
-    let parse = from(progress().parse_stream(from_iter(preprocessor_parse.into_iter())))?;
+fn get_procedure_parse_route(procedure: String, rev: Revision, sources: State<Sources>, parses: State<ParseCache>) -> ProgressResult<Content<String>> {
+    let file = sources.get_procedure_contents(&procedure, rev.get())?;
+    let Decoded { text, codepage } = decode(&file.contents, file.codepage.as_ref().map(String::as_str))?;
+    let file_contents_str: &str = &text;
+    let parse = parses.statements(&ContentKey::new(&file.contents, &codepage), file_contents_str)?.progress(file_contents_str);
 
-    Ok(JSON(ProcedureParseRes {
+    // The parse borrows from the file contents, so it is turned into JSON here
+    let json = serde_json::to_string(&ProcedureParseRes {
+        encoding: codepage,
         parse
-    }))
-}
-
-#[get("/search/procedure/<procedure>/<inner_procedure>")]
-fn find_inner_procedure_route(procedure: String, inner_procedure: String) -> ProgressResult<JSON<InnerProcedureSearchRes>> {
-    let find_procedures = find_procedure(&procedure)?;
-    let mut results = Vec::new();
-    for each_procedure in find_procedures {
-        let file_contents = get_procedure_contents(&each_procedure)?;
-        let file_contents_str: &str = &u8_ref_to_string(&file_contents);
-        let parse = from(preprocessed_progress().parse_stream(file_contents_str))?;
-        let sections = PreprocessorAnalysisSection::from(parse)?;
-        for section in sections {
-            if let PreprocessorAnalysisSection::CodeBlock { block_type, contents } = section {
-                if let CodeBlockType::Procedure { name, frame_name } = block_type {
-                    if name.contains(&inner_procedure) {
-                        println!("{}", name);
-                        results.push((each_procedure.clone(), name))
-                    }
-                }
-            }
-        }
-    }
-    Ok(JSON(InnerProcedureSearchRes {
-        results
-    }))
+    })?;
+    Ok(Content(ContentType::JSON, json))
 }
 
 // Find a procedure based upon the search query
 #[get("/search/procedure/<procedure>", rank = 2)]
-fn find_procedure_route(procedure: &str) -> ProgressResult<JSON<ProcedureSearchRes>> {
-    let find_results = find_procedure(procedure)?;
-    Ok(JSON(ProcedureSearchRes {
-        results: find_results
+fn find_procedure_route(procedure: &str, query: QueryString, rev: Revision, sources: State<Sources>) -> ProgressResult<JSON<Page<String>>> {
+    let page = PageQuery::from_query(&query.0)?;
+    let find_results = sources.find_procedure(procedure, rev.get())?;
+    Ok(JSON(page.page(find_results, &[SortOrder::Relevance, SortOrder::Name, SortOrder::Path], SEARCH_PAGE_SIZE)?))
+}
+
+// Search the index with a structured query like "kind:procedure file:ar/* calls:validate*"
+#[get("/search")]
+fn search_route(query: QueryString, indexer: State<Indexer>) -> ProgressResult<JSON<Page<SearchResult>>> {
+    let page = PageQuery::from_query(&query.0)?;
+    let sorts = &[SortOrder::Relevance, SortOrder::Name, SortOrder::Path];
+    let results = SearchQuery::from_query(&query.0)?.search(indexer.index(), page.needed(sorts, SEARCH_PAGE_SIZE)?)?;
+    Ok(JSON(page.page_needed(results, sorts, SEARCH_PAGE_SIZE)?))
+}
+
+// Search every indexed file for symbols by name, best matches first
+#[get("/search/symbol")]
+fn search_symbol_route(query: QueryString, indexer: State<Indexer>) -> ProgressResult<JSON<Page<SymbolMatch>>> {
+    let page = PageQuery::from_query(&query.0)?;
+    let sorts = &[SortOrder::Relevance, SortOrder::Name, SortOrder::Path];
+    let results = indexer.index().search(&SymbolQuery::from_query(&query.0)?, page.needed(sorts, SEARCH_PAGE_SIZE)?)?;
+    Ok(JSON(page.page_needed(results, sorts, SEARCH_PAGE_SIZE)?))
+}
+
+// Grep every indexed file for literal text or a regex
+#[get("/search/text")]
+fn search_text_route(query: QueryString, sources: State<Sources>, indexer: State<Indexer>, parses: State<ParseCache>) -> ProgressResult<JSON<Page<TextHit>>> {
+    let page = PageQuery::from_query(&query.0)?;
+    let query = TextQuery::from_query(&query.0)?;
+    let propath = sources.propath(None)?;
+    Ok(JSON(search_text(indexer.index(), &propath, &parses, &query, sources.parallelism(), &page, TEXT_SEARCH_PAGE_SIZE)?))
+}
+
+// Find the statements of every indexed file that match an ABL pattern with metavariables
+#[get("/search/structure")]
+fn search_structure_route(query: QueryString, sources: State<Sources>, indexer: State<Indexer>, parses: State<ParseCache>) -> ProgressResult<JSON<Page<StructureHit>>> {
+    let page = PageQuery::from_query(&query.0)?;
+    let query = StructureQuery::from_query(&query.0)?;
+    let propath = sources.propath(None)?;
+    Ok(JSON(search_structure(indexer.index(), &propath, &parses, &query, sources.parallelism(), &page, TEXT_SEARCH_PAGE_SIZE)?))
+}
+
+// The program, and maybe internal procedure, that a path like ar/cust.p/validate names
+fn callable(path: &PathBuf, resolver: &mut CallResolver) -> ProgressResult<Callable> {
+    let parts: Vec<String> = path.iter().map(|part| part.to_string_lossy().into_owned()).collect();
+    let mut callable = Callable::from_parts(&parts)
+        .ok_or_else(|| Error::NotFound(format!("'{}' is not a program or an internal procedure of one", parts.join("/"))))?;
+    callable.program = resolver.program(&callable.program)?
+        .ok_or_else(|| Error::NotFound(format!("'{}' is not on the PROPATH", callable.program)))?;
+    Ok(callable)
+}
+
+// The RUN statements that run a program, or an internal procedure of one
+#[get("/callers/<path..>")]
+fn callers_route(path: PathBuf, sources: State<Sources>, indexer: State<Indexer>) -> ProgressResult<JSON<CallsRes>> {
+    let propath = sources.propath(None)?;
+    let mut resolver = CallResolver::new(indexer.index(), &propath);
+    let callee = callable(&path, &mut resolver)?;
+    let (calls, unresolved) = callers(&mut resolver, &callee)?;
+    Ok(JSON(CallsRes { calls, unresolved }))
+}
+
+// The RUN statements in a program, or in one procedure, function, method or trigger of it
+#[get("/callees/<path..>")]
+fn callees_route(path: PathBuf, sources: State<Sources>, indexer: State<Indexer>) -> ProgressResult<JSON<CallsRes>> {
+    let propath = sources.propath(None)?;
+    let mut resolver = CallResolver::new(indexer.index(), &propath);
+    let caller = callable(&path, &mut resolver)?;
+    let calls = callees(&mut resolver, &caller)?;
+    let unresolved = calls.iter().filter(|call| call.callee.is_none()).count();
+    Ok(JSON(CallsRes { calls, unresolved }))
+}
+
+// The call or include graph of the index, or the part of it around one program, as DOT or GraphML
+#[get("/graph/<kind>")]
+fn graph_route(kind: String, query: QueryString, sources: State<Sources>, indexer: State<Indexer>) -> ProgressResult<Content<String>> {
+    let kind = GraphKind::from_name(&kind).ok_or_else(|| Error::NotFound(format!("There is no '{}' graph", kind)))?;
+    let query = GraphQuery::from_query(&query.0)?;
+    let propath = sources.propath(None)?;
+    let graph = build_graph(indexer.index(), &propath, kind, &query)?;
+    let content_type = match query.format {
+        GraphFormat::Dot => ContentType::new("text", "vnd.graphviz"),
+        GraphFormat::GraphML => ContentType::new("application", "graphml+xml"),
+    };
+    Ok(Content(content_type, graph.render(query.format)))
+}
+
+// Which PROPATH root a procedure or include file is read from, and which roots it shadows
+#[get("/resolve/<name..>")]
+fn resolve_route(name: PathBuf, rev: Revision, sources: State<Sources>) -> ProgressResult<JSON<Resolution>> {
+    let name: Vec<String> = name.iter().map(|part| part.to_string_lossy().into_owned()).collect();
+    Ok(JSON(sources.resolve_procedure(&name.join("/"), rev.get())?))
+}
+
+// The entries of a directory on the PROPATH, directories first
+fn tree(path: String, rev: Revision, sources: &Sources) -> ProgressResult<JSON<TreeRes>> {
+    let mut entries = sources.list_directory(&path, rev.get())?;
+    entries.sort_by(|a, b| (!a.is_dir, &a.name).cmp(&(!b.is_dir, &b.name)));
+    Ok(JSON(TreeRes {
+        path,
+        entries: entries.into_iter().map(|DirEntry { name, path, is_dir, size, modified }| TreeEntryRes {
+            kind: if is_dir { None } else { FileKind::of(&name) },
+            entry_type: if is_dir { "directory" } else { "file" }.to_string(),
+            name,
+            path,
+            size,
+            modified,
+        }).collect()
     }))
 }
 
+#[get("/tree")]
+fn tree_root_route(rev: Revision, sources: State<Sources>) -> ProgressResult<JSON<TreeRes>> {
+    tree(String::new(), rev, &sources)
+}
+
+#[get("/tree/<path..>")]
+fn tree_route(path: PathBuf, rev: Revision, sources: State<Sources>) -> ProgressResult<JSON<TreeRes>> {
+    let path: Vec<String> = path.iter().map(|part| part.to_string_lossy().into_owned()).collect();
+    tree(path.join("/"), rev, &sources)
+}
+
 // Return the given program's analysis sections
 #[get("/analysis_sections/<procedure>")]
-fn get_analysis_sections_route(procedure: String) -> ProgressResult<JSON<AnalysisSectionsRes>> {
-    let file_contents = get_procedure_contents(&procedure)?;
-    let file_contents_str: &str = &u8_ref_to_string(&file_contents);
-    let parse = from(preprocessed_progress().parse_stream(file_contents_str))?;
-    let sections = PreprocessorAnalysisSection::from(parse)?;
+fn get_analysis_sections_route(procedure: String, rev: Revision, sources: State<Sources>, parses: State<ParseCache>) -> ProgressResult<JSON<AnalysisSectionsRes>> {
+    let file = sources.get_procedure_contents(&procedure, rev.get())?;
+    let Decoded { text, codepage } = decode(&file.contents, file.codepage.as_ref().map(String::as_str))?;
+    let preprocessed = parses.preprocessed(&ContentKey::new(&file.contents, &codepage), &text)?;
     Ok(JSON(AnalysisSectionsRes {
-        sections: sections
+        encoding: codepage,
+        sections: preprocessed.sections.clone()
     }))
 }
 
+// Return an SVG mockup of the given window
+#[get("/window_preview/<procedure>")]
+fn get_window_preview_route(procedure: String, rev: Revision, sources: State<Sources>, parses: State<ParseCache>) -> ProgressResult<Content<String>> {
+    let file = sources.get_procedure_contents(&procedure, rev.get())?;
+    let Decoded { text, codepage } = decode(&file.contents, file.codepage.as_ref().map(String::as_str))?;
+    let preprocessed = parses.preprocessed(&ContentKey::new(&file.contents, &codepage), &text)?;
+    let layout = WindowLayout::from(&preprocessed.sections)?;
+    Ok(Content(ContentType::new("image", "svg+xml"), render_svg(&layout)))
+}
+
+// How well the fetched sources and parse results are being reused
+#[get("/admin/cache")]
+fn cache_stats_route(sources: State<Sources>, parses: State<ParseCache>) -> JSON<CacheStatsRes> {
+    JSON(CacheStatsRes {
+        content: sources.cache_stats(),
+        parse: parses.stats(),
+    })
+}
+
+// How far the background indexer got
+#[get("/admin/index")]
+fn index_status_route(indexer: State<Indexer>) -> ProgressResult<JSON<IndexStatus>> {
+    Ok(JSON(indexer.status()?))
+}
+
+// Crawl the sources again once the current crawl is done
+#[post("/admin/index")]
+fn index_refresh_route(indexer: State<Indexer>) -> ProgressResult<JSON<IndexStatus>> {
+    indexer.refresh();
+    Ok(JSON(indexer.status()?))
+}
+
+// The source files that changed. Pass the `latest` of the last answer as `since`; without it the
+// answer is only where to start from. This answers at once, so polling never holds a worker.
+#[get("/changes")]
+fn changes_route(since: Since, indexer: State<Indexer>) -> JSON<Changes> {
+    JSON(indexer.changes().poll(since.0))
+}
+
+// The graph command, which reads the index without crawling the sources
+fn export_graph(args: &ArgvMap, sources: &Sources) -> ProgressResult<()> {
+    let kind = GraphKind::from_name(args.get_str("<kind>"))
+        .ok_or_else(|| Error::new(format!("Unknown graph '{}'", args.get_str("<kind>"))))?;
+    let format = GraphFormat::from_name(args.get_str("--format"))
+        .ok_or_else(|| Error::new(format!("Unknown graph format '{}'", args.get_str("--format"))))?;
+    let depth = args.get_str("--depth").parse::<usize>().map_err(|_| Error::new("--depth is not a number of programs"))?;
+    let around = match args.get_str("--around") {
+        "" => None,
+        around => Some(around.to_string()),
+    };
+    let query = GraphQuery { format, around, depth: Some(depth) };
+    let propath = sources.propath(None)?;
+    let graph = build_graph(&Index::from_config()?, &propath, kind, &query)?.render(format);
+    match args.get_str("--output") {
+        "" => stdout().write_all(graph.as_bytes())?,
+        path => File::create(path)?.write_all(graph.as_bytes())?,
+    }
+    Ok(())
+}
+
 fn main() {
-    Rocket::ignite()
+    let args = Docopt::new(USAGE).and_then(|docopt| docopt.parse()).unwrap_or_else(|err| err.exit());
+    if args.get_bool("graph") {
+        // Rocket logs what it reads from Rocket.toml to standard output, where the graph goes
+        set_var("ROCKET_LOG", "critical");
+    }
+    // Rocket.toml is read when Rocket ignites, even for a command
+    let rocket = Rocket::ignite();
+    let sources = Sources::from_config().unwrap_or_else(|err| {
+        let _ = writeln!(stderr(), "{}", err);
+        exit(1)
+    });
+    if args.get_bool("graph") {
+        export_graph(&args, &sources).unwrap_or_else(|err| {
+            let _ = writeln!(stderr(), "{}", err);
+            exit(1)
+        });
+        return;
+    }
+    let parses = ParseCache::from_config().unwrap_or_else(|err| {
+        let _ = writeln!(stderr(), "{}", err);
+        exit(1)
+    });
+    let indexer = Indexer::from_config(&sources).unwrap_or_else(|err| {
+        let _ = writeln!(stderr(), "{}", err);
+        exit(1)
+    });
+    rocket
+        .manage(sources)
+        .manage(indexer)
+        .manage(parses)
         .mount("/", routes![static_html_handler, static_html_index])
         .mount("/api", routes![
                get_procedure_route,
                get_procedure_parse_route,
                find_procedure_route,
-               find_inner_procedure_route,
+               search_route,
+               search_symbol_route,
+               search_text_route,
+               search_structure_route,
+               callers_route,
+               callees_route,
+               graph_route,
                get_analysis_sections_route,
+               get_window_preview_route,
+               resolve_route,
+               tree_root_route,
+               tree_route,
+               cache_stats_route,
+               index_status_route,
+               index_refresh_route,
+               changes_route,
         ])
         .mount("/static", routes![static_handler])
         .launch();
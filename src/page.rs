@@ -0,0 +1,319 @@
+use std::ascii::AsciiExt;
+use std::usize;
+
+use url::form_urlencoded;
+
+use error::{ProgressResult, Error};
+use util::parse_flag;
+
+/// The order search results are given in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SortOrder {
+    /// Best matches first, or the order the search finds them in
+    Relevance,
+    /// By name ignoring case, then by path
+    Name,
+    /// By path, then by where in the file
+    Path,
+}
+
+impl SortOrder {
+    pub fn from_name(name: &str) -> Option<SortOrder> {
+        match &name.to_ascii_lowercase()[..] {
+            "relevance" => Some(SortOrder::Relevance),
+            "name" => Some(SortOrder::Name),
+            "path" => Some(SortOrder::Path),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match *self {
+            SortOrder::Relevance => "relevance",
+            SortOrder::Name => "name",
+            SortOrder::Path => "path",
+        }
+    }
+}
+
+/// What a search result is sorted by for `sort=name` and `sort=path`
+pub trait Sortable {
+    fn sort_name(&self) -> &str;
+    fn sort_path(&self) -> &str;
+    /// The row and column the result is at in its file
+    fn sort_position(&self) -> (u32, u32) {
+        (0, 0)
+    }
+}
+
+/// A path is named by its file name
+impl Sortable for String {
+    fn sort_name(&self) -> &str {
+        self.rsplit('/').next().unwrap_or(self)
+    }
+
+    fn sort_path(&self) -> &str {
+        self
+    }
+}
+
+/// Which page of results a request wants
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct PageQuery {
+    /// How many results to give, up to the most the route gives, which is also the default
+    pub limit: Option<usize>,
+    /// The `next` of the page before
+    pub cursor: Option<String>,
+    /// The order to sort by, or the first one the route supports
+    pub sort: Option<SortOrder>,
+    /// Whether the search finds every result, to give the total
+    pub count: bool,
+}
+
+/// One page of search results
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Page<T> {
+    pub results: Vec<T>,
+    /// How many results there are on every page, when that is known
+    pub total: Option<usize>,
+    /// The cursor of the next page, when there is one
+    pub next: Option<String>,
+}
+
+fn invalid_cursor(cursor: &str) -> Error {
+    Error::BadRequest(format!("Invalid cursor '{}'", cursor))
+}
+
+impl PageQuery {
+    /// The page a search wants, from `limit`, `cursor`, `sort` and `count` in the query string of
+    /// a request
+    pub fn from_query(query: &str) -> ProgressResult<Self> {
+        let mut page = PageQuery::default();
+        for (key, value) in form_urlencoded::parse(query.as_bytes()) {
+            match &key[..] {
+                // A page of no results would give the same cursor for the next page
+                "limit" => match value.parse() {
+                    Ok(0) => return Err(Error::BadRequest("limit must be at least 1".to_string())),
+                    Ok(limit) => page.limit = Some(limit),
+                    Err(_) => return Err(Error::BadRequest("limit is not a number of results".to_string())),
+                },
+                "cursor" => page.cursor = Some(value.into_owned()),
+                "sort" => match SortOrder::from_name(&value) {
+                    Some(sort) => page.sort = Some(sort),
+                    None => return Err(Error::BadRequest(format!("Unknown sort '{}'", value))),
+                },
+                "count" => match parse_flag(&value) {
+                    Some(count) => page.count = count,
+                    None => return Err(Error::BadRequest("count is not true or false".to_string())),
+                },
+                _ => {},
+            }
+        }
+        Ok(page)
+    }
+
+    pub fn limit(&self, max: usize) -> usize {
+        self.limit.map_or(max, |limit| limit.min(max))
+    }
+
+    /// The order asked for, or the first of `supported` when none was. Any other order is an
+    /// error.
+    pub fn sort(&self, supported: &[SortOrder]) -> ProgressResult<SortOrder> {
+        match self.sort {
+            None => Ok(supported[0]),
+            Some(sort) if supported.contains(&sort) => Ok(sort),
+            Some(sort) => Err(Error::BadRequest(format!("These results can not be sorted by {}", sort.name()))),
+        }
+    }
+
+    // How many results came before the page
+    fn start(&self) -> ProgressResult<usize> {
+        match self.cursor {
+            Some(ref cursor) => cursor.parse::<usize>().map_err(|_| invalid_cursor(cursor)),
+            None => Ok(0),
+        }
+    }
+
+    /// How many of the best results a search has to find for the page: one more than the end of
+    /// the page, to know whether there is a next one. Other orders need every result, and so does
+    /// `count`.
+    pub fn needed(&self, supported: &[SortOrder], max: usize) -> ProgressResult<usize> {
+        if self.sort(supported)? != SortOrder::Relevance || self.count {
+            return Ok(usize::MAX);
+        }
+        Ok(self.start()?.saturating_add(self.limit(max)).saturating_add(1))
+    }
+
+    /// The page of `results`, which are at most `needed` results in relevance order. When there
+    /// were that many, the search stopped early and the total is not known.
+    pub fn page_needed<T: Sortable>(&self, results: Vec<T>, supported: &[SortOrder], max: usize) -> ProgressResult<Page<T>> {
+        let complete = results.len() < self.needed(supported, max)?;
+        let mut page = self.page(results, supported, max)?;
+        if !complete {
+            page.total = None;
+        }
+        Ok(page)
+    }
+
+    /// The page of `results`, which are every result in relevance order. Its cursor is how many
+    /// results came before it.
+    pub fn page<T: Sortable>(&self, mut results: Vec<T>, supported: &[SortOrder], max: usize) -> ProgressResult<Page<T>> {
+        match self.sort(supported)? {
+            SortOrder::Relevance => {},
+            SortOrder::Name => results.sort_by(|a, b| {
+                (a.sort_name().to_ascii_lowercase(), a.sort_path(), a.sort_position())
+                    .cmp(&(b.sort_name().to_ascii_lowercase(), b.sort_path(), b.sort_position()))
+            }),
+            SortOrder::Path => results.sort_by(|a, b| (a.sort_path(), a.sort_position()).cmp(&(b.sort_path(), b.sort_position()))),
+        }
+        let start = self.start()?;
+        let total = results.len();
+        let end = start.saturating_add(self.limit(max)).min(total);
+        let next = if end < total { Some(end.to_string()) } else { None };
+        let results = if start < total { results.into_iter().skip(start).take(end - start).collect() } else { Vec::new() };
+        Ok(Page { results, total: Some(total), next })
+    }
+
+    /// The page of the results of a search that reads `files`, which are in path order. `find`
+    /// gives the results in each of a chunk of files, in the order of the chunk. Chunks of
+    /// `chunk_size` files are read only until the page is full, unless `count` asks for the total.
+    /// The cursor is how many results of the file the page starts in came before it, and the
+    /// path of that file.
+    pub fn page_files<T, F>(&self, files: &[String], chunk_size: usize, max: usize, mut find: F) -> ProgressResult<Page<T>>
+        where F: FnMut(&[String]) -> ProgressResult<Vec<Vec<T>>> {
+        self.sort(&[SortOrder::Path])?;
+        let (skip, from) = match self.cursor {
+            Some(ref cursor) => {
+                let mut parts = cursor.splitn(2, ':');
+                match (parts.next().and_then(|skip| skip.parse::<usize>().ok()), parts.next()) {
+                    (Some(skip), Some(path)) => (skip, path.to_string()),
+                    _ => return Err(invalid_cursor(cursor)),
+                }
+            },
+            None => (0, String::new()),
+        };
+        let limit = self.limit(max);
+        let first = if self.count { 0 } else { files.iter().position(|path| *path >= from).unwrap_or(files.len()) };
+
+        let mut results = Vec::new();
+        // How many results there are before and after the page, of those that were found
+        let (mut before, mut after) = (0, 0);
+        let mut next = None;
+        let mut finished = true;
+        'chunks: for chunk in files[first..].chunks(chunk_size.max(1)) {
+            for (path, found) in chunk.iter().zip(find(chunk)?) {
+                for (i, result) in found.into_iter().enumerate() {
+                    if (path, i) < (&from, skip) {
+                        before += 1;
+                    } else if results.len() < limit {
+                        results.push(result);
+                    } else {
+                        if next.is_none() {
+                            next = Some(format!("{}:{}", i, path));
+                        }
+                        if !self.count {
+                            finished = false;
+                            break 'chunks;
+                        }
+                        after += 1;
+                    }
+                }
+            }
+        }
+        // Without `count` the results before the cursor were not all read
+        let total = if finished && (self.count || self.cursor.is_none()) { Some(before + results.len() + after) } else { None };
+        Ok(Page { results, total, next })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::usize;
+
+    use error::Error;
+    use super::{PageQuery, SortOrder};
+
+    fn paths(paths: &[&str]) -> Vec<String> {
+        paths.iter().map(|path| path.to_string()).collect()
+    }
+
+    #[test]
+    fn test_page() {
+        let found = paths(&["b/x.p", "a/z.p", "c/y.p"]);
+        let mut query = PageQuery { limit: Some(2), ..PageQuery::default() };
+        let page = query.page(found.clone(), &[SortOrder::Relevance, SortOrder::Name], 10).unwrap();
+        assert_eq!((paths(&["b/x.p", "a/z.p"]), Some(3), Some("2".to_string())), (page.results, page.total, page.next));
+
+        query.cursor = Some("2".to_string());
+        query.sort = Some(SortOrder::Name);
+        let page = query.page(found.clone(), &[SortOrder::Relevance, SortOrder::Name], 10).unwrap();
+        assert_eq!((paths(&["a/z.p"]), None), (page.results, page.next));
+
+        query.sort = Some(SortOrder::Path);
+        assert!(query.page(found.clone(), &[SortOrder::Relevance], 10).is_err());
+        query.cursor = Some("x".to_string());
+        assert!(query.page(found, &[SortOrder::Path], 10).is_err());
+    }
+
+    #[test]
+    fn test_page_needed() {
+        let relevance = &[SortOrder::Relevance, SortOrder::Name];
+        let mut query = PageQuery { limit: Some(2), cursor: Some("1".to_string()), ..PageQuery::default() };
+        assert_eq!(4, query.needed(relevance, 10).unwrap());
+        // The search stopped at 4 results, so there may be more
+        let page = query.page_needed(paths(&["a.p", "b.p", "c.p", "d.p"]), relevance, 10).unwrap();
+        assert_eq!((paths(&["b.p", "c.p"]), None, Some("3".to_string())), (page.results, page.total, page.next));
+        let page = query.page_needed(paths(&["a.p", "b.p", "c.p"]), relevance, 10).unwrap();
+        assert_eq!((paths(&["b.p", "c.p"]), Some(3), None), (page.results, page.total, page.next));
+
+        query.count = true;
+        assert_eq!(usize::MAX, query.needed(relevance, 10).unwrap());
+        query.count = false;
+        query.sort = Some(SortOrder::Name);
+        assert_eq!(usize::MAX, query.needed(relevance, 10).unwrap());
+        query.cursor = Some("x".to_string());
+        query.sort = None;
+        assert!(query.needed(relevance, 10).is_err());
+    }
+
+    #[test]
+    fn test_page_files() {
+        let files = paths(&["a.p", "b.p", "c.p", "d.p"]);
+        // Each file has as many results as the number of its letter, and d.p is never read
+        let find = |chunk: &[String]| -> Vec<Vec<String>> {
+            assert!(!chunk.contains(&"d.p".to_string()));
+            chunk.iter().map(|path| {
+                let count = (path.as_bytes()[0] - b'a' + 1) as usize;
+                (0..count).map(|i| format!("{}{}", path, i)).collect()
+            }).collect()
+        };
+        let mut query = PageQuery { limit: Some(2), ..PageQuery::default() };
+        let page = query.page_files(&files, 1, 10, |chunk| Ok(find(chunk))).unwrap();
+        assert_eq!((paths(&["a.p0", "b.p0"]), None, Some("1:b.p".to_string())), (page.results, page.total, page.next));
+
+        query.cursor = page.next;
+        let page = query.page_files(&files, 1, 10, |chunk| Ok(find(chunk))).unwrap();
+        assert_eq!((paths(&["b.p1", "c.p0"]), Some("1:c.p".to_string())), (page.results, page.next));
+
+        query.cursor = Some("x".to_string());
+        assert!(query.page_files(&files, 1, 10, |chunk| Ok(find(chunk))).is_err());
+    }
+
+    #[test]
+    fn test_from_query() {
+        let query = PageQuery::from_query("q=customer&limit=5&cursor=1%3Aa.p&sort=Path&count=yes").unwrap();
+        assert_eq!(PageQuery {
+            limit: Some(5),
+            cursor: Some("1:a.p".to_string()),
+            sort: Some(SortOrder::Path),
+            count: true,
+        }, query);
+        assert_eq!(PageQuery::default(), PageQuery::from_query("").unwrap());
+        for invalid in &["limit=many", "limit=0", "sort=size", "count=maybe"] {
+            match PageQuery::from_query(invalid) {
+                Err(Error::BadRequest(_)) => {},
+                result => panic!("{}: {:?}", invalid, result),
+            }
+        }
+    }
+}
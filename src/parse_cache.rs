@@ -0,0 +1,267 @@
+use std::collections::HashSet;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::sync::{Arc, Condvar, Mutex};
+
+use config::get_integer;
+use error::{ProgressResult, Error};
+use lru::{CacheStats, Lru};
+use parser::{
+    PreprocessorASTNode,
+    PreprocessorAnalysisSection,
+    Progress,
+    Span,
+    Statement,
+    Token,
+    parse_progress,
+    preprocess,
+};
+
+const DEFAULT_PARSE_CACHE_ENTRIES: i64 = 512;
+
+/// What a parse depends on: the source bytes and the codepage they were decoded with
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ContentKey {
+    hash: u64,
+    len: usize,
+    codepage: String,
+}
+
+impl ContentKey {
+    pub fn new(contents: &[u8], codepage: &str) -> Self {
+        let mut hasher = DefaultHasher::new();
+        contents.hash(&mut hasher);
+        ContentKey { hash: hasher.finish(), len: contents.len(), codepage: codepage.to_string() }
+    }
+}
+
+/// The preprocessor nodes and sections of a file. The nodes are kept as spans, since they borrow
+/// their text, and are put back together against the source with `nodes`.
+pub struct Preprocessed {
+    nodes: Vec<(PreprocessorASTNode<'static>, Span)>,
+    pub sections: Vec<PreprocessorAnalysisSection>,
+    // Where the contents of each section are in the source
+    section_spans: Vec<Span>,
+}
+
+impl Preprocessed {
+    fn parse(source: &str) -> ProgressResult<Self> {
+        let nodes = preprocess(source)?;
+        let sections = PreprocessorAnalysisSection::from(source, &nodes)?;
+        Ok(Preprocessed {
+            nodes: nodes.iter().map(|node| (node.with_text(""), node.span(source))).collect(),
+            section_spans: sections.iter().map(|section| Span::of(source, section.contents())).collect(),
+            sections: sections.into_iter().map(PreprocessorAnalysisSection::into_owned).collect(),
+        })
+    }
+
+    /// The section whose contents hold the byte at `offset` of the source
+    pub fn section_at(&self, offset: usize) -> Option<&PreprocessorAnalysisSection> {
+        self.section_spans.iter()
+            .position(|span| span.start <= offset && offset < span.end)
+            .map(|i| &self.sections[i])
+    }
+
+    /// The nodes, borrowing from `source`, which has to be the text that was parsed
+    pub fn nodes<'a>(&self, source: &'a str) -> Vec<PreprocessorASTNode<'a>> {
+        self.nodes.iter().map(|&(ref node, span)| node.with_text(&source[span.start..span.end])).collect()
+    }
+}
+
+/// The statements of a file, kept like the nodes of `Preprocessed`
+pub struct ParsedStatements {
+    progress: Progress<'static>,
+}
+
+impl ParsedStatements {
+    fn parse(source: &str) -> ProgressResult<Self> {
+        Ok(ParsedStatements { progress: rebind(&parse_progress(source)?, "") })
+    }
+
+    /// The statements, borrowing from `source`, which has to be the text that was parsed
+    pub fn progress<'a>(&self, source: &'a str) -> Progress<'a> {
+        rebind(&self.progress, source)
+    }
+}
+
+// The same statements with the text of each token taken from `source`, or left empty when it is
+fn rebind<'a>(progress: &Progress, source: &'a str) -> Progress<'a> {
+    let statements = progress.statements.iter().map(|statement| Statement {
+        span: statement.span,
+        tokens: statement.tokens.iter().map(|token| Token {
+            kind: token.kind,
+            text: if source.is_empty() { "" } else { &source[token.span.start..token.span.end] },
+            span: token.span,
+        }).collect(),
+    }).collect();
+    Progress { statements }
+}
+
+struct MemoState<V> {
+    values: Lru<ContentKey, Arc<V>>,
+    // Keys that are being parsed right now. Anyone else asking for one waits for that parse.
+    parsing: HashSet<ContentKey>,
+    hits: u64,
+    misses: u64,
+}
+
+// Parses by content. Each content is only parsed once, even when it is asked for by several
+// threads at the same time.
+struct Memo<V> {
+    state: Mutex<MemoState<V>>,
+    parsed: Condvar,
+}
+
+// Takes a key off the list of keys being parsed and wakes anyone waiting for it, however the parse
+// ends, so a parse that panics does not leave them waiting forever
+struct Parsing<'m, V: 'm> {
+    memo: &'m Memo<V>,
+    key: ContentKey,
+}
+
+impl<'m, V> Drop for Parsing<'m, V> {
+    fn drop(&mut self) {
+        let mut state = self.memo.state.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        state.parsing.remove(&self.key);
+        self.memo.parsed.notify_all();
+    }
+}
+
+impl<V> Memo<V> {
+    fn new(capacity: usize) -> Self {
+        Memo {
+            state: Mutex::new(MemoState { values: Lru::new(capacity), parsing: HashSet::new(), hits: 0, misses: 0 }),
+            parsed: Condvar::new(),
+        }
+    }
+
+    fn get_or_parse<F>(&self, key: ContentKey, parse: F) -> ProgressResult<Arc<V>>
+        where F: FnOnce() -> ProgressResult<V> {
+        let mut state = self.state.lock().unwrap();
+        loop {
+            let cached = state.values.get(&key).cloned();
+            if let Some(value) = cached {
+                state.hits += 1;
+                return Ok(value);
+            }
+            if !state.parsing.contains(&key) {
+                break;
+            }
+            state = self.parsed.wait(state).unwrap();
+        }
+        state.misses += 1;
+        state.parsing.insert(key.clone());
+        drop(state);
+
+        let parsing = Parsing { memo: self, key: key.clone() };
+        let result = parse().map(Arc::new);
+        if let Ok(ref value) = result {
+            self.state.lock().unwrap().values.insert(key, value.clone(), 1);
+        }
+        drop(parsing);
+        result
+    }
+
+    fn stats(&self) -> CacheStats {
+        let state = self.state.lock().unwrap();
+        CacheStats::new(state.values.len(), state.values.weight(), state.values.capacity(), state.hits, state.misses)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ParseCacheStats {
+    pub preprocessed: CacheStats,
+    pub statements: CacheStats,
+}
+
+/// Parse results shared by every route, so the same content is never parsed twice
+pub struct ParseCache {
+    preprocessed: Memo<Preprocessed>,
+    statements: Memo<ParsedStatements>,
+}
+
+impl ParseCache {
+    /// A cache of up to `entries` parses of each kind
+    pub fn new(entries: usize) -> Self {
+        ParseCache { preprocessed: Memo::new(entries), statements: Memo::new(entries) }
+    }
+
+    /// Holds `parse_cache_entries` from Rocket.toml
+    pub fn from_config() -> ProgressResult<Self> {
+        let entries = get_integer("parse_cache_entries")?.unwrap_or(DEFAULT_PARSE_CACHE_ENTRIES);
+        if entries < 0 {
+            return Err(Error::new("parse_cache_entries can not be negative"));
+        }
+        Ok(ParseCache::new(entries as usize))
+    }
+
+    /// The preprocessor nodes and sections of `source`, which is `key`'s content decoded
+    pub fn preprocessed(&self, key: &ContentKey, source: &str) -> ProgressResult<Arc<Preprocessed>> {
+        self.preprocessed.get_or_parse(key.clone(), || Preprocessed::parse(source))
+    }
+
+    /// The statements of `source`, which is `key`'s content decoded
+    pub fn statements(&self, key: &ContentKey, source: &str) -> ProgressResult<Arc<ParsedStatements>> {
+        self.statements.get_or_parse(key.clone(), || ParsedStatements::parse(source))
+    }
+
+    pub fn stats(&self) -> ParseCacheStats {
+        ParseCacheStats { preprocessed: self.preprocessed.stats(), statements: self.statements.stats() }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::panic::{AssertUnwindSafe, catch_unwind};
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use error::ProgressResult;
+    use util::parallel_map;
+    use super::{ContentKey, Memo, ParseCache};
+
+    #[test]
+    fn test_parse_cache() {
+        let source = "&ANALYZE-SUSPEND _UIB-CODE-BLOCK _CUSTOM _DEFINITIONS wWin\nDEFINE VARIABLE i AS INTEGER.\n&ANALYZE-RESUME\nRUN x.p.\n";
+        let key = ContentKey::new(source.as_bytes(), "UTF-8");
+        let cache = ParseCache::new(4);
+
+        let first = cache.preprocessed(&key, source).unwrap();
+        let second = cache.preprocessed(&key, source).unwrap();
+        assert!(Arc::ptr_eq(&first, &second));
+        assert_eq!(source, first.nodes(source).iter().map(|node| node.text()).collect::<String>());
+        assert_eq!(2, cache.statements(&key, source).unwrap().progress(source).statements.len());
+        let define = source.find("DEFINE").unwrap();
+        assert_eq!(Some("_CUSTOM _DEFINITIONS".to_string()), first.section_at(define).and_then(|section| section.label()));
+
+        let stats = cache.stats();
+        assert_eq!((1, 1, 0.5), (stats.preprocessed.hits, stats.preprocessed.misses, stats.preprocessed.hit_rate));
+
+        // Another codepage is another parse
+        let latin1 = ContentKey::new(source.as_bytes(), "ISO8859-1");
+        cache.preprocessed(&latin1, source).unwrap();
+        assert_eq!(2, cache.stats().preprocessed.misses);
+    }
+
+    #[test]
+    fn test_parse_once() {
+        let memo = Memo::new(4);
+        let parses = AtomicUsize::new(0);
+        let key = ContentKey::new(b"DISPLAY 1.", "UTF-8");
+        let keys: Vec<ContentKey> = (0..16).map(|_| key.clone()).collect();
+        parallel_map(&keys, 8, |key| memo.get_or_parse(key.clone(), || {
+            parses.fetch_add(1, Ordering::SeqCst);
+            Ok(())
+        }).unwrap());
+        assert_eq!(1, parses.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn test_parse_panics() {
+        let memo = Memo::new(4);
+        let key = ContentKey::new(b"DISPLAY 1.", "UTF-8");
+        assert!(catch_unwind(AssertUnwindSafe(|| memo.get_or_parse(key.clone(), || -> ProgressResult<()> { panic!("parser bug") }))).is_err());
+        // The next request parses again instead of waiting for the parse that panicked
+        assert!(memo.get_or_parse(key, || Ok(())).is_ok());
+    }
+}
@@ -1,7 +1,26 @@
-#[derive(Serialize, Deserialize)]
+/// A range of bytes in a source file
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    /// The span of `part` in `source`. `part` has to have been sliced out of `source`.
+    pub fn of(source: &str, part: &str) -> Span {
+        let start = part.as_ptr() as usize - source.as_ptr() as usize;
+        Span { start, end: start + part.len() }
+    }
+
+    pub fn len(&self) -> usize {
+        self.end - self.start
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
 pub struct FilePosition {
-    row: u32,
-    column: u32,
+    pub row: u32,
+    pub column: u32,
 }
 
 impl FilePosition { 
@@ -10,6 +29,28 @@ impl FilePosition {
     }
 }
 
+/// Where each line of a source starts, to turn byte offsets into positions
+pub struct LineIndex {
+    starts: Vec<usize>,
+}
+
+impl LineIndex {
+    pub fn new(source: &str) -> Self {
+        let mut starts = vec![0];
+        starts.extend(source.bytes().enumerate().filter(|&(_, c)| c == b'\n').map(|(i, _)| i + 1));
+        LineIndex { starts }
+    }
+
+    /// The row and column of `offset`, both counted from 1. The column is in bytes.
+    pub fn position(&self, offset: usize) -> FilePosition {
+        let row = match self.starts.binary_search(&offset) {
+            Ok(row) => row,
+            Err(next_row) => next_row - 1,
+        };
+        FilePosition { row: row as u32 + 1, column: (offset - self.starts[row]) as u32 + 1 }
+    }
+}
+
 pub struct FilePositionM<T> {
     file_position: FilePosition,
     inner_type: T,
@@ -0,0 +1,323 @@
+use std::ascii::AsciiExt;
+
+use parser::file_position::Span;
+
+// The reserved words we tell apart from identifiers. This is not every ABL keyword, just the ones
+// the parsers care about or that commonly start a statement.
+const KEYWORDS: &'static [&'static str] = &[
+    "AND", "AS", "ASSIGN", "AT", "BUFFER", "BY", "CASE", "CATCH", "CLASS", "CREATE", "DEF",
+    "DEFINE", "DELETE", "DISPLAY", "DO", "EACH", "ELSE", "END", "FIELD", "FIND", "FINALLY", "FIRST",
+    "FOR", "FORWARD", "FRAME", "FUNCTION", "IF", "IN", "INPUT", "INPUT-OUTPUT", "LAST", "LEAVE",
+    "MESSAGE", "METHOD", "NEW", "NEXT", "NO-ERROR", "NO-LOCK", "NO-UNDO", "NOT", "OF", "ON", "OR",
+    "OUTPUT", "PARAMETER", "PERSISTENT", "PROCEDURE", "QUERY", "REPEAT", "RETURN", "RETURNS", "RUN",
+    "SET", "SHARE-LOCK", "SHARED", "TABLE", "TEMP-TABLE", "THEN", "TRIGGER", "VALUE", "VAR",
+    "VARIABLE", "WHEN", "WHERE", "WITH", "EXCLUSIVE-LOCK",
+];
+
+/// What kind of text a token is. Every character of a file belongs to exactly one token, so the
+/// tokens of a file can be joined back into it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TokenKind {
+    Identifier,
+    Keyword,
+    String,
+    Number,
+    Punctuation,
+    /// The '.' or ':' that ends a statement or starts a block
+    Terminator,
+    Whitespace,
+    Comment,
+    /// An '&' directive, which runs to the end of the line
+    PreprocessorDirective,
+    /// Anything in braces: an include file, an argument or a preprocessor name
+    IncludeReference,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct Token<'a> {
+    pub kind: TokenKind,
+    pub text: &'a str,
+    pub span: Span,
+}
+
+impl TokenKind {
+    /// Whether the token is part of the code the statement parser sees
+    pub fn is_significant(&self) -> bool {
+        match *self {
+            TokenKind::Whitespace | TokenKind::Comment | TokenKind::PreprocessorDirective | TokenKind::IncludeReference => false,
+            _ => true,
+        }
+    }
+}
+
+impl<'a> Token<'a> {
+    pub fn is_keyword(&self, keyword: &str) -> bool {
+        (self.kind == TokenKind::Keyword || self.kind == TokenKind::Identifier) && self.text.eq_ignore_ascii_case(keyword)
+    }
+}
+
+/// Splits ABL source into tokens. It never fails: text it does not understand becomes punctuation,
+/// and strings and comments that never end run to the end of the file.
+pub struct Lexer<'a> {
+    source: &'a str,
+    position: usize,
+}
+
+fn is_identifier_start(c: u8) -> bool {
+    (c as char).is_alphabetic() || c == b'_' || c >= 0x80
+}
+
+fn is_identifier_char(c: u8) -> bool {
+    (c as char).is_alphanumeric() || c == b'_' || c == b'-' || c == b'#' || c == b'$' || c == b'%' || c == b'&' || c >= 0x80
+}
+
+// A letter or digit of a string attribute. Only ASCII, so a scan never stops inside a character.
+fn is_attribute_char(c: u8) -> bool {
+    c.is_ascii() && (c as char).is_alphanumeric()
+}
+
+fn is_whitespace(c: u8) -> bool {
+    c == b' ' || c == b'\t' || c == b'\r' || c == b'\n' || c == 0x0c
+}
+
+fn is_digit(c: u8) -> bool {
+    c >= b'0' && c <= b'9'
+}
+
+impl<'a> Lexer<'a> {
+    pub fn new(source: &'a str) -> Self {
+        Lexer::starting_at(source, 0)
+    }
+
+    /// Lex from `position`, which has to be the start of a token
+    pub fn starting_at(source: &'a str, position: usize) -> Self {
+        Lexer { source, position }
+    }
+
+    fn peek(&self, offset: usize) -> Option<u8> {
+        self.source.as_bytes().get(self.position + offset).map(|&c| c)
+    }
+
+    fn starts_with(&self, s: &str) -> bool {
+        self.source[self.position..].starts_with(s)
+    }
+
+    // The end of the token that starts at the current position
+    fn token_end(&self, first: u8) -> (TokenKind, usize) {
+        let bytes = self.source.as_bytes();
+        let len = bytes.len();
+        let start = self.position;
+        let at = |i: usize| if i < len { Some(bytes[i]) } else { None };
+
+        if is_whitespace(first) {
+            let mut end = start + 1;
+            while at(end).map_or(false, is_whitespace) {
+                end += 1;
+            }
+            return (TokenKind::Whitespace, end);
+        }
+        if self.starts_with("/*") {
+            let mut depth = 0;
+            let mut end = start;
+            while end < len {
+                if bytes[end] == b'/' && at(end + 1) == Some(b'*') {
+                    depth += 1;
+                    end += 2;
+                } else if bytes[end] == b'*' && at(end + 1) == Some(b'/') {
+                    depth -= 1;
+                    end += 2;
+                    if depth == 0 {
+                        break;
+                    }
+                } else {
+                    end += 1;
+                }
+            }
+            return (TokenKind::Comment, end.min(len));
+        }
+        if self.starts_with("//") {
+            let mut end = start;
+            while at(end).map_or(false, |c| c != b'\n' && c != b'\r') {
+                end += 1;
+            }
+            return (TokenKind::Comment, end);
+        }
+        if first == b'&' {
+            // To the end of the line, including the line ending. A '~' at the end of a line
+            // continues the directive on the next one.
+            let mut end = start;
+            loop {
+                while at(end).map_or(false, |c| c != b'\n' && c != b'\r') {
+                    end += 1;
+                }
+                let continued = end > start && bytes[end - 1] == b'~';
+                if at(end) == Some(b'\r') && at(end + 1) == Some(b'\n') {
+                    end += 2;
+                } else if at(end).is_some() {
+                    end += 1;
+                }
+                if !continued || end >= len {
+                    break;
+                }
+            }
+            return (TokenKind::PreprocessorDirective, end);
+        }
+        if first == b'{' {
+            let mut depth = 0;
+            let mut end = start;
+            while end < len {
+                if bytes[end] == b'{' {
+                    depth += 1;
+                } else if bytes[end] == b'}' {
+                    depth -= 1;
+                    if depth == 0 {
+                        return (TokenKind::IncludeReference, end + 1);
+                    }
+                }
+                end += 1;
+            }
+            return (TokenKind::Punctuation, start + 1);
+        }
+        if first == b'"' || first == b'\'' {
+            let mut end = start + 1;
+            while end < len {
+                if bytes[end] == b'~' {
+                    end += 2;
+                } else if bytes[end] == first {
+                    // A doubled quote is an escaped quote
+                    if at(end + 1) == Some(first) {
+                        end += 2;
+                    } else {
+                        end += 1;
+                        break;
+                    }
+                } else {
+                    end += 1;
+                }
+            }
+            let mut end = end.min(len);
+            // String attributes like :U or :R30
+            if at(end) == Some(b':') && at(end + 1).map_or(false, is_attribute_char) {
+                end += 1;
+                while at(end).map_or(false, is_attribute_char) {
+                    end += 1;
+                }
+            }
+            return (TokenKind::String, end);
+        }
+        if is_digit(first) || (first == b'.' && at(start + 1).map_or(false, is_digit)) {
+            let mut end = start;
+            while at(end).map_or(false, is_digit) {
+                end += 1;
+            }
+            if at(end) == Some(b'.') && at(end + 1).map_or(false, is_digit) {
+                end += 1;
+                while at(end).map_or(false, is_digit) {
+                    end += 1;
+                }
+            }
+            return (TokenKind::Number, end);
+        }
+        if is_identifier_start(first) {
+            let mut end = start + 1;
+            loop {
+                while at(end).map_or(false, is_identifier_char) {
+                    end += 1;
+                }
+                // Qualified names like Customer.Name
+                if at(end) == Some(b'.') && at(end + 1).map_or(false, is_identifier_start) {
+                    end += 1;
+                } else {
+                    break;
+                }
+            }
+            while !self.source.is_char_boundary(end) {
+                end += 1;
+            }
+            let kind = if is_keyword(&self.source[start..end]) { TokenKind::Keyword } else { TokenKind::Identifier };
+            return (kind, end);
+        }
+        if (first == b'.' || first == b':') && at(start + 1).map_or(true, is_whitespace) {
+            return (TokenKind::Terminator, start + 1);
+        }
+        let mut end = start + 1;
+        while !self.source.is_char_boundary(end) {
+            end += 1;
+        }
+        (TokenKind::Punctuation, end)
+    }
+}
+
+impl<'a> Iterator for Lexer<'a> {
+    type Item = Token<'a>;
+
+    fn next(&mut self) -> Option<Token<'a>> {
+        let first = match self.peek(0) {
+            Some(first) => first,
+            None => return None,
+        };
+        let start = self.position;
+        let (kind, end) = self.token_end(first);
+        self.position = end;
+        Some(Token {
+            kind,
+            text: &self.source[start..end],
+            span: Span { start, end },
+        })
+    }
+}
+
+pub fn is_keyword(word: &str) -> bool {
+    KEYWORDS.iter().any(|keyword| keyword.eq_ignore_ascii_case(word))
+}
+
+pub fn tokenize(source: &str) -> Vec<Token> {
+    Lexer::new(source).collect()
+}
+
+/// The tokens the statement parser works on, without whitespace, comments and preprocessor text
+pub fn significant_tokens<'a>(tokens: &[Token<'a>]) -> Vec<Token<'a>> {
+    tokens.iter().filter(|token| token.kind.is_significant()).cloned().collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{TokenKind, tokenize};
+
+    #[test]
+    fn test_tokenize() {
+        let source = "&SCOPED-DEFINE x ~\r\n  1\r\nFIND FIRST Customer.Name WHERE x = \"a\"\"b\":U /* c /* d */ */ NO-LOCK.\n{inc/a.i &p={&x}} DO: .5 'x' {";
+        let tokens = tokenize(source);
+
+        let joined: String = tokens.iter().map(|token| token.text).collect();
+        assert_eq!(source, joined);
+
+        let kinds: Vec<(TokenKind, &str)> = tokens.iter()
+            .filter(|token| token.kind != TokenKind::Whitespace)
+            .map(|token| (token.kind, token.text))
+            .collect();
+        assert_eq!(vec![
+            (TokenKind::PreprocessorDirective, "&SCOPED-DEFINE x ~\r\n  1\r\n"),
+            (TokenKind::Keyword, "FIND"),
+            (TokenKind::Keyword, "FIRST"),
+            (TokenKind::Identifier, "Customer.Name"),
+            (TokenKind::Keyword, "WHERE"),
+            (TokenKind::Identifier, "x"),
+            (TokenKind::Punctuation, "="),
+            (TokenKind::String, "\"a\"\"b\":U"),
+            (TokenKind::Comment, "/* c /* d */ */"),
+            (TokenKind::Keyword, "NO-LOCK"),
+            (TokenKind::Terminator, "."),
+            (TokenKind::IncludeReference, "{inc/a.i &p={&x}}"),
+            (TokenKind::Keyword, "DO"),
+            (TokenKind::Terminator, ":"),
+            (TokenKind::Number, ".5"),
+            (TokenKind::String, "'x'"),
+            (TokenKind::Punctuation, "{"),
+        ], kinds);
+
+        // An attribute ends before a character that is not ASCII
+        let tokens = tokenize("\"x\":Ué");
+        assert_eq!(vec!["\"x\":U", "é"], tokens.iter().map(|token| token.text).collect::<Vec<_>>());
+    }
+}
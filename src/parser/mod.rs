@@ -1,54 +1,85 @@
 
+mod lexer;
 mod preprocessor;
 mod util;
 mod file_position;
+mod window_layout;
 
-use combine::{skip_many, any, choice, many1, token, try, value, satisfy};
+use combine::{eof, many, many1, satisfy};
+use combine::combinator::optional;
 use combine::primitives::{Parser, Stream};
 
+use error::{from, ProgressResult};
+
+pub use self::lexer::{
+    Lexer,
+    Token,
+    TokenKind,
+    significant_tokens,
+    tokenize,
+};
 pub use self::preprocessor::{
     PreprocessorASTNode,
     PreprocessorAnalysisSection,
     CodeBlockType,
+    SyntaxSection,
+    SyntaxTree,
+    TextEdit,
+    preprocess,
     preprocessed_progress,
 };
-pub use self::file_position::{FilePosition};
+pub use self::file_position::{FilePosition, LineIndex, Span};
+pub use self::window_layout::{
+    FrameLayout,
+    WidgetKind,
+    WidgetLayout,
+    WindowLayout,
+};
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Progress<'a> {
+    pub statements: Vec<Statement<'a>>
+}
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct Progress {
-    statements: Vec<Statement>
+/// The significant tokens of a statement, up to and including the '.' that ends it or the ':'
+/// that starts its block
+#[derive(Debug, Clone, Serialize)]
+pub struct Statement<'a> {
+    pub span: Span,
+    pub tokens: Vec<Token<'a>>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub enum Statement {
-    Generic(String),
+impl<'a> Statement<'a> {
+    /// The text of the statement, including any comments and whitespace inside of it
+    pub fn text<'s>(&self, source: &'s str) -> &'s str {
+        &source[self.span.start..self.span.end]
+    }
 }
 
-pub fn ignore<I: Stream<Item=PreprocessorASTNode>>() -> impl Parser<Input=I, Output=PreprocessorASTNode> {
-    satisfy(|node| 
-            match node {
-                PreprocessorASTNode::AnalysisSuspend(_) => true,
-                PreprocessorASTNode::AnalysisResume => true,
-                PreprocessorASTNode::PreprocessorLine(_) => true, // TODO: this may define code, so don't ignore it
-                PreprocessorASTNode::Import(_) => true, // TODO: this definately defines code, so don't ignoe it
-                PreprocessorASTNode::Replace(_) => true, // TODO: this may define code, so don't ignoe it
-                PreprocessorASTNode::Code(_) => false,
-                PreprocessorASTNode::Comment(_) => true,
-            }
-           )
+fn is_terminator(token: &Token) -> bool {
+    token.kind == TokenKind::Terminator
 }
 
-pub fn statement<I: Stream<Item=PreprocessorASTNode>>() -> impl Parser<Input=I, Output=Statement> {
-    skip_many(ignore()).with(any()).map(|node| 
-                                   match node {
-                                       PreprocessorASTNode::Code(code) => Statement::Generic(code),
-                                       expr => unreachable!("This should not have code: {:?}", expr)
-                                   }
-                                  )
+pub fn statement<'a, I: Stream<Item=Token<'a>>>() -> impl Parser<Input=I, Output=Statement<'a>> {
+    let body = (many1(satisfy(|token: Token<'a>| !is_terminator(&token))), optional(satisfy(|token: Token<'a>| is_terminator(&token))));
+    let empty = satisfy(|token: Token<'a>| is_terminator(&token)).map(|terminator| (Vec::new(), Some(terminator)));
+    body.or(empty).map(|(mut tokens, terminator): (Vec<Token<'a>>, Option<Token<'a>>)| {
+        tokens.extend(terminator);
+        let span = Span { start: tokens[0].span.start, end: tokens[tokens.len() - 1].span.end };
+        Statement { span, tokens }
+    })
 }
 
-pub fn progress<I: Stream<Item=PreprocessorASTNode>>() -> impl Parser<Input=I, Output=Progress> {
-    many1(statement()).map(|statements| Progress {
+pub fn progress<'a, I: Stream<Item=Token<'a>>>() -> impl Parser<Input=I, Output=Progress<'a>> {
+    many(statement()).skip(eof()).map(|statements| Progress {
         statements
     })
 }
+
+/// Lex `source` and parse its statements. Whitespace, comments and anything for the preprocessor
+/// are left out.
+pub fn parse_progress<'a>(source: &'a str) -> ProgressResult<Progress<'a>> {
+    let tokens = significant_tokens(&tokenize(source));
+    let tokens_slice: &[Token<'a>] = &tokens;
+    from(progress().parse_stream(tokens_slice))
+}
@@ -4,7 +4,6 @@ use combine::primitives::{Parser, Stream};
 
 use parser::file_position::{FilePositionM, wrap};
 use parser::util::{identifier, till_eol, tag_no_case};
-use util::u8_ref_to_string;
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum AnalysisSuspendHeader {
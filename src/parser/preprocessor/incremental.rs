@@ -0,0 +1,190 @@
+use combine::Parser;
+
+use error::{ProgressResult, Error};
+use parser::lexer::Token;
+use super::{PreprocessorASTNode, SyntaxTree, node_tokens, preprocessor_node};
+
+// How far past its end the lexer may look to decide where a token, and so a node, ends
+const LOOKAHEAD: usize = 2;
+
+fn is_delimiter(c: u8) -> bool {
+    c == b'{' || c == b'}' || c == b'/' || c == b'*'
+}
+
+// Whether `edit` of `source` adds, removes or touches a brace or a comment delimiter. A '{' that
+// is never closed is lexed on its own, so a '}' added anywhere after it, or a '{' taken out
+// between them, changes how the text from that '{' on is lexed.
+fn changes_delimiters(source: &str, edit: &TextEdit) -> bool {
+    let bytes = source.as_bytes();
+    let before = if edit.start > 0 { Some(bytes[edit.start - 1]) } else { None };
+    let after = bytes.get(edit.end).cloned();
+    bytes[edit.start..edit.end].iter().chain(edit.replacement.as_bytes()).any(|&c| is_delimiter(c))
+        || before.map_or(false, is_delimiter)
+        || after.map_or(false, is_delimiter)
+}
+
+/// Replace the bytes from `start` to `end` of a file with `replacement`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TextEdit {
+    pub start: usize,
+    pub end: usize,
+    pub replacement: String,
+}
+
+impl TextEdit {
+    /// Whether the edit is inside `text` and starts and ends on character boundaries
+    pub fn check(&self, text: &str) -> ProgressResult<()> {
+        if self.start > self.end || self.end > text.len() {
+            return Err(Error::ParseError(format!("The edit {}..{} is outside of the file of length {}", self.start, self.end, text.len())));
+        }
+        if !text.is_char_boundary(self.start) || !text.is_char_boundary(self.end) {
+            return Err(Error::ParseError(format!("The edit {}..{} starts or ends inside a character", self.start, self.end)));
+        }
+        Ok(())
+    }
+
+    pub fn apply(&self, text: &str) -> ProgressResult<String> {
+        self.check(text)?;
+        let mut result = String::with_capacity(text.len() - (self.end - self.start) + self.replacement.len());
+        result.push_str(&text[..self.start]);
+        result.push_str(&self.replacement);
+        result.push_str(&text[self.end..]);
+        Ok(result)
+    }
+}
+
+fn is_code_node(node: &PreprocessorASTNode) -> bool {
+    match *node {
+        PreprocessorASTNode::Code(_) => true,
+        _ => false,
+    }
+}
+
+impl<'a> SyntaxTree<'a> {
+    /// Parse `new_source`, which is the source of this tree with `edit` applied to it. Only the
+    /// nodes around the edit are parsed again: parsing stops as soon as a new node ends where an
+    /// old node after the edit started, since everything from there on is the same text and so
+    /// parses the same way. An edit that adds, removes or touches a brace or a comment delimiter
+    /// can change the nodes long before it, so the whole source is parsed again for it. The nodes
+    /// that are kept are moved over to `new_source` without copying any text.
+    pub fn reparse<'b>(&self, edit: &TextEdit, new_source: &'b str) -> ProgressResult<SyntaxTree<'b>> {
+        let nodes = self.nodes();
+        let old_len = self.source.len();
+        edit.check(self.source)?;
+        if new_source.len() != old_len - (edit.end - edit.start) + edit.replacement.len() || !new_source.is_char_boundary(edit.start + edit.replacement.len()) {
+            return Err(Error::ParseError("The new source does not match the edit".to_string()));
+        }
+        let starts: Vec<usize> = nodes.iter().map(|node| self.span(node).start).collect();
+
+        // A node can look a little past its end to decide where it stops, so the nodes just before
+        // the edited one have to be parsed again too
+        let mut first = starts.iter().rposition(|&start| start <= edit.start).unwrap_or(0);
+        while first > 0 && starts[first] + LOOKAHEAD > edit.start {
+            first -= 1;
+        }
+        // Code runs on until the next node that is not code, so new code right after old code
+        // has to be parsed together with it
+        while first > 0 && is_code_node(nodes[first - 1]) {
+            first -= 1;
+        }
+        if changes_delimiters(self.source, edit) {
+            first = 0;
+        }
+        let base = if first < starts.len() { starts[first] } else { 0 };
+        // Where the text after the edit starts in the new source
+        let unchanged_start = edit.start + edit.replacement.len();
+
+        let mut new_nodes = Vec::new();
+        let mut resume_index = nodes.len();
+        let mut old_index = first;
+        let mut position = base;
+        loop {
+            if position >= unchanged_start {
+                let old_position = edit.end + (position - unchanged_start);
+                while old_index < starts.len() && starts[old_index] < old_position {
+                    old_index += 1;
+                }
+                let at_old_start = old_index < starts.len() && starts[old_index] == old_position;
+                let at_old_end = old_index == starts.len() && old_position == old_len;
+                if at_old_start || at_old_end {
+                    resume_index = old_index;
+                    break;
+                }
+            }
+            if position == new_source.len() {
+                break;
+            }
+            let tokens = node_tokens(new_source, position);
+            let tokens_slice: &[Token<'b>] = &tokens;
+            let (node, _) = preprocessor_node(new_source).parse(tokens_slice)
+                .map_err(|err| Error::ParseError(format!("{:?}", err.errors)))?;
+            position = node.span(new_source).end;
+            new_nodes.push(node);
+        }
+
+        // Everything after the edit moves by the difference in length
+        let new_position = |old_position: usize| old_position + unchanged_start - edit.end;
+        let mut result = Vec::with_capacity(first + new_nodes.len() + nodes.len() - resume_index);
+        for node in &nodes[..first] {
+            let span = self.span(node);
+            result.push(node.with_text(&new_source[span.start..span.end]));
+        }
+        result.extend(new_nodes);
+        for node in &nodes[resume_index..] {
+            let span = self.span(node);
+            result.push(node.with_text(&new_source[new_position(span.start)..new_position(span.end)]));
+        }
+        Ok(SyntaxTree::from(new_source, result))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TextEdit;
+    use super::super::{parse, preprocess, print};
+
+    #[test]
+    fn test_reparse() {
+        let src = "&ANALYZE-SUSPEND _UIB-CODE-BLOCK _PROCEDURE enable_UI wWin\r\n\
+                   PROCEDURE enable_UI :\r\n\
+                   {&OPEN-QUERY-fMain}\r\n\
+                   END PROCEDURE.\r\n\
+                   &ANALYZE-RESUME\r\n\
+                   RUN enable_UI.\r\n";
+        let insert_at = src.find("END PROCEDURE").unwrap();
+        let edits = vec![
+            TextEdit { start: insert_at, end: insert_at, replacement: "VIEW wWin.\r\n".to_string() },
+            TextEdit { start: insert_at, end: insert_at, replacement: "{src/adm2/x.i}".to_string() },
+            TextEdit { start: insert_at, end: insert_at, replacement: "&ANALYZE-RESUME\r\n".to_string() },
+            TextEdit { start: 0, end: src.len(), replacement: String::new() },
+            TextEdit { start: src.len(), end: src.len(), replacement: "{".to_string() },
+            TextEdit { start: 2, end: 9, replacement: "x".to_string() },
+        ];
+        let mut cases: Vec<(&str, TextEdit)> = edits.into_iter().map(|edit| (src, edit)).collect();
+        // Taking out the '}' of an include makes its '{' part of the code before it
+        cases.push(("abc {inc.i} def", TextEdit { start: 10, end: 11, replacement: String::new() }));
+        // A '}' long after a '{' that was never closed makes an include of all of it
+        let unclosed = "{ x /* c */ y";
+        cases.push((unclosed, TextEdit { start: unclosed.len(), end: unclosed.len(), replacement: "}".to_string() }));
+        for (src, edit) in cases {
+            let edited = edit.apply(src).unwrap();
+            let reparsed = parse(src).unwrap().reparse(&edit, &edited).unwrap();
+            assert_eq!(parse(&edited).unwrap(), reparsed);
+            assert_eq!(preprocess(&edited).unwrap().iter().collect::<Vec<_>>(), reparsed.nodes());
+            assert_eq!(edited, print(&reparsed));
+        }
+
+        // Edits outside of the source, or inside a character, are errors rather than panics
+        let src = "DISPLAY \"é\".\r\n";
+        let tree = parse(src).unwrap();
+        let inside = src.find('é').unwrap() + 1;
+        for edit in vec![
+            TextEdit { start: 0, end: src.len() + 1, replacement: String::new() },
+            TextEdit { start: 4, end: 2, replacement: String::new() },
+            TextEdit { start: inside, end: inside, replacement: "x".to_string() },
+        ] {
+            assert!(edit.apply(src).is_err());
+            assert!(tree.reparse(&edit, src).is_err());
+        }
+    }
+}
@@ -0,0 +1,135 @@
+use error::ProgressResult;
+use parser::file_position::Span;
+use super::{PreprocessorASTNode, preprocess};
+
+/// A lossless tree of a preprocessed file. Nothing is dropped or rewritten, including whitespace,
+/// comments and the analyze-suspend/analyze-resume markers, so printing the tree gives back the
+/// exact source it was parsed from. The nodes borrow their text from `source`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SyntaxTree<'a> {
+    pub source: &'a str,
+    pub sections: Vec<SyntaxSection<'a>>,
+}
+
+/// The nodes between an analyze-suspend and its analyze-resume. Code outside of any section has
+/// neither marker.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SyntaxSection<'a> {
+    pub suspend: Option<PreprocessorASTNode<'a>>,
+    pub nodes: Vec<PreprocessorASTNode<'a>>,
+    pub resume: Option<PreprocessorASTNode<'a>>,
+}
+
+impl<'a> SyntaxSection<'a> {
+    fn new(suspend: Option<PreprocessorASTNode<'a>>) -> Self {
+        SyntaxSection { suspend, nodes: Vec::new(), resume: None }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.suspend.is_none() && self.nodes.is_empty() && self.resume.is_none()
+    }
+
+    pub fn print_to(&self, output: &mut String) {
+        if let Some(ref suspend) = self.suspend {
+            output.push_str(suspend.text());
+        }
+        for node in &self.nodes {
+            output.push_str(node.text());
+        }
+        if let Some(ref resume) = self.resume {
+            output.push_str(resume.text());
+        }
+    }
+}
+
+impl<'a> SyntaxTree<'a> {
+    /// Group the nodes into sections. Unlike `PreprocessorAnalysisSection::from` this never fails:
+    /// an unmatched marker just ends up in a section with only one of its markers.
+    pub fn from(source: &'a str, nodes: Vec<PreprocessorASTNode<'a>>) -> SyntaxTree<'a> {
+        let mut sections = Vec::new();
+        let mut current = SyntaxSection::new(None);
+        for node in nodes {
+            match node {
+                PreprocessorASTNode::AnalysisSuspend(..) => {
+                    if !current.is_empty() {
+                        sections.push(current);
+                    }
+                    current = SyntaxSection::new(Some(node));
+                },
+                PreprocessorASTNode::AnalysisResume(_) => {
+                    current.resume = Some(node);
+                    sections.push(current);
+                    current = SyntaxSection::new(None);
+                },
+                node => current.nodes.push(node),
+            }
+        }
+        if !current.is_empty() {
+            sections.push(current);
+        }
+        SyntaxTree { source, sections }
+    }
+
+    /// All of the nodes of the tree in source order
+    pub fn nodes(&self) -> Vec<&PreprocessorASTNode<'a>> {
+        let mut nodes = Vec::new();
+        for section in &self.sections {
+            if let Some(ref suspend) = section.suspend {
+                nodes.push(suspend);
+            }
+            nodes.extend(section.nodes.iter());
+            if let Some(ref resume) = section.resume {
+                nodes.push(resume);
+            }
+        }
+        nodes
+    }
+
+    /// Where the node is in the source of the tree
+    pub fn span(&self, node: &PreprocessorASTNode<'a>) -> Span {
+        node.span(self.source)
+    }
+}
+
+pub fn parse<'a>(src: &'a str) -> ProgressResult<SyntaxTree<'a>> {
+    let nodes = preprocess(src)?;
+    Ok(SyntaxTree::from(src, nodes))
+}
+
+pub fn print(tree: &SyntaxTree) -> String {
+    let mut output = String::with_capacity(tree.source.len());
+    for section in &tree.sections {
+        section.print_to(&mut output);
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse, print};
+
+    #[test]
+    fn test_round_trip() {
+        let sources = vec![
+            "",
+            "DISPLAY \"no newline at the end\".",
+            "&ANALYZE-SUSPEND _VERSION-NUMBER AB_v10r12 GUI\r\n&ANALYZE-RESUME\r\n\
+             /* Connected Databases \r\n*/\r\n\
+             &Scoped-define WINDOW-NAME wWin\r\n\
+             &ANALYZE-SUSPEND _UIB-CODE-BLOCK _CUSTOM _DEFINITIONS wWin \r\n\
+             {src/adm2/widgetprto.i}\r\n\
+             DEFINE VARIABLE i AS INTEGER NO-UNDO. /* a /* nested */ comment */\r\n\
+             MESSAGE {1} {&WINDOW-NAME}.\r\n\
+             \r\n\
+             /* _UIB-CODE-BLOCK-END */\r\n\
+             &ANALYZE-RESUME\r\n\
+             \n\
+             RUN initializeObject.   \n\
+             IF x THEN DO: {",
+        ];
+        for src in sources {
+            let tree = parse(src).unwrap();
+            assert_eq!(src, print(&tree));
+        }
+    }
+}
@@ -1,13 +1,14 @@
 use std::ascii::AsciiExt;
 
-use combine::{many, many1, satisfy, choice};
+use combine::{eof, many, many1, satisfy, choice, try};
 use combine::char::{char, letter, alpha_num, crlf, newline, string, string_cmp};
 use combine::primitives::{Parser, Stream};
 
 // type Parser<O> = combine::Parser<Input: &[u8], Output: O>;
 
 pub fn till_eol<I: Stream<Item=char>>() -> impl Parser<Input=I, Output=String> {
-    many(satisfy(|c| c != '\n' && c != '\r')).skip(crlf().or(newline()))
+    let eol = try(crlf()).map(|_| ()).or(newline().map(|_| ())).or(eof());
+    many(satisfy(|c| c != '\n' && c != '\r')).skip(eol)
 }
 
 pub fn one_of<I: Stream<Item=char>>(chars: &str) -> impl Parser<Input=I, Output=char> {
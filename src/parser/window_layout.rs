@@ -0,0 +1,450 @@
+use std::ascii::AsciiExt;
+
+use error::ProgressResult;
+use parser::lexer::{Lexer, TokenKind};
+use parser::preprocessor::PreprocessorAnalysisSection;
+
+// The size the AppBuilder gives a widget when the definition does not say
+const DEFAULT_BUTTON_SIZE: (f32, f32) = (15.0, 1.14);
+const DEFAULT_FILL_IN_SIZE: (f32, f32) = (14.0, 1.0);
+const DEFAULT_BROWSE_SIZE: (f32, f32) = (40.0, 6.0);
+const DEFAULT_OTHER_SIZE: (f32, f32) = (10.0, 1.0);
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum WidgetKind {
+    Button,
+    FillIn,
+    Browse,
+    Text,
+    Rectangle,
+    Toggle,
+    ComboBox,
+    Editor,
+    Other,
+}
+
+/// A widget placed in a frame. All coordinates are in character units and are 1 based like the
+/// ROW and COL of an AT phrase.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct WidgetLayout {
+    pub name: String,
+    pub kind: WidgetKind,
+    pub row: f32,
+    pub column: f32,
+    pub width: f32,
+    pub height: f32,
+    pub label: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FrameLayout {
+    pub name: String,
+    pub row: f32,
+    pub column: f32,
+    pub width: Option<f32>,
+    pub height: Option<f32>,
+    pub title: Option<String>,
+    pub widgets: Vec<WidgetLayout>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct WindowLayout {
+    pub width: f32,
+    pub height: f32,
+    pub frames: Vec<FrameLayout>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum LayoutToken {
+    Word(String),
+    Str(String),
+    Number(f32),
+    Period,
+    Punct(char),
+}
+
+// What a DEFINE statement said about a widget before it was placed in a frame
+struct WidgetDefinition {
+    kind: WidgetKind,
+    size: Option<(f32, f32)>,
+    label: Option<String>,
+}
+
+impl WindowLayout {
+    /// Build the layout of a window from the sections of a .w file. The window size comes from the
+    /// CreateWindow section, the frames and widgets from the DEFINE statements.
+    pub fn from<S: AsRef<str>>(sections: &[PreprocessorAnalysisSection<S>]) -> ProgressResult<WindowLayout> {
+        let mut width = None;
+        let mut height = None;
+        let mut statements = Vec::new();
+        for section in sections {
+            let contents = match section {
+                &PreprocessorAnalysisSection::CreateWindow { ref attributes, .. } => {
+                    for &(ref name, value) in attributes {
+                        if name.eq_ignore_ascii_case("WIDTH") {
+                            width = Some(value);
+                        } else if name.eq_ignore_ascii_case("HEIGHT") {
+                            height = Some(value);
+                        }
+                    }
+                    continue;
+                },
+                &PreprocessorAnalysisSection::NotInSection { ref contents } => contents,
+                &PreprocessorAnalysisSection::CodeBlock { ref contents, .. } => contents,
+                _ => continue,
+            };
+            statements.extend(split_statements(layout_tokens(contents.as_ref())));
+        }
+
+        let mut definitions = Vec::new();
+        let mut frames = Vec::new();
+        for statement in statements {
+            if !is_word(statement.get(0), "DEFINE") && !is_word(statement.get(0), "DEF") {
+                continue;
+            }
+            // Skip NEW SHARED and the like to get to the type of the definition
+            let mut index = 1;
+            while index < statement.len() && is_any_word(statement.get(index), &["NEW", "GLOBAL", "SHARED", "PRIVATE", "PROTECTED", "PUBLIC", "STATIC"]) {
+                index += 1;
+            }
+            let definition_type = match statement.get(index) {
+                Some(&LayoutToken::Word(ref word)) => word.to_uppercase(),
+                _ => continue,
+            };
+            let name = match statement.get(index + 1) {
+                Some(&LayoutToken::Word(ref name)) => name.clone(),
+                _ => continue,
+            };
+            let rest = &statement[index + 2..];
+            if definition_type == "FRAME" {
+                frames.push(frame_layout(name, rest, &definitions));
+            } else if let Some(definition) = widget_definition(&definition_type, rest) {
+                definitions.push((name, definition));
+            }
+        }
+
+        // Without a CREATE WINDOW fall back to the space the frames take up
+        let (frames_width, frames_height) = frames.iter().fold((0.0f32, 0.0f32), |(w, h), frame| {
+            let (frame_width, frame_height) = frame.extent();
+            (w.max(frame.column - 1.0 + frame_width), h.max(frame.row - 1.0 + frame_height))
+        });
+        Ok(WindowLayout {
+            width: width.unwrap_or(frames_width),
+            height: height.unwrap_or(frames_height),
+            frames,
+        })
+    }
+}
+
+impl FrameLayout {
+    /// The size of the frame, either from its SIZE phrase or from the widgets inside of it
+    pub fn extent(&self) -> (f32, f32) {
+        let (widgets_width, widgets_height) = self.widgets.iter().fold((0.0f32, 0.0f32), |(w, h), widget| {
+            (w.max(widget.column - 1.0 + widget.width), h.max(widget.row - 1.0 + widget.height))
+        });
+        (self.width.unwrap_or(widgets_width), self.height.unwrap_or(widgets_height))
+    }
+}
+
+fn is_word(token: Option<&LayoutToken>, expected: &str) -> bool {
+    match token {
+        Some(&LayoutToken::Word(ref word)) => word.eq_ignore_ascii_case(expected),
+        _ => false,
+    }
+}
+
+fn is_any_word(token: Option<&LayoutToken>, expected: &[&str]) -> bool {
+    expected.iter().any(|word| is_word(token, word))
+}
+
+fn number_at(tokens: &[LayoutToken], index: usize) -> Option<f32> {
+    match tokens.get(index) {
+        Some(&LayoutToken::Number(number)) => Some(number),
+        _ => None,
+    }
+}
+
+fn string_at(tokens: &[LayoutToken], index: usize) -> Option<String> {
+    match tokens.get(index) {
+        Some(&LayoutToken::Str(ref s)) => Some(s.clone()),
+        _ => None,
+    }
+}
+
+// SIZE w BY h, returning the size and the number of tokens used
+fn size_phrase(tokens: &[LayoutToken], index: usize) -> Option<((f32, f32), usize)> {
+    if !is_word(tokens.get(index), "SIZE") || !is_word(tokens.get(index + 2), "BY") {
+        return None;
+    }
+    match (number_at(tokens, index + 1), number_at(tokens, index + 3)) {
+        (Some(width), Some(height)) => Some(((width, height), 4)),
+        _ => None,
+    }
+}
+
+// AT ROW r COL c or AT COL c ROW r, returning (row, column) and the number of tokens used
+fn at_phrase(tokens: &[LayoutToken], index: usize) -> Option<((f32, f32), usize)> {
+    if !is_word(tokens.get(index), "AT") {
+        return None;
+    }
+    let first = number_at(tokens, index + 2);
+    let second = number_at(tokens, index + 4);
+    match (first, second) {
+        (Some(first), Some(second)) => {
+            if is_word(tokens.get(index + 1), "ROW") && is_any_word(tokens.get(index + 3), &["COL", "COLUMN"]) {
+                Some(((first, second), 5))
+            } else if is_any_word(tokens.get(index + 1), &["COL", "COLUMN"]) && is_word(tokens.get(index + 3), "ROW") {
+                Some(((second, first), 5))
+            } else {
+                None
+            }
+        },
+        _ => None,
+    }
+}
+
+fn view_as_kind(word: &str) -> WidgetKind {
+    match &word.to_uppercase()[..] {
+        "FILL-IN" => WidgetKind::FillIn,
+        "TEXT" => WidgetKind::Text,
+        "TOGGLE-BOX" => WidgetKind::Toggle,
+        "COMBO-BOX" => WidgetKind::ComboBox,
+        "EDITOR" => WidgetKind::Editor,
+        _ => WidgetKind::Other,
+    }
+}
+
+fn widget_definition(definition_type: &str, tokens: &[LayoutToken]) -> Option<WidgetDefinition> {
+    let mut kind = match definition_type {
+        "BUTTON" => WidgetKind::Button,
+        "VARIABLE" | "VAR" => WidgetKind::FillIn,
+        "BROWSE" => WidgetKind::Browse,
+        "RECTANGLE" | "RECT" => WidgetKind::Rectangle,
+        "IMAGE" => WidgetKind::Other,
+        _ => return None,
+    };
+    let mut size = None;
+    let mut label = None;
+    let mut index = 0;
+    while index < tokens.len() {
+        if let Some((phrase_size, used)) = size_phrase(tokens, index) {
+            size = Some(phrase_size);
+            index += used;
+        } else if is_word(tokens.get(index), "LABEL") {
+            label = string_at(tokens, index + 1).or(label);
+            index += 2;
+        } else if is_word(tokens.get(index), "VIEW-AS") && kind == WidgetKind::FillIn {
+            if let Some(&LayoutToken::Word(ref view_as)) = tokens.get(index + 1) {
+                kind = view_as_kind(view_as);
+            }
+            index += 2;
+        } else {
+            index += 1;
+        }
+    }
+    Some(WidgetDefinition { kind, size, label })
+}
+
+fn default_size(kind: &WidgetKind) -> (f32, f32) {
+    match kind {
+        &WidgetKind::Button => DEFAULT_BUTTON_SIZE,
+        &WidgetKind::FillIn => DEFAULT_FILL_IN_SIZE,
+        &WidgetKind::Browse => DEFAULT_BROWSE_SIZE,
+        _ => DEFAULT_OTHER_SIZE,
+    }
+}
+
+// The widget list of a DEFINE FRAME, up to its WITH phrase
+fn frame_layout(name: String, tokens: &[LayoutToken], definitions: &[(String, WidgetDefinition)]) -> FrameLayout {
+    let mut frame = FrameLayout {
+        name,
+        row: 1.0,
+        column: 1.0,
+        width: None,
+        height: None,
+        title: None,
+        widgets: Vec::new(),
+    };
+
+    // A widget that has been named but not placed yet
+    let mut current: Option<WidgetLayout> = None;
+    let mut index = 0;
+    while index < tokens.len() && !is_word(tokens.get(index), "WITH") {
+        if let Some(((row, column), used)) = at_phrase(tokens, index) {
+            if let Some(ref mut widget) = current {
+                widget.row = row;
+                widget.column = column;
+            }
+            index += used;
+        } else if let Some(((width, height), used)) = size_phrase(tokens, index) {
+            if let Some(ref mut widget) = current {
+                widget.width = width;
+                widget.height = height;
+            }
+            index += used;
+        } else if is_word(tokens.get(index), "LABEL") {
+            if let Some(ref mut widget) = current {
+                widget.label = string_at(tokens, index + 1).or(widget.label.take());
+            }
+            index += 2;
+        } else if is_word(tokens.get(index), "NO-LABEL") {
+            if let Some(ref mut widget) = current {
+                widget.label = None;
+            }
+            index += 1;
+        } else if is_any_word(tokens.get(index), &["VIEW-AS", "WIDGET-ID", "FORMAT", "FONT", "FGCOLOR", "BGCOLOR", "TOOLTIP", "HELP"]) {
+            index += 2;
+        } else {
+            // Anything else that looks like a name starts the next widget once the current one has
+            // been placed
+            let next = match tokens.get(index) {
+                Some(&LayoutToken::Word(ref word)) => {
+                    let definition = definitions.iter().rev().find(|&&(ref defined, _)| defined.eq_ignore_ascii_case(word));
+                    match definition {
+                        Some(&(_, ref definition)) => {
+                            let (width, height) = definition.size.unwrap_or(default_size(&definition.kind));
+                            Some(WidgetLayout {
+                                name: word.clone(),
+                                kind: definition.kind.clone(),
+                                row: 0.0,
+                                column: 0.0,
+                                width,
+                                height,
+                                label: definition.label.clone(),
+                            })
+                        },
+                        None => None,
+                    }
+                },
+                Some(&LayoutToken::Str(ref text)) => Some(WidgetLayout {
+                    name: text.clone(),
+                    kind: WidgetKind::Text,
+                    row: 0.0,
+                    column: 0.0,
+                    width: text.chars().count() as f32,
+                    height: 1.0,
+                    label: None,
+                }),
+                _ => None,
+            };
+            if let Some(next) = next {
+                if let Some(widget) = current.take() {
+                    if widget.row > 0.0 {
+                        frame.widgets.push(widget);
+                    }
+                }
+                current = Some(next);
+            }
+            index += 1;
+        }
+    }
+    if let Some(widget) = current.take() {
+        if widget.row > 0.0 {
+            frame.widgets.push(widget);
+        }
+    }
+
+    // The WITH phrase places and sizes the frame itself
+    while index < tokens.len() {
+        if let Some(((row, column), used)) = at_phrase(tokens, index) {
+            frame.row = row;
+            frame.column = column;
+            index += used;
+        } else if let Some(((width, height), used)) = size_phrase(tokens, index) {
+            frame.width = Some(width);
+            frame.height = Some(height);
+            index += used;
+        } else if is_word(tokens.get(index), "TITLE") {
+            frame.title = string_at(tokens, index + 1);
+            index += 2;
+        } else {
+            index += 1;
+        }
+    }
+    frame
+}
+
+fn split_statements(tokens: Vec<LayoutToken>) -> Vec<Vec<LayoutToken>> {
+    let mut statements = Vec::new();
+    let mut statement = Vec::new();
+    for token in tokens {
+        if token == LayoutToken::Period {
+            statements.push(statement);
+            statement = Vec::new();
+        } else {
+            statement.push(token);
+        }
+    }
+    if !statement.is_empty() {
+        statements.push(statement);
+    }
+    statements
+}
+
+// The text of a string literal without its quotes or attribute
+fn unquote(literal: &str) -> String {
+    let quote = &literal[..1];
+    let end = literal.rfind(quote).unwrap_or(literal.len());
+    let inner = if end > 0 { &literal[1..end] } else { &literal[1..] };
+    inner.replace(&format!("{}{}", quote, quote), quote)
+}
+
+fn layout_tokens(source: &str) -> Vec<LayoutToken> {
+    Lexer::new(source).filter_map(|token| match token.kind {
+        TokenKind::Identifier | TokenKind::Keyword => Some(LayoutToken::Word(token.text.to_string())),
+        TokenKind::String => Some(LayoutToken::Str(unquote(token.text))),
+        TokenKind::Number => {
+            let number = if token.text.starts_with('.') { format!("0{}", token.text) } else { token.text.to_string() };
+            Some(LayoutToken::Number(number.parse().unwrap_or(0.0)))
+        },
+        TokenKind::Terminator if token.text == "." => Some(LayoutToken::Period),
+        TokenKind::Terminator | TokenKind::Punctuation => token.text.chars().next().map(LayoutToken::Punct),
+        _ => None,
+    }).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use parser::preprocessor::PreprocessorAnalysisSection;
+
+    use super::{WindowLayout, WidgetKind};
+
+    #[test]
+    fn test_window_layout() {
+        let definitions = "DEFINE BUTTON btnOk LABEL \"OK\" SIZE 15 BY 1.14.\n\
+                           DEFINE VARIABLE fiName AS CHARACTER FORMAT \"X(256)\":U LABEL \"Name\" VIEW-AS FILL-IN SIZE 30 BY 1 NO-UNDO.\n\
+                           /* Frame Definitions */\n\
+                           DEFINE FRAME fMain\n     \
+                               fiName AT ROW 2 COL 10 COLON-ALIGNED WIDGET-ID 2\n     \
+                               btnOk AT ROW 4 COL 2\n     \
+                               \"Customer\" VIEW-AS TEXT SIZE 10 BY .62 AT ROW 1 COL 2\n    \
+                               WITH 1 DOWN NO-BOX SIDE-LABELS AT COL 1 ROW 1 SIZE 80 BY 10.\n";
+        let sections = vec![
+            PreprocessorAnalysisSection::CreateWindow {
+                contents: String::new(),
+                attributes: vec![("HEIGHT".to_string(), 10.0), ("WIDTH".to_string(), 80.0)],
+            },
+            PreprocessorAnalysisSection::NotInSection { contents: definitions.to_string() },
+        ];
+
+        let layout = WindowLayout::from(&sections).unwrap();
+        assert_eq!(80.0, layout.width);
+        assert_eq!(10.0, layout.height);
+        assert_eq!(1, layout.frames.len());
+
+        let frame = &layout.frames[0];
+        assert_eq!("fMain", frame.name);
+        assert_eq!(Some(80.0), frame.width);
+        assert_eq!(3, frame.widgets.len());
+
+        assert_eq!(WidgetKind::FillIn, frame.widgets[0].kind);
+        assert_eq!((2.0, 10.0, 30.0), (frame.widgets[0].row, frame.widgets[0].column, frame.widgets[0].width));
+        assert_eq!(Some("Name".to_string()), frame.widgets[0].label);
+
+        assert_eq!(WidgetKind::Button, frame.widgets[1].kind);
+        assert_eq!((4.0, 2.0, 1.14), (frame.widgets[1].row, frame.widgets[1].column, frame.widgets[1].height));
+
+        assert_eq!(WidgetKind::Text, frame.widgets[2].kind);
+        assert_eq!((10.0, 0.62), (frame.widgets[2].width, frame.widgets[2].height));
+    }
+}
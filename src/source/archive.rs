@@ -0,0 +1,340 @@
+use std::ascii::AsciiExt;
+use std::collections::HashMap;
+use std::fs;
+use std::fs::File;
+use std::io::{Cursor, Read, Seek, SeekFrom};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, UNIX_EPOCH};
+
+use flate2::read::GzDecoder;
+use tar;
+use zip::ZipArchive;
+
+use error::{ProgressResult, Error};
+use super::{DirEntry, FileStat, SourceFile, SourceProvider};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ArchiveKind {
+    Zip,
+    Tar,
+    TarGz,
+}
+
+impl ArchiveKind {
+    fn of(path: &str) -> Option<ArchiveKind> {
+        let path = path.to_ascii_lowercase();
+        if path.ends_with(".zip") {
+            Some(ArchiveKind::Zip)
+        } else if path.ends_with(".tar") {
+            Some(ArchiveKind::Tar)
+        } else if path.ends_with(".tar.gz") || path.ends_with(".tgz") {
+            Some(ArchiveKind::TarGz)
+        } else {
+            None
+        }
+    }
+}
+
+// One file or directory in the archive
+struct ArchiveEntry {
+    path: String,
+    is_dir: bool,
+    size: u64,
+    // The index of a zip entry, or where the contents of a tar entry start
+    position: u64,
+}
+
+// What the contents of the entries are read from
+enum Contents {
+    Zip(ZipArchive<File>),
+    Tar(File),
+    // A gzipped tar can only be read from the start, so it is kept decompressed
+    TarGz(Vec<u8>),
+}
+
+// The entries of the archive, read once for as long as the archive file does not change
+struct EntryTable {
+    etag: String,
+    entries: Vec<ArchiveEntry>,
+    // The index in `entries` of each file
+    files: HashMap<String, usize>,
+    contents: Contents,
+}
+
+impl EntryTable {
+    fn read(&mut self, index: usize) -> ProgressResult<Vec<u8>> {
+        let entry = &self.entries[index];
+        let mut buffer = Vec::with_capacity(entry.size as usize);
+        match self.contents {
+            Contents::Zip(ref mut archive) => {
+                archive.by_index(entry.position as usize)?.read_to_end(&mut buffer)?;
+            },
+            Contents::Tar(ref mut file) => {
+                file.seek(SeekFrom::Start(entry.position))?;
+                file.take(entry.size).read_to_end(&mut buffer)?;
+            },
+            Contents::TarGz(ref bytes) => {
+                let start = entry.position as usize;
+                buffer.extend_from_slice(&bytes[start..start + entry.size as usize]);
+            },
+        }
+        Ok(buffer)
+    }
+}
+
+/// A zip or tar snapshot of a codebase on local disk. It is read in place and never extracted.
+/// Its entries are read the first time it is used, and again only when the archive file changes.
+pub struct Archive {
+    path: PathBuf,
+    kind: ArchiveKind,
+    table: Mutex<Option<EntryTable>>,
+}
+
+// Paths in archives can use either separator and start with ./
+fn archive_path(path: &str) -> String {
+    let path = path.replace('\\', "/");
+    let path = path.trim_matches('/');
+    let path = if path.starts_with("./") { &path[2..] } else { path };
+    path.to_string()
+}
+
+impl Archive {
+    /// Whether the file name is an archive that can be read: .zip, .tar, .tar.gz or .tgz
+    pub fn is_archive(path: &str) -> bool {
+        ArchiveKind::of(path).is_some()
+    }
+
+    pub fn new<S: Into<String>>(path: S) -> ProgressResult<Self> {
+        let path = path.into();
+        let kind = ArchiveKind::of(&path).ok_or(Error::new(format!("'{}' is not a zip or tar archive", path)))?;
+        Ok(Archive { path: PathBuf::from(path), kind, table: Mutex::new(None) })
+    }
+
+    fn read_table(&self, etag: String) -> ProgressResult<EntryTable> {
+        let file = File::open(&self.path)?;
+        let (entries, contents) = match self.kind {
+            ArchiveKind::Zip => {
+                let mut archive = ZipArchive::new(file)?;
+                let mut entries = Vec::with_capacity(archive.len());
+                for index in 0..archive.len() {
+                    let zip_file = archive.by_index(index)?;
+                    entries.push(ArchiveEntry {
+                        path: archive_path(zip_file.name()),
+                        is_dir: zip_file.name().ends_with('/'),
+                        size: zip_file.size(),
+                        position: index as u64,
+                    });
+                }
+                (entries, Contents::Zip(archive))
+            },
+            ArchiveKind::Tar => (tar_entries(file)?, Contents::Tar(File::open(&self.path)?)),
+            ArchiveKind::TarGz => {
+                let mut bytes = Vec::new();
+                GzDecoder::new(file)?.read_to_end(&mut bytes)?;
+                let entries = tar_entries(Cursor::new(&bytes[..]))?;
+                (entries, Contents::TarGz(bytes))
+            },
+        };
+        let files = entries.iter().enumerate()
+            .filter(|&(_, entry)| !entry.is_dir)
+            .map(|(i, entry)| (entry.path.clone(), i))
+            .collect();
+        Ok(EntryTable { etag, entries, files, contents })
+    }
+
+    // Call `f` with the entries of the archive, reading them first if the archive changed
+    fn with_table<T, F>(&self, f: F) -> ProgressResult<T>
+        where F: FnOnce(&mut EntryTable) -> ProgressResult<T> {
+        let etag = self.etag()?;
+        let mut table = self.table.lock().unwrap();
+        let stale = table.as_ref().map_or(true, |table| table.etag != etag);
+        if stale {
+            *table = Some(self.read_table(etag)?);
+        }
+        f(table.as_mut().unwrap())
+    }
+
+    // Nothing in the archive changes unless the archive file does
+    fn etag(&self) -> ProgressResult<String> {
+        let metadata = fs::metadata(&self.path)?;
+        let modified = metadata.modified()?.duration_since(UNIX_EPOCH).unwrap_or(Duration::from_secs(0));
+        Ok(format!("{}-{}.{}", metadata.len(), modified.as_secs(), modified.subsec_nanos()))
+    }
+
+    // Every entry counts for the modification time of the archive
+    fn modified(&self) -> Option<u64> {
+        fs::metadata(&self.path).ok()
+            .and_then(|metadata| metadata.modified().ok())
+            .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+            .map(|duration| duration.as_secs())
+    }
+}
+
+fn tar_entries<R: Read>(reader: R) -> ProgressResult<Vec<ArchiveEntry>> {
+    let mut archive = tar::Archive::new(reader);
+    let mut entries = Vec::new();
+    for entry in archive.entries()? {
+        let entry = entry?;
+        entries.push(ArchiveEntry {
+            path: archive_path(&entry.path()?.to_string_lossy()),
+            is_dir: entry.header().entry_type().is_dir(),
+            size: entry.header().size()?,
+            position: entry.raw_file_position(),
+        });
+    }
+    Ok(entries)
+}
+
+impl SourceProvider for Archive {
+    fn get_file(&self, path: &str) -> ProgressResult<SourceFile> {
+        let path = archive_path(path);
+        self.with_table(|table| {
+            let index = *table.files.get(&path).ok_or(Error::NotFound(format!("'{}' is not in {}", path, self.path.display())))?;
+            let contents = table.read(index)?;
+            Ok(SourceFile { contents: Arc::new(contents), codepage: None, etag: Some(table.etag.clone()), last_modified: None })
+        })
+    }
+
+    fn get_file_if_changed(&self, path: &str, cached: &SourceFile) -> ProgressResult<Option<SourceFile>> {
+        if cached.etag.as_ref() == Some(&self.etag()?) {
+            return Ok(None);
+        }
+        self.get_file(path).map(Some)
+    }
+
+    fn find(&self, query: &str) -> ProgressResult<Vec<String>> {
+        let query = query.to_ascii_lowercase();
+        let mut results: Vec<String> = self.with_table(|table| Ok(table.entries.iter()
+            .filter(|entry| !entry.is_dir)
+            .filter(|entry| entry.path.rsplit('/').next().unwrap_or("").to_ascii_lowercase().contains(&query))
+            .map(|entry| entry.path.clone())
+            .collect()))?;
+        results.sort();
+        Ok(results)
+    }
+
+    // Archives do not need an entry for every directory, so directories are worked out from the
+    // paths of the files in them
+    fn list(&self, path: &str) -> ProgressResult<Vec<DirEntry>> {
+        let path = archive_path(path);
+        let prefix = if path.is_empty() { String::new() } else { format!("{}/", path) };
+        let mut found = path.is_empty();
+        let modified = self.modified();
+        let mut entries: Vec<DirEntry> = Vec::new();
+        self.with_table(|table| {
+            for entry in &table.entries {
+                if !entry.path.starts_with(&prefix) || entry.path.len() == prefix.len() {
+                    found = found || (entry.path == path && entry.is_dir);
+                    continue;
+                }
+                found = true;
+                let rest = &entry.path[prefix.len()..];
+                let name = rest.split('/').next().unwrap_or(rest).to_string();
+                let is_dir = entry.is_dir || rest.contains('/');
+                if !entries.iter().any(|existing| existing.name == name) {
+                    entries.push(DirEntry {
+                        path: format!("{}{}", prefix, name),
+                        name,
+                        is_dir,
+                        size: if is_dir { 0 } else { entry.size },
+                        modified,
+                    });
+                }
+            }
+            Ok(())
+        })?;
+        if !found {
+            return Err(Error::NotFound(format!("'{}' is not a directory in {}", path, self.path.display())));
+        }
+        entries.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(entries)
+    }
+
+    fn stat(&self, path: &str) -> ProgressResult<FileStat> {
+        let path = archive_path(path);
+        let prefix = format!("{}/", path);
+        let found = self.with_table(|table| Ok(table.entries.iter()
+            .find(|entry| entry.path == path || entry.path.starts_with(&prefix))
+            .map(|entry| {
+                let is_dir = entry.is_dir || entry.path != path;
+                (is_dir, if is_dir { 0 } else { entry.size })
+            })))?;
+        match found {
+            Some((is_dir, size)) => Ok(FileStat { path: path.clone(), is_dir, size, modified: self.modified() }),
+            None => Err(Error::NotFound(format!("'{}' is not in {}", path, self.path.display()))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env::temp_dir;
+    use std::fs::{File, remove_file};
+    use std::io::Write;
+    use std::path::Path;
+
+    use tar;
+    use zip::ZipWriter;
+    use zip::write::FileOptions;
+
+    use super::Archive;
+    use source::SourceProvider;
+
+    const FILES: &'static [(&'static str, &'static str)] = &[
+        ("src/wWin.w", "RUN x.p."),
+        ("src/inc/customer.i", "DEFINE VARIABLE i AS INTEGER."),
+    ];
+
+    fn check(archive: &Archive) {
+        assert_eq!(b"RUN x.p.".to_vec(), *archive.get_file("src/wWin.w").unwrap().contents);
+        assert_eq!(vec!["src/inc/customer.i".to_string()], archive.find("CUSTOMER").unwrap());
+        let names: Vec<(String, bool)> = archive.list("src").unwrap().into_iter().map(|entry| (entry.name, entry.is_dir)).collect();
+        assert_eq!(vec![("inc".to_string(), true), ("wWin.w".to_string(), false)], names);
+        assert!(archive.stat("src/inc").unwrap().is_dir);
+        assert_eq!(8, archive.stat("src/wWin.w").unwrap().size);
+        assert!(archive.get_file("src/missing.p").is_err());
+    }
+
+    fn write_zip(path: &Path, files: &[(&str, &str)]) {
+        let mut zip = ZipWriter::new(File::create(path).unwrap());
+        for &(name, contents) in files {
+            zip.start_file(name, FileOptions::default()).unwrap();
+            zip.write_all(contents.as_bytes()).unwrap();
+        }
+        zip.finish().unwrap();
+    }
+
+    #[test]
+    fn test_zip_archive() {
+        let path = temp_dir().join("progress_server_test_archive.zip");
+        write_zip(&path, FILES);
+        let archive = Archive::new(path.to_string_lossy().into_owned()).unwrap();
+        check(&archive);
+
+        // The entries are read again once the archive changes
+        write_zip(&path, &[("src/wWin.w", "RUN y.p. /* changed */")]);
+        assert_eq!(b"RUN y.p. /* changed */".to_vec(), *archive.get_file("src/wWin.w").unwrap().contents);
+        assert!(archive.get_file("src/inc/customer.i").is_err());
+        remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_tar_archive() {
+        let path = temp_dir().join("progress_server_test_archive.tar");
+        {
+            let mut builder = tar::Builder::new(File::create(&path).unwrap());
+            for &(name, contents) in FILES {
+                let mut header = tar::Header::new_gnu();
+                header.set_path(name).unwrap();
+                header.set_size(contents.len() as u64);
+                header.set_mode(0o644);
+                header.set_cksum();
+                builder.append(&header, contents.as_bytes()).unwrap();
+            }
+            builder.finish().unwrap();
+        }
+        check(&Archive::new(path.to_string_lossy().into_owned()).unwrap());
+        remove_file(&path).unwrap();
+    }
+}
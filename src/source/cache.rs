@@ -0,0 +1,159 @@
+use std::sync::{Arc, Mutex};
+
+use error::{ProgressResult, Error};
+use lru::{CacheStats, Lru};
+use super::{DirEntry, FileStat, SourceFile, SourceProvider};
+
+struct CacheState {
+    files: Lru<String, Arc<SourceFile>>,
+    hits: u64,
+    misses: u64,
+}
+
+/// Fetched sources, up to `max_bytes` of contents. The least recently used files are dropped first.
+pub struct ContentCache {
+    state: Mutex<CacheState>,
+}
+
+impl ContentCache {
+    pub fn new(max_bytes: usize) -> Self {
+        ContentCache {
+            state: Mutex::new(CacheState { files: Lru::new(max_bytes), hits: 0, misses: 0 }),
+        }
+    }
+
+    fn get(&self, key: &str) -> Option<Arc<SourceFile>> {
+        let mut state = self.state.lock().unwrap();
+        state.files.get(&key.to_string()).cloned()
+    }
+
+    fn insert(&self, key: String, file: SourceFile) {
+        let size = file.contents.len();
+        self.state.lock().unwrap().files.insert(key, Arc::new(file), size);
+    }
+
+    fn remove(&self, key: &str) {
+        self.state.lock().unwrap().files.remove(&key.to_string());
+    }
+
+    /// Drop the file at `path` of the backend whose files are cached under `key_prefix`
+    pub fn invalidate(&self, key_prefix: &str, path: &str) {
+        self.remove(&cache_key(key_prefix, path));
+    }
+
+    fn record(&self, hit: bool) {
+        let mut state = self.state.lock().unwrap();
+        if hit {
+            state.hits += 1;
+        } else {
+            state.misses += 1;
+        }
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        let state = self.state.lock().unwrap();
+        CacheStats::new(state.files.len(), state.files.weight(), state.files.capacity(), state.hits, state.misses)
+    }
+}
+
+fn cache_key(key_prefix: &str, path: &str) -> String {
+    format!("{}\n{}", key_prefix, path)
+}
+
+/// A backend whose files are kept in a `ContentCache`. A cached file is checked with the backend
+/// before it is used, which for a file server is a conditional request that sends nothing back
+/// when the file did not change.
+pub struct CachedSource {
+    // Tells the files of this backend apart from the same paths in others
+    key_prefix: String,
+    source: Arc<SourceProvider>,
+    cache: Arc<ContentCache>,
+}
+
+impl CachedSource {
+    pub fn new(key_prefix: String, source: Arc<SourceProvider>, cache: Arc<ContentCache>) -> Self {
+        CachedSource { key_prefix, source, cache }
+    }
+}
+
+impl SourceProvider for CachedSource {
+    fn get_file(&self, path: &str) -> ProgressResult<SourceFile> {
+        let key = cache_key(&self.key_prefix, path);
+        let result = match self.cache.get(&key) {
+            Some(cached) => match self.source.get_file_if_changed(path, &cached) {
+                Ok(None) => {
+                    self.cache.record(true);
+                    return Ok((*cached).clone());
+                },
+                result => result,
+            },
+            None => self.source.get_file(path).map(Some),
+        };
+        self.cache.record(false);
+        match result {
+            Ok(Some(file)) => {
+                self.cache.insert(key, file.clone());
+                Ok(file)
+            },
+            Ok(None) => Err(Error::Upstream(format!("'{}' was not changed but is not cached", path))),
+            Err(err) => {
+                self.cache.remove(&key);
+                Err(err)
+            },
+        }
+    }
+
+    fn find(&self, query: &str) -> ProgressResult<Vec<String>> {
+        self.source.find(query)
+    }
+
+    fn list(&self, path: &str) -> ProgressResult<Vec<DirEntry>> {
+        self.source.list(path)
+    }
+
+    fn stat(&self, path: &str) -> ProgressResult<FileStat> {
+        self.source.stat(path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env::temp_dir;
+    use std::fs::{File, create_dir_all, remove_dir_all};
+    use std::io::Write;
+    use std::sync::Arc;
+
+    use source::{LocalDirectory, SourceProvider};
+    use super::{CachedSource, ContentCache};
+
+    #[test]
+    fn test_content_cache() {
+        let root = temp_dir().join("progress_server_test_content_cache");
+        let _ = remove_dir_all(&root);
+        create_dir_all(&root).unwrap();
+        for &(name, contents) in &[("a.p", "RUN b.p."), ("b.p", "RUN c.p."), ("c.p", "DISPLAY 1.")] {
+            File::create(root.join(name)).unwrap().write_all(contents.as_bytes()).unwrap();
+        }
+        // Room for two of the files
+        let cache = Arc::new(ContentCache::new(18));
+        let source = CachedSource::new("local".to_string(), Arc::new(LocalDirectory::new(root.clone())), cache.clone());
+
+        source.get_file("a.p").unwrap();
+        source.get_file("b.p").unwrap();
+        source.get_file("a.p").unwrap();
+        // A hit shares the cached contents instead of copying them
+        assert!(Arc::ptr_eq(&source.get_file("a.p").unwrap().contents, &source.get_file("a.p").unwrap().contents));
+        assert_eq!((3, 2, 2), { let stats = cache.stats(); (stats.hits, stats.misses, stats.entries) });
+
+        // b.p is the least recently used, so it makes room for c.p
+        source.get_file("c.p").unwrap();
+        source.get_file("a.p").unwrap();
+        source.get_file("b.p").unwrap();
+        assert_eq!((4, 4), { let stats = cache.stats(); (stats.hits, stats.misses) });
+
+        File::create(root.join("b.p")).unwrap().write_all(b"RUN dd.p.").unwrap();
+        assert_eq!(b"RUN dd.p.".to_vec(), *source.get_file("b.p").unwrap().contents);
+
+        remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,288 @@
+use hyper;
+use hyper::Client;
+use hyper::client::pool::{Config, Pool};
+use hyper::client::response::Response;
+use hyper::header::{ContentLength, Headers, LastModified};
+use hyper::method::Method;
+use hyper::net::{HttpStream, NetworkConnector};
+use hyper::status::StatusCode as hStatusCode;
+use serde_json::from_str;
+use std::io;
+use std::io::Read;
+use std::net::{TcpStream, ToSocketAddrs};
+use std::str;
+use std::sync::Arc;
+use std::thread::sleep;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use url::Url;
+
+use error::{ProgressResult, Error};
+use super::{DirEntry, FileStat, SourceFile, SourceProvider};
+
+// The first retry waits this long, and every retry after it twice as long as the one before
+const RETRY_BACKOFF_MS: u64 = 100;
+
+// Opens plain http connections, giving up on a host after `timeout`. There is no TLS, so https
+// file servers are turned down when the PROPATH is set up.
+struct TimeoutConnector {
+    timeout: Duration,
+}
+
+impl NetworkConnector for TimeoutConnector {
+    type Stream = HttpStream;
+
+    fn connect(&self, host: &str, port: u16, scheme: &str) -> hyper::Result<HttpStream> {
+        if scheme != "http" {
+            return Err(hyper::Error::Io(io::Error::new(io::ErrorKind::InvalidInput, format!("Unsupported scheme '{}'", scheme))));
+        }
+        let mut last_err = io::Error::new(io::ErrorKind::NotFound, format!("'{}' has no addresses", host));
+        for address in (host, port).to_socket_addrs()? {
+            match TcpStream::connect_timeout(&address, self.timeout) {
+                Ok(stream) => return Ok(HttpStream(stream)),
+                Err(err) => last_err = err,
+            }
+        }
+        Err(hyper::Error::Io(last_err))
+    }
+}
+
+/// The connection pool for file servers along with how long to wait on them and how often to try
+/// again. It is shared by every file server so connections are kept alive between requests.
+pub struct HttpClient {
+    client: Client,
+    retries: u32,
+}
+
+impl HttpClient {
+    pub fn new(connect_timeout: Duration, read_timeout: Duration, retries: u32) -> Self {
+        let mut client = Client::with_connector(Pool::with_connector(Config::default(), TimeoutConnector { timeout: connect_timeout }));
+        client.set_read_timeout(Some(read_timeout));
+        client.set_write_timeout(Some(read_timeout));
+        HttpClient { client, retries }
+    }
+
+    // Send a GET or HEAD, trying again with a growing delay when the server can not be reached,
+    // does not answer in time or fails with a 5xx. A 404 becomes NotFound; a 304 for a conditional
+    // request is returned as it is.
+    fn send(&self, method: Method, url: Url, path: &str, headers: Headers) -> ProgressResult<Response> {
+        let mut attempt = 0;
+        loop {
+            let result = match self.client.request(method.clone(), url.clone()).headers(headers.clone()).send() {
+                Ok(res) => {
+                    if res.status == hStatusCode::NotFound {
+                        return Err(Error::NotFound(format!("'{}' does not exist on the file server", path)));
+                    } else if res.status.is_success() || res.status == hStatusCode::NotModified {
+                        return Ok(res);
+                    } else if res.status.is_server_error() {
+                        Error::Upstream(format!("The file server failed with {} for '{}'", res.status, path))
+                    } else {
+                        return Err(Error::Upstream(format!("The file server answered {} for '{}'", res.status, path)));
+                    }
+                },
+                Err(err) => upstream_error(err, path),
+            };
+            if attempt >= self.retries {
+                return Err(result);
+            }
+            sleep(Duration::from_millis(RETRY_BACKOFF_MS << attempt));
+            attempt += 1;
+        }
+    }
+}
+
+fn upstream_error(err: hyper::Error, path: &str) -> Error {
+    match err {
+        hyper::Error::Io(ref io_err) if io_err.kind() == io::ErrorKind::TimedOut || io_err.kind() == io::ErrorKind::WouldBlock =>
+            Error::Timeout(format!("The file server did not answer in time for '{}'", path)),
+        err => Error::Upstream(format!("Could not reach the file server for '{}': {}", path, err)),
+    }
+}
+
+/// The separate file server process, which serves files under file/, searches under find/ and
+/// directory listings under list/
+pub struct FileServer {
+    address: Url,
+    client: Arc<HttpClient>,
+}
+
+impl FileServer {
+    /// A file server reached through `client`, which is shared so its connections are reused
+    pub fn new(address: Url, client: Arc<HttpClient>) -> Self {
+        FileServer { address, client }
+    }
+}
+
+impl SourceProvider for FileServer {
+    fn get_file(&self, path: &str) -> ProgressResult<SourceFile> {
+        get_progress_file(&self.client, &self.address, path, None)?
+            .ok_or(Error::Upstream(format!("The file server answered 304 for '{}' without being asked", path)))
+    }
+
+    fn get_file_if_changed(&self, path: &str, cached: &SourceFile) -> ProgressResult<Option<SourceFile>> {
+        get_progress_file(&self.client, &self.address, path, Some(cached))
+    }
+
+    fn find(&self, query: &str) -> ProgressResult<Vec<String>> {
+        find_progress_file(&self.client, &self.address, query)
+    }
+
+    fn list(&self, path: &str) -> ProgressResult<Vec<DirEntry>> {
+        list_progress_directory(&self.client, &self.address, path)
+    }
+
+    fn stat(&self, path: &str) -> ProgressResult<FileStat> {
+        stat_progress_file(&self.client, &self.address, path)
+    }
+}
+
+// The codepage of a file, from an X-Codepage header or the charset of its Content-Type
+fn codepage_from_headers(headers: &Headers) -> Option<String> {
+    if let Some(codepage) = raw_header(headers, "X-Codepage") {
+        return Some(codepage);
+    }
+    raw_header(headers, "Content-Type").and_then(|content_type| {
+        content_type.split(';')
+            .map(|param| param.trim())
+            .filter(|param| param.to_lowercase().starts_with("charset="))
+            .map(|param| param["charset=".len()..].trim_matches('"').to_string())
+            .next()
+    })
+}
+
+fn raw_header(headers: &Headers, name: &str) -> Option<String> {
+    headers.get_raw(name)
+        .and_then(|values| values.first())
+        .and_then(|value| str::from_utf8(value).ok())
+        .map(|value| value.trim().to_string())
+}
+
+// Get the contents of the progress file from the path. When there is a cached copy the server is
+// asked for the file only if it changed since, and None means it did not.
+fn get_progress_file(conn: &HttpClient, base: &Url, path: &str, cached: Option<&SourceFile>) -> ProgressResult<Option<SourceFile>> {
+    let url = base.clone();
+    let url = url.join("file/")?;
+    let url = url.join(path)?;
+
+    let mut headers = Headers::new();
+    if let Some(cached) = cached {
+        if let Some(ref etag) = cached.etag {
+            headers.set_raw("If-None-Match", vec![etag.clone().into_bytes()]);
+        }
+        if let Some(ref last_modified) = cached.last_modified {
+            headers.set_raw("If-Modified-Since", vec![last_modified.clone().into_bytes()]);
+        }
+    }
+    let mut res = conn.send(Method::Get, url, path, headers)?;
+    if res.status == hStatusCode::NotModified {
+        return Ok(None);
+    }
+    let mut contents = Vec::new();
+    res.read_to_end(&mut contents).map_err(|err| upstream_error(hyper::Error::Io(err), path))?;
+    Ok(Some(SourceFile {
+        contents: Arc::new(contents),
+        codepage: codepage_from_headers(&res.headers),
+        etag: raw_header(&res.headers, "ETag"),
+        last_modified: raw_header(&res.headers, "Last-Modified"),
+    }))
+}
+
+// The size and modification time of the file from a HEAD request
+fn stat_progress_file(conn: &HttpClient, base: &Url, path: &str) -> ProgressResult<FileStat> {
+    let url = base.clone();
+    let url = url.join("file/")?;
+    let url = url.join(path)?;
+
+    let res = conn.send(Method::Head, url, path, Headers::new())?;
+    let size = res.headers.get::<ContentLength>().map_or(0, |&ContentLength(size)| size);
+    let modified = res.headers.get::<LastModified>()
+        .and_then(|&LastModified(date)| SystemTime::from(date).duration_since(UNIX_EPOCH).ok())
+        .map(|duration| duration.as_secs());
+    Ok(FileStat { path: path.to_string(), is_dir: false, size, modified })
+}
+
+// Find a progress file based upon the search query. Right now only works on program file names
+fn find_progress_file(conn: &HttpClient, base: &Url, path: &str) -> ProgressResult<Vec<String>> {
+    let url = base.clone();
+    let url = url.join("find/")?;
+    let url = url.join(path)?;
+
+    let mut res = conn.send(Method::Get, url, path, Headers::new())?;
+    let mut ret = String::new();
+    res.read_to_string(&mut ret).map_err(|err| upstream_error(hyper::Error::Io(err), path))?;
+    Ok(from_str(&ret)?)
+}
+
+// The entries of a directory, which the file server sends as a JSON array of `DirEntry`. The root
+// is list/ with nothing after it.
+fn list_progress_directory(conn: &HttpClient, base: &Url, path: &str) -> ProgressResult<Vec<DirEntry>> {
+    let url = base.clone();
+    let url = url.join("list/")?;
+    let url = url.join(path.trim_left_matches('/'))?;
+
+    let mut res = conn.send(Method::Get, url, path, Headers::new())?;
+    let mut ret = String::new();
+    res.read_to_string(&mut ret).map_err(|err| upstream_error(hyper::Error::Io(err), path))?;
+    Ok(from_str(&ret)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::Duration;
+    use url::Url;
+
+    use error::Error;
+    use source::SourceProvider;
+    use super::{FileServer, HttpClient};
+
+    // A file server that answers each connection with the next of `responses` and closes it. None
+    // never answers.
+    fn stub_server(responses: Vec<Option<&'static str>>) -> FileServer {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = Url::parse(&format!("http://{}/", listener.local_addr().unwrap())).unwrap();
+        thread::spawn(move || {
+            for response in responses {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut request = [0; 4096];
+                let _ = stream.read(&mut request);
+                match response {
+                    Some(response) => { let _ = stream.write_all(response.as_bytes()); },
+                    None => thread::sleep(Duration::from_millis(500)),
+                }
+            }
+        });
+        let client = HttpClient::new(Duration::from_millis(200), Duration::from_millis(200), 2);
+        FileServer::new(address, Arc::new(client))
+    }
+
+    const OK: &'static str = "HTTP/1.1 200 OK\r\nContent-Length: 8\r\nX-Codepage: 1252\r\nConnection: close\r\n\r\nRUN x.p.";
+    const NOT_FOUND: &'static str = "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n";
+    const LISTING: &'static str = "HTTP/1.1 200 OK\r\nContent-Length: 141\r\nConnection: close\r\n\r\n[{\"name\":\"inc\",\"path\":\"src/inc\",\"is_dir\":true,\"size\":0,\"modified\":null},{\"name\":\"x.w\",\"path\":\"src/x.w\",\"is_dir\":false,\"size\":8,\"modified\":1}]";
+    const UNAVAILABLE: &'static str = "HTTP/1.1 503 Service Unavailable\r\nContent-Length: 0\r\nConnection: close\r\n\r\n";
+
+    #[test]
+    fn test_file_server_errors() {
+        let file = stub_server(vec![Some(UNAVAILABLE), Some(OK)]).get_file("x.w").unwrap();
+        assert_eq!(b"RUN x.p.".to_vec(), *file.contents);
+        assert_eq!(Some("1252".to_string()), file.codepage);
+
+        let entries = stub_server(vec![Some(LISTING)]).list("src").unwrap();
+        assert_eq!(vec![("src/inc", true), ("src/x.w", false)], entries.iter().map(|entry| (&entry.path[..], entry.is_dir)).collect::<Vec<_>>());
+
+        match stub_server(vec![Some(NOT_FOUND)]).get_file("x.w") {
+            Err(Error::NotFound(_)) => {},
+            other => panic!("Expected NotFound, got {:?}", other.err()),
+        }
+        match stub_server(vec![Some(UNAVAILABLE), Some(UNAVAILABLE), Some(UNAVAILABLE)]).get_file("x.w") {
+            Err(Error::Upstream(_)) => {},
+            other => panic!("Expected Upstream, got {:?}", other.err()),
+        }
+        match stub_server(vec![None, None, None]).get_file("x.w") {
+            Err(Error::Timeout(_)) => {},
+            other => panic!("Expected Timeout, got {:?}", other.err()),
+        }
+    }
+}
@@ -0,0 +1,216 @@
+use std::ascii::AsciiExt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use git2::{ObjectType, Repository, Tree, TreeWalkMode, TreeWalkResult};
+
+use error::{ProgressResult, Error};
+use super::{DirEntry, FileStat, SourceFile, SourceProvider};
+
+/// A local git repository, bare or with a working tree, read at a branch, tag or commit. Nothing is
+/// checked out: files are read straight from the object database.
+pub struct GitRepository {
+    path: PathBuf,
+    rev: String,
+}
+
+impl GitRepository {
+    pub fn new<P: Into<PathBuf>, S: Into<String>>(path: P, rev: S) -> Self {
+        GitRepository { path: path.into(), rev: rev.into() }
+    }
+
+    // A repository is not Sync, so it is opened for each read
+    fn open(&self) -> ProgressResult<Repository> {
+        Ok(Repository::open(&self.path)?)
+    }
+
+    // A revision that does not exist is an error in the request, not a missing file, so the
+    // PROPATH does not move on to the next root
+    fn tree<'r>(&self, repo: &'r Repository) -> ProgressResult<Tree<'r>> {
+        let object = repo.revparse_single(&self.rev)
+            .map_err(|_| Error::BadRequest(format!("'{}' is not a revision of {}", self.rev, self.path.display())))?;
+        let tree_id = object.peel(ObjectType::Tree)?.id();
+        Ok(repo.find_tree(tree_id)?)
+    }
+
+    // When the revision was committed, which is the modification time of everything in it
+    fn commit_time(&self, repo: &Repository) -> Option<u64> {
+        repo.revparse_single(&self.rev).ok()
+            .and_then(|object| object.peel(ObjectType::Commit).ok())
+            .and_then(|object| repo.find_commit(object.id()).ok())
+            .map(|commit| commit.time().seconds() as u64)
+    }
+}
+
+// A submodule is a commit in the tree rather than a blob. It is shown as an empty directory.
+fn is_dir_kind(kind: Option<ObjectType>) -> bool {
+    kind == Some(ObjectType::Tree) || kind == Some(ObjectType::Commit)
+}
+
+fn tree_path(path: &str) -> String {
+    path.replace('\\', "/").trim_matches('/').to_string()
+}
+
+impl SourceProvider for GitRepository {
+    fn get_file(&self, path: &str) -> ProgressResult<SourceFile> {
+        let repo = self.open()?;
+        let tree = self.tree(&repo)?;
+        let entry = tree.get_path(Path::new(&tree_path(path)))
+            .map_err(|_| Error::NotFound(format!("'{}' does not exist at {}", path, self.rev)))?;
+        let blob = repo.find_blob(entry.id())
+            .map_err(|_| Error::new(format!("'{}' is not a file at {}", path, self.rev)))?;
+        Ok(SourceFile {
+            contents: Arc::new(blob.content().to_vec()),
+            codepage: None,
+            etag: Some(blob.id().to_string()),
+            last_modified: None,
+        })
+    }
+
+    // The blob id is the hash of the contents, so the file only changed if the id did
+    fn get_file_if_changed(&self, path: &str, cached: &SourceFile) -> ProgressResult<Option<SourceFile>> {
+        let repo = self.open()?;
+        let tree = self.tree(&repo)?;
+        let entry = tree.get_path(Path::new(&tree_path(path)))
+            .map_err(|_| Error::NotFound(format!("'{}' does not exist at {}", path, self.rev)))?;
+        if cached.etag.as_ref() == Some(&entry.id().to_string()) {
+            return Ok(None);
+        }
+        self.get_file(path).map(Some)
+    }
+
+    fn find(&self, query: &str) -> ProgressResult<Vec<String>> {
+        let repo = self.open()?;
+        let tree = self.tree(&repo)?;
+        let query = query.to_ascii_lowercase();
+        let mut results = Vec::new();
+        tree.walk(TreeWalkMode::PreOrder, |dir, entry| {
+            if entry.kind() == Some(ObjectType::Blob) {
+                if let Some(name) = entry.name() {
+                    if name.to_ascii_lowercase().contains(&query) {
+                        results.push(format!("{}{}", dir, name));
+                    }
+                }
+            }
+            TreeWalkResult::Ok
+        })?;
+        results.sort();
+        Ok(results)
+    }
+
+    fn list(&self, path: &str) -> ProgressResult<Vec<DirEntry>> {
+        let repo = self.open()?;
+        let root = self.tree(&repo)?;
+        let path = tree_path(path);
+        let tree = if path.is_empty() {
+            root
+        } else {
+            let entry = root.get_path(Path::new(&path))
+                .map_err(|_| Error::NotFound(format!("'{}' does not exist at {}", path, self.rev)))?;
+            if entry.kind() == Some(ObjectType::Commit) {
+                return Ok(Vec::new());
+            }
+            repo.find_tree(entry.id())
+                .map_err(|_| Error::new(format!("'{}' is not a directory at {}", path, self.rev)))?
+        };
+        let modified = self.commit_time(&repo);
+        let mut entries = Vec::new();
+        for entry in tree.iter() {
+            let name = entry.name().unwrap_or("").to_string();
+            let is_dir = is_dir_kind(entry.kind());
+            entries.push(DirEntry {
+                path: if path.is_empty() { name.clone() } else { format!("{}/{}", path, name) },
+                name,
+                is_dir,
+                size: if is_dir { 0 } else { repo.find_blob(entry.id())?.content().len() as u64 },
+                modified,
+            });
+        }
+        entries.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(entries)
+    }
+
+    fn stat(&self, path: &str) -> ProgressResult<FileStat> {
+        let repo = self.open()?;
+        let tree = self.tree(&repo)?;
+        let entry = tree.get_path(Path::new(&tree_path(path)))
+            .map_err(|_| Error::NotFound(format!("'{}' does not exist at {}", path, self.rev)))?;
+        let is_dir = is_dir_kind(entry.kind());
+        let size = if is_dir { 0 } else { repo.find_blob(entry.id())?.content().len() as u64 };
+        Ok(FileStat {
+            path: path.to_string(),
+            is_dir,
+            size,
+            modified: self.commit_time(&repo),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env::temp_dir;
+    use std::fs::{File, create_dir_all, remove_dir_all};
+    use std::io::Write;
+    use std::path::Path;
+
+    use git2::{Repository, Signature};
+
+    use error::Error;
+    use super::GitRepository;
+    use source::SourceProvider;
+
+    // Write the files into the working tree, commit them and tag the commit
+    fn commit(repo: &Repository, files: &[(&str, &str)], tag: &str) {
+        let workdir = repo.workdir().unwrap().to_path_buf();
+        let mut index = repo.index().unwrap();
+        for &(path, contents) in files {
+            create_dir_all(workdir.join(path).parent().unwrap()).unwrap();
+            File::create(workdir.join(path)).unwrap().write_all(contents.as_bytes()).unwrap();
+            index.add_path(Path::new(path)).unwrap();
+        }
+        index.write().unwrap();
+        let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+        let signature = Signature::now("test", "test@example.com").unwrap();
+        let parent = repo.head().ok().and_then(|head| head.target()).map(|id| repo.find_commit(id).unwrap());
+        let parents: Vec<_> = parent.iter().collect();
+        let id = repo.commit(Some("HEAD"), &signature, &signature, tag, &tree, &parents).unwrap();
+        repo.tag_lightweight(tag, &repo.find_object(id, None).unwrap(), false).unwrap();
+    }
+
+    #[test]
+    fn test_git_repository() {
+        let path = temp_dir().join("progress_server_test_git_repository");
+        let _ = remove_dir_all(&path);
+        let repo = Repository::init(&path).unwrap();
+        commit(&repo, &[("src/wWin.w", "DISPLAY 1."), ("src/inc/a.i", "")], "release-1");
+        commit(&repo, &[("src/wWin.w", "DISPLAY 2.")], "release-2");
+
+        let old = GitRepository::new(path.clone(), "release-1");
+        let head = GitRepository::new(path.clone(), "HEAD");
+        assert_eq!(b"DISPLAY 1.".to_vec(), *old.get_file("src/wWin.w").unwrap().contents);
+        assert_eq!(b"DISPLAY 2.".to_vec(), *head.get_file("src/wWin.w").unwrap().contents);
+        assert_eq!(vec!["src/wWin.w".to_string()], old.find("WWIN").unwrap());
+        let names: Vec<String> = head.list("src").unwrap().into_iter().map(|entry| entry.name).collect();
+        assert_eq!(vec!["inc".to_string(), "wWin.w".to_string()], names);
+        assert!(head.stat("src/inc").unwrap().is_dir);
+        // A submodule is an empty directory
+        let head_commit = repo.head().unwrap().peel_to_commit().unwrap();
+        let mut builder = repo.treebuilder(Some(&head_commit.tree().unwrap())).unwrap();
+        builder.insert("vendor", head_commit.id(), 0o160000).unwrap();
+        let tree = repo.find_tree(builder.write().unwrap()).unwrap();
+        let signature = Signature::now("test", "test@example.com").unwrap();
+        let id = repo.commit(None, &signature, &signature, "submodule", &tree, &[&head_commit]).unwrap();
+        let with_submodule = GitRepository::new(path.clone(), id.to_string());
+        let names: Vec<(String, bool)> = with_submodule.list("").unwrap().into_iter().map(|entry| (entry.name, entry.is_dir)).collect();
+        assert_eq!(vec![("src".to_string(), true), ("vendor".to_string(), true)], names);
+        assert!(with_submodule.stat("vendor").unwrap().is_dir);
+        assert!(with_submodule.list("vendor").unwrap().is_empty());
+
+        match GitRepository::new(path.clone(), "no-such-tag").get_file("src/wWin.w") {
+            Err(Error::BadRequest(_)) => {},
+            other => panic!("A missing revision gave {:?}", other.map(|file| file.contents)),
+        }
+
+        remove_dir_all(&path).unwrap();
+    }
+}
@@ -0,0 +1,164 @@
+use std::ascii::AsciiExt;
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::io::Read;
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, UNIX_EPOCH};
+
+use error::{ProgressResult, Error};
+use super::{DirEntry, FileStat, SourceFile, SourceProvider};
+
+/// A directory on this machine, like a checkout of the code
+pub struct LocalDirectory {
+    root: PathBuf,
+}
+
+impl LocalDirectory {
+    pub fn new<P: Into<PathBuf>>(root: P) -> Self {
+        LocalDirectory { root: root.into() }
+    }
+
+    // The path on disk. Paths that would leave the root are refused.
+    fn full_path(&self, path: &str) -> ProgressResult<PathBuf> {
+        let relative = path.replace('\\', "/");
+        let relative = Path::new(relative.trim_left_matches('/'));
+        for component in relative.components() {
+            match component {
+                Component::Normal(_) | Component::CurDir => {},
+                _ => return Err(Error::new(format!("'{}' is not a path under the source root", path))),
+            }
+        }
+        Ok(self.root.join(relative))
+    }
+
+    // The path relative to the root, with '/' separators
+    fn relative_path(&self, full_path: &Path) -> String {
+        let relative = full_path.strip_prefix(&self.root).unwrap_or(full_path);
+        let parts: Vec<String> = relative.components()
+            .map(|component| component.as_os_str().to_string_lossy().into_owned())
+            .collect();
+        parts.join("/")
+    }
+
+    // A missing file is NotFound, any other problem reading it is an io error
+    fn io_error(&self, path: &str, err: io::Error) -> Error {
+        if err.kind() == io::ErrorKind::NotFound {
+            Error::NotFound(format!("'{}' does not exist in {}", path, self.root.display()))
+        } else {
+            err.into()
+        }
+    }
+
+    // The size and modification time, which change whenever the file does
+    fn etag(&self, path: &str) -> ProgressResult<String> {
+        let metadata = fs::metadata(self.full_path(path)?).map_err(|err| self.io_error(path, err))?;
+        let modified = metadata.modified()?.duration_since(UNIX_EPOCH).unwrap_or(Duration::from_secs(0));
+        Ok(format!("{}-{}.{}", metadata.len(), modified.as_secs(), modified.subsec_nanos()))
+    }
+
+    fn find_in(&self, dir: &Path, query: &str, results: &mut Vec<String>) -> ProgressResult<()> {
+        for entry in fs::read_dir(dir)? {
+            let entry = entry?;
+            let path = entry.path();
+            if entry.file_type()?.is_dir() {
+                self.find_in(&path, query, results)?;
+            } else if entry.file_name().to_string_lossy().to_ascii_lowercase().contains(query) {
+                results.push(self.relative_path(&path));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl SourceProvider for LocalDirectory {
+    fn get_file(&self, path: &str) -> ProgressResult<SourceFile> {
+        let full_path = self.full_path(path)?;
+        let etag = self.etag(path)?;
+        let mut file = File::open(&full_path).map_err(|err| self.io_error(path, err))?;
+        let mut contents = Vec::new();
+        file.read_to_end(&mut contents)?;
+        Ok(SourceFile { contents: Arc::new(contents), codepage: None, etag: Some(etag), last_modified: None })
+    }
+
+    fn get_file_if_changed(&self, path: &str, cached: &SourceFile) -> ProgressResult<Option<SourceFile>> {
+        if cached.etag.as_ref() == Some(&self.etag(path)?) {
+            return Ok(None);
+        }
+        self.get_file(path).map(Some)
+    }
+
+    fn find(&self, query: &str) -> ProgressResult<Vec<String>> {
+        let mut results = Vec::new();
+        self.find_in(&self.root, &query.to_ascii_lowercase(), &mut results)?;
+        results.sort();
+        Ok(results)
+    }
+
+    fn list(&self, path: &str) -> ProgressResult<Vec<DirEntry>> {
+        // A file is not there as far as listing goes, like it is for the other backends
+        if !fs::metadata(self.full_path(path)?).map_err(|err| self.io_error(path, err))?.is_dir() {
+            return Err(Error::NotFound(format!("'{}' is not a directory in {}", path, self.root.display())));
+        }
+        let mut entries = Vec::new();
+        for entry in fs::read_dir(self.full_path(path)?).map_err(|err| self.io_error(path, err))? {
+            let entry = entry?;
+            let metadata = entry.metadata()?;
+            entries.push(DirEntry {
+                name: entry.file_name().to_string_lossy().into_owned(),
+                path: self.relative_path(&entry.path()),
+                is_dir: metadata.is_dir(),
+                size: if metadata.is_dir() { 0 } else { metadata.len() },
+                modified: modified_secs(&metadata),
+            });
+        }
+        entries.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(entries)
+    }
+
+    fn stat(&self, path: &str) -> ProgressResult<FileStat> {
+        let metadata = fs::metadata(self.full_path(path)?).map_err(|err| self.io_error(path, err))?;
+        Ok(FileStat {
+            path: path.to_string(),
+            is_dir: metadata.is_dir(),
+            size: metadata.len(),
+            modified: modified_secs(&metadata),
+        })
+    }
+}
+
+fn modified_secs(metadata: &fs::Metadata) -> Option<u64> {
+    metadata.modified().ok()
+        .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+        .map(|duration| duration.as_secs())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env::temp_dir;
+    use std::fs::{File, create_dir_all, remove_dir_all};
+    use std::io::Write;
+
+    use super::LocalDirectory;
+    use source::SourceProvider;
+
+    #[test]
+    fn test_local_directory() {
+        let root = temp_dir().join("progress_server_test_local_directory");
+        let _ = remove_dir_all(&root);
+        create_dir_all(root.join("src/inc")).unwrap();
+        File::create(root.join("src/Customer.w")).unwrap().write_all(b"RUN x.p.").unwrap();
+        File::create(root.join("src/inc/customer.i")).unwrap().write_all(b"").unwrap();
+
+        let source = LocalDirectory::new(root.clone());
+        assert_eq!(b"RUN x.p.".to_vec(), *source.get_file("src/Customer.w").unwrap().contents);
+        assert_eq!(vec!["src/Customer.w".to_string(), "src/inc/customer.i".to_string()], source.find("CUSTOMER").unwrap());
+        let entries: Vec<(String, bool, u64)> = source.list("src").unwrap().into_iter().map(|entry| (entry.name, entry.is_dir, entry.size)).collect();
+        assert_eq!(vec![("Customer.w".to_string(), false, 8), ("inc".to_string(), true, 0)], entries);
+        assert_eq!(8, source.stat("src/Customer.w").unwrap().size);
+        assert!(source.get_file("../etc/passwd").is_err());
+
+        remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,293 @@
+use std::ascii::AsciiExt;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use rocket::Outcome;
+use rocket::request::{self, FromRequest, Request};
+use url::Url;
+use url::form_urlencoded;
+
+use error::{ProgressResult, Error, add_message};
+use config::{get_integer, get_string, get_string_list};
+use lru::CacheStats;
+
+mod archive;
+mod cache;
+mod file_server;
+mod git;
+mod local;
+mod propath;
+
+pub use self::archive::Archive;
+pub use self::cache::{CachedSource, ContentCache};
+pub use self::file_server::{FileServer, HttpClient};
+pub use self::git::GitRepository;
+pub use self::local::LocalDirectory;
+pub use self::propath::{Propath, Resolution};
+
+const DEFAULT_PARALLELISM: usize = 8;
+const DEFAULT_CONNECT_TIMEOUT_MS: i64 = 2000;
+const DEFAULT_READ_TIMEOUT_MS: i64 = 10000;
+const DEFAULT_RETRIES: i64 = 2;
+const DEFAULT_CONTENT_CACHE_BYTES: i64 = 64 * 1024 * 1024;
+
+/// The raw contents of a source file, and the codepage the backend says it is in
+#[derive(Debug, Clone, PartialEq)]
+pub struct SourceFile {
+    /// Shared, so a file from the content cache is not copied
+    pub contents: Arc<Vec<u8>>,
+    pub codepage: Option<String>,
+    /// Tells this version of the file from others: the ETag from a file server, or whatever the
+    /// backend can cheaply check again, like the modification time of a local file
+    pub etag: Option<String>,
+    /// The Last-Modified header from a file server
+    pub last_modified: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct DirEntry {
+    pub name: String,
+    pub path: String,
+    pub is_dir: bool,
+    /// 0 for directories
+    pub size: u64,
+    /// Seconds since the unix epoch, when the backend knows it
+    pub modified: Option<u64>,
+}
+
+/// The kinds of OpenEdge source files, told apart by their extension
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileKind {
+    /// .p
+    Procedure,
+    /// .w, an AppBuilder window
+    Window,
+    /// .i
+    Include,
+    /// .cls
+    Class,
+    /// .df, a schema definition
+    Schema,
+}
+
+impl FileKind {
+    pub fn of(path: &str) -> Option<FileKind> {
+        let extension = match path.rfind('.') {
+            Some(dot) if !path[dot..].contains('/') => path[dot + 1..].to_ascii_lowercase(),
+            _ => return None,
+        };
+        match &extension[..] {
+            "p" => Some(FileKind::Procedure),
+            "w" => Some(FileKind::Window),
+            "i" => Some(FileKind::Include),
+            "cls" => Some(FileKind::Class),
+            "df" => Some(FileKind::Schema),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct FileStat {
+    pub path: String,
+    pub is_dir: bool,
+    pub size: u64,
+    /// Seconds since the unix epoch, when the backend knows it
+    pub modified: Option<u64>,
+}
+
+/// Somewhere the sources can be read from. Paths are relative to the root of the backend and use
+/// '/' as the separator.
+pub trait SourceProvider: Send + Sync {
+    fn get_file(&self, path: &str) -> ProgressResult<SourceFile>;
+
+    /// Read the file only when it changed since `cached` was read from this backend. None means
+    /// `cached` is still current.
+    fn get_file_if_changed(&self, path: &str, cached: &SourceFile) -> ProgressResult<Option<SourceFile>> {
+        let _ = cached;
+        self.get_file(path).map(Some)
+    }
+
+    /// Find files by name. Right now only works on file names.
+    fn find(&self, query: &str) -> ProgressResult<Vec<String>>;
+
+    fn list(&self, path: &str) -> ProgressResult<Vec<DirEntry>>;
+
+    fn stat(&self, path: &str) -> ProgressResult<FileStat>;
+}
+
+/// The git revision a request asked for with `?rev=`, such as a branch, tag or commit. It is used
+/// for every git repository on the PROPATH instead of the revision the repository was set up with.
+pub struct Revision(pub Option<String>);
+
+impl Revision {
+    pub fn get(&self) -> Option<&str> {
+        self.0.as_ref().map(String::as_str)
+    }
+}
+
+impl<'a, 'r> FromRequest<'a, 'r> for Revision {
+    type Error = ();
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<Self, ()> {
+        let rev = request.uri().query().and_then(|query| {
+            form_urlencoded::parse(query.as_bytes())
+                .find(|&(ref key, _)| key == "rev")
+                .map(|(_, value)| value.into_owned())
+        });
+        Outcome::Success(Revision(rev))
+    }
+}
+
+/// A backend from one entry of the PROPATH: an http address is a file server,
+/// `git:<repository>#<rev>` is a git repository at a revision (HEAD when there is no '#'), a .zip,
+/// .tar, .tar.gz or .tgz file is an archive, and anything else is a local directory. `rev` replaces
+/// the revision of a git repository.
+pub fn source_from_spec(spec: &str, rev: Option<&str>, client: &Arc<HttpClient>) -> ProgressResult<Arc<SourceProvider>> {
+    if spec.starts_with("https://") {
+        Err(Error::new(format!("'{}' is an https address, but file servers can only be reached over http", spec)))
+    } else if spec.starts_with("http://") {
+        Ok(Arc::new(FileServer::new(Url::parse(spec)?, client.clone())))
+    } else if spec.starts_with("git:") {
+        let mut parts = spec["git:".len()..].splitn(2, '#');
+        let path = parts.next().unwrap_or("");
+        let default_rev = parts.next().unwrap_or("HEAD");
+        Ok(Arc::new(GitRepository::new(path, rev.unwrap_or(default_rev))))
+    } else if Archive::is_archive(spec) {
+        Ok(Arc::new(Archive::new(spec)?))
+    } else {
+        Ok(Arc::new(LocalDirectory::new(spec)))
+    }
+}
+
+// The files of each root at each revision are cached apart
+fn cache_key_prefix(spec: &str, rev: Option<&str>) -> String {
+    format!("{}\n{}", spec, rev.unwrap_or(""))
+}
+
+/// The sources as set up in Rocket.toml, kept in managed state so the config is read once, every
+/// file server request goes through the same pool of keep-alive connections and fetched files are
+/// cached across requests. Clones share the connections and the cache.
+#[derive(Clone)]
+pub struct Sources {
+    specs: Vec<String>,
+    /// The backend of each spec, kept so an archive reads its entries once. Git repositories are
+    /// None, since they are opened at the revision each request asks for.
+    backends: Vec<Option<Arc<SourceProvider>>>,
+    client: Arc<HttpClient>,
+    cache: Arc<ContentCache>,
+    parallelism: usize,
+}
+
+impl Sources {
+    pub fn new(specs: Vec<String>, parallelism: usize, client: HttpClient, cache: ContentCache) -> ProgressResult<Self> {
+        let client = Arc::new(client);
+        let mut backends = Vec::with_capacity(specs.len());
+        for spec in &specs {
+            backends.push(if spec.starts_with("git:") { None } else { Some(source_from_spec(spec, None, &client)?) });
+        }
+        Ok(Sources { specs, backends, client, cache: Arc::new(cache), parallelism })
+    }
+
+    /// The `propath` in Rocket.toml. Without one the PROPATH is just the backend chosen by
+    /// `source`: "http" (the default) for the file server at `file_server_address`, "local" for the
+    /// directory at `source_root`, "git" for the repository at `source_root` at `source_rev`, or
+    /// "archive" for the zip or tar file at `source_root`. `fetch_parallelism` is how many files
+    /// are read at once when a search has to read many. File servers are given
+    /// `file_server_connect_timeout_ms` to connect and `file_server_read_timeout_ms` to answer, and
+    /// failed reads are tried `file_server_retries` more times. Up to `content_cache_bytes` of
+    /// fetched files are cached.
+    pub fn from_config() -> ProgressResult<Self> {
+        let parallelism = get_integer("fetch_parallelism")?.unwrap_or(DEFAULT_PARALLELISM as i64);
+        if parallelism < 1 {
+            return Err(Error::new("fetch_parallelism has to be at least 1"));
+        }
+        let connect_timeout = get_integer("file_server_connect_timeout_ms")?.unwrap_or(DEFAULT_CONNECT_TIMEOUT_MS);
+        let read_timeout = get_integer("file_server_read_timeout_ms")?.unwrap_or(DEFAULT_READ_TIMEOUT_MS);
+        let retries = get_integer("file_server_retries")?.unwrap_or(DEFAULT_RETRIES);
+        if connect_timeout < 1 || read_timeout < 1 || retries < 0 {
+            return Err(Error::new("The file server timeouts have to be positive and the retries can not be negative"));
+        }
+        let client = HttpClient::new(Duration::from_millis(connect_timeout as u64), Duration::from_millis(read_timeout as u64), retries as u32);
+        let cache_bytes = get_integer("content_cache_bytes")?.unwrap_or(DEFAULT_CONTENT_CACHE_BYTES);
+        if cache_bytes < 0 {
+            return Err(Error::new("content_cache_bytes can not be negative"));
+        }
+        let cache = ContentCache::new(cache_bytes as usize);
+        if let Some(specs) = get_string_list("propath")? {
+            return Sources::new(specs, parallelism as usize, client, cache);
+        }
+        let kind = get_string("source")?.unwrap_or("http".to_string());
+        let spec = match &kind[..] {
+            "http" => {
+                let address = get_string("file_server_address")?.ok_or(Error::new("No file_server_address in config file"))?;
+                Url::parse(&address).map_err(add_message(format!("file_server_address: '{}' in Rocket.tml is not a valid address", address)))?;
+                address
+            },
+            "local" | "archive" => get_string("source_root")?.ok_or(Error::new("No source_root in config file"))?,
+            "git" => {
+                let root = get_string("source_root")?.ok_or(Error::new("No source_root in config file"))?;
+                let default_rev = get_string("source_rev")?.unwrap_or("HEAD".to_string());
+                format!("git:{}#{}", root, default_rev)
+            },
+            _ => return Err(Error::new(format!("Unknown source '{}' in Rocket.toml", kind))),
+        };
+        Sources::new(vec![spec], parallelism as usize, client, cache)
+    }
+
+    /// How many files to read at once
+    pub fn parallelism(&self) -> usize {
+        self.parallelism
+    }
+
+    pub fn cache_stats(&self) -> CacheStats {
+        self.cache.stats()
+    }
+
+    /// The local directories on the PROPATH
+    pub fn local_roots(&self) -> Vec<PathBuf> {
+        self.specs.iter()
+            .filter(|spec| !spec.starts_with("http://") && !spec.starts_with("https://") && !spec.starts_with("git:") && !Archive::is_archive(spec))
+            .map(PathBuf::from)
+            .collect()
+    }
+
+    /// Forget the cached contents of `path` in every root, for when it is known to have changed
+    pub fn invalidate(&self, path: &str) {
+        for spec in &self.specs {
+            self.cache.invalidate(&cache_key_prefix(spec, None), path);
+        }
+    }
+
+    pub fn propath(&self, rev: Option<&str>) -> ProgressResult<Propath> {
+        let mut roots = Vec::with_capacity(self.specs.len());
+        for (spec, backend) in self.specs.iter().zip(&self.backends) {
+            let source = match *backend {
+                Some(ref backend) => backend.clone(),
+                None => source_from_spec(spec, rev, &self.client)?,
+            };
+            let key_prefix = cache_key_prefix(spec, rev);
+            let cached: Box<SourceProvider> = Box::new(CachedSource::new(key_prefix, source, self.cache.clone()));
+            roots.push((spec.clone(), cached));
+        }
+        Ok(Propath::new(roots))
+    }
+
+    pub fn get_procedure_contents(&self, procedure: &str, rev: Option<&str>) -> ProgressResult<SourceFile> {
+        self.propath(rev)?.get_file(procedure)
+    }
+
+    pub fn find_procedure(&self, procedure: &str, rev: Option<&str>) -> ProgressResult<Vec<String>> {
+        self.propath(rev)?.find(procedure)
+    }
+
+    pub fn resolve_procedure(&self, procedure: &str, rev: Option<&str>) -> ProgressResult<Resolution> {
+        self.propath(rev)?.resolve(procedure)
+    }
+
+    /// The directory `path` as seen through the PROPATH, with every root's entries merged
+    pub fn list_directory(&self, path: &str, rev: Option<&str>) -> ProgressResult<Vec<DirEntry>> {
+        self.propath(rev)?.list(path)
+    }
+}
@@ -0,0 +1,160 @@
+use error::{ProgressResult, Error};
+use super::{DirEntry, FileStat, SourceFile, SourceProvider};
+
+/// Where a name was found on the PROPATH
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Resolution {
+    pub name: String,
+    /// The root the name resolves to, which is the first one that has it
+    pub root: String,
+    /// Later roots that also have the name and so are never used for it
+    pub shadowed: Vec<String>,
+}
+
+/// An ordered list of roots searched like the OpenEdge PROPATH: a name is read from the first root
+/// that has it. The roots can be different kinds of backend.
+pub struct Propath {
+    roots: Vec<(String, Box<SourceProvider>)>,
+}
+
+impl Propath {
+    pub fn new(roots: Vec<(String, Box<SourceProvider>)>) -> Self {
+        Propath { roots }
+    }
+
+    pub fn roots(&self) -> Vec<&str> {
+        self.roots.iter().map(|&(ref spec, _)| &spec[..]).collect()
+    }
+
+    /// Find the root the name resolves to and every root it shadows
+    pub fn resolve(&self, name: &str) -> ProgressResult<Resolution> {
+        let mut found = Vec::new();
+        for &(ref spec, ref source) in &self.roots {
+            match source.stat(name) {
+                Ok(stat) => if !stat.is_dir {
+                    found.push(spec.clone());
+                },
+                Err(Error::NotFound(_)) => {},
+                Err(err) => return Err(err),
+            }
+        }
+        if found.is_empty() {
+            return Err(not_found(name));
+        }
+        let root = found.remove(0);
+        Ok(Resolution {
+            name: name.to_string(),
+            root,
+            shadowed: found,
+        })
+    }
+}
+
+fn not_found(path: &str) -> Error {
+    Error::NotFound(format!("'{}' is not on the PROPATH", path))
+}
+
+// Only a file that is missing moves on to the next root. Anything else, like a file server that is
+// down, could hide the file that should be used or leave results out, so it is an error.
+impl SourceProvider for Propath {
+    fn get_file(&self, path: &str) -> ProgressResult<SourceFile> {
+        for &(_, ref source) in &self.roots {
+            match source.get_file(path) {
+                Err(Error::NotFound(_)) => {},
+                result => return result,
+            }
+        }
+        Err(not_found(path))
+    }
+
+    // The matches from every root, without the ones shadowed by an earlier root
+    fn find(&self, query: &str) -> ProgressResult<Vec<String>> {
+        let mut results: Vec<String> = Vec::new();
+        for &(_, ref source) in &self.roots {
+            let found = match source.find(query) {
+                Ok(found) => found,
+                Err(Error::NotFound(_)) => continue,
+                Err(err) => return Err(err),
+            };
+            for path in found {
+                if !results.contains(&path) {
+                    results.push(path);
+                }
+            }
+        }
+        Ok(results)
+    }
+
+    fn list(&self, path: &str) -> ProgressResult<Vec<DirEntry>> {
+        let mut entries: Vec<DirEntry> = Vec::new();
+        let mut listed = false;
+        for &(_, ref source) in &self.roots {
+            let root_entries = match source.list(path) {
+                Ok(root_entries) => root_entries,
+                Err(Error::NotFound(_)) => continue,
+                Err(err) => return Err(err),
+            };
+            listed = true;
+            for entry in root_entries {
+                if !entries.iter().any(|existing| existing.name == entry.name) {
+                    entries.push(entry);
+                }
+            }
+        }
+        if !listed {
+            return Err(Error::NotFound(format!("'{}' is not a directory on the PROPATH", path)));
+        }
+        entries.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(entries)
+    }
+
+    fn stat(&self, path: &str) -> ProgressResult<FileStat> {
+        for &(_, ref source) in &self.roots {
+            match source.stat(path) {
+                Err(Error::NotFound(_)) => {},
+                result => return result,
+            }
+        }
+        Err(not_found(path))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env::temp_dir;
+    use std::fs::{File, create_dir_all, remove_dir_all};
+    use std::time::Duration;
+
+    use super::Resolution;
+    use source::{ContentCache, HttpClient, Sources, SourceProvider};
+
+    #[test]
+    fn test_resolve() {
+        let base = temp_dir().join("progress_server_test_resolve");
+        let _ = remove_dir_all(&base);
+        for &(root, file) in &[("custom", "inc/bar.i"), ("app", "inc/bar.i"), ("app", "foo.p")] {
+            let path = base.join(root).join(file);
+            create_dir_all(path.parent().unwrap()).unwrap();
+            File::create(path).unwrap();
+        }
+        let custom = base.join("custom").to_string_lossy().into_owned();
+        let app = base.join("app").to_string_lossy().into_owned();
+        let propath = Sources::new(vec![custom.clone(), app.clone()], 1, HttpClient::new(Duration::from_secs(1), Duration::from_secs(1), 0), ContentCache::new(0)).unwrap().propath(None).unwrap();
+
+        assert_eq!(Resolution { name: "inc/bar.i".to_string(), root: custom.clone(), shadowed: vec![app.clone()] },
+                   propath.resolve("inc/bar.i").unwrap());
+        assert_eq!(app, propath.resolve("foo.p").unwrap().root);
+        assert!(propath.resolve("missing.p").is_err());
+        assert_eq!(vec!["inc/bar.i".to_string()], propath.find("bar").unwrap());
+        assert_eq!(1, propath.list("inc").unwrap().len());
+        assert!(propath.list("nowhere").is_err());
+
+        // A root that can not be reached fails a search or listing, rather than leaving its files out
+        let propath = Sources::new(vec![custom.clone(), "http://127.0.0.1:1/".to_string()], 1, HttpClient::new(Duration::from_secs(1), Duration::from_secs(1), 0), ContentCache::new(0)).unwrap().propath(None).unwrap();
+        assert!(propath.find("bar").is_err());
+        assert!(Sources::new(vec!["https://127.0.0.1:1/".to_string()], 1, HttpClient::new(Duration::from_secs(1), Duration::from_secs(1), 0), ContentCache::new(0)).is_err());
+        assert!(propath.list("inc").is_err());
+
+        remove_dir_all(&base).unwrap();
+    }
+}
@@ -1,6 +1,10 @@
-pub fn u8_ref_to_string(input: &[u8]) -> String{
-    String::from_utf8_lossy(input).into_owned()
-}
+use std::ascii::AsciiExt;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crossbeam;
+use rocket::Outcome;
+use rocket::request::{self, FromRequest, Request};
 
 pub fn restrict_string(to_restrict: &str) -> String {
     if to_restrict.len() < 20 {
@@ -13,3 +17,121 @@ pub fn restrict_string(to_restrict: &str) -> String {
         return result;
     }
 }
+
+/// Map `f` over the items on up to `limit` threads at once. The results are in the order of the
+/// items.
+pub fn parallel_map<T, R, F>(items: &[T], limit: usize, f: F) -> Vec<R>
+    where T: Sync, R: Send, F: Fn(&T) -> R + Sync {
+    let next = AtomicUsize::new(0);
+    let results: Mutex<Vec<Option<R>>> = Mutex::new(items.iter().map(|_| None).collect());
+    crossbeam::scope(|scope| {
+        for _ in 0..limit.max(1).min(items.len()) {
+            scope.spawn(|| {
+                loop {
+                    let index = next.fetch_add(1, Ordering::SeqCst);
+                    if index >= items.len() {
+                        break;
+                    }
+                    let result = f(&items[index]);
+                    results.lock().unwrap()[index] = Some(result);
+                }
+            });
+        }
+    });
+    results.into_inner().unwrap().into_iter().map(|result| result.unwrap()).collect()
+}
+
+/// A flag from the query of a request, like "true" or "no"
+pub fn parse_flag(value: &str) -> Option<bool> {
+    match &value.to_ascii_lowercase()[..] {
+        "true" | "1" | "yes" => Some(true),
+        "false" | "0" | "no" => Some(false),
+        _ => None,
+    }
+}
+
+/// The query string of a request. A route parses it itself, so that a bad parameter is answered
+/// with its JSON error instead of Rocket's HTML 400 page.
+pub struct QueryString(pub String);
+
+impl<'a, 'r> FromRequest<'a, 'r> for QueryString {
+    type Error = ();
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<Self, ()> {
+        Outcome::Success(QueryString(request.uri().query().unwrap_or("").to_string()))
+    }
+}
+
+/// Whether `text` matches `pattern`, where '*' is any run of characters and '?' is any one
+/// character, ignoring ASCII case
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().map(|c| c.to_ascii_lowercase()).collect();
+    let text: Vec<char> = text.chars().map(|c| c.to_ascii_lowercase()).collect();
+    let (mut p, mut t) = (0, 0);
+    // Where the last '*' was, and how much of the text it took so far
+    let mut star = None;
+    while t < text.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == text[t]) {
+            p += 1;
+            t += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            star = Some((p, t));
+            p += 1;
+        } else if let Some((star_p, star_t)) = star {
+            p = star_p + 1;
+            t = star_t + 1;
+            star = Some((star_p, star_t + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// Escape the characters that mean something to SQL LIKE, with a backslash
+pub fn escape_like(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if c == '%' || c == '_' || c == '\\' {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// Escape text for an XML attribute or element
+pub fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// The SQL LIKE pattern for a `glob_match` pattern
+pub fn glob_to_like(pattern: &str) -> String {
+    escape_like(pattern).replace('*', "%").replace('?', "_")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{glob_match, glob_to_like, parallel_map};
+
+    #[test]
+    fn test_parallel_map() {
+        let items: Vec<usize> = (0..100).collect();
+        let doubled: Vec<usize> = items.iter().map(|item| item * 2).collect();
+        assert_eq!(doubled, parallel_map(&items, 7, |item| item * 2));
+        assert_eq!(Vec::<usize>::new(), parallel_map(&[], 7, |item: &usize| *item));
+    }
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match("init*", "Initialize-Customer"));
+        assert!(glob_match("*cust?mer", "initialize-customer"));
+        assert!(glob_match("a*b*c", "aXbYbZc"));
+        assert!(!glob_match("init", "initialize"));
+        assert!(!glob_match("*.p", "a.w"));
+        assert_eq!("%a\\_b%", glob_to_like("*a_b*"));
+    }
+}
@@ -0,0 +1,88 @@
+use std::fmt::Write;
+
+use parser::{FrameLayout, WidgetKind, WidgetLayout, WindowLayout};
+use util::escape_xml;
+
+// The default size of a character unit in a session, which is what the AppBuilder lays out with
+const PIXELS_PER_COLUMN: f32 = 5.0;
+const PIXELS_PER_ROW: f32 = 21.0;
+const FONT_SIZE: f32 = 9.0;
+
+fn x(column: f32) -> f32 {
+    (column - 1.0) * PIXELS_PER_COLUMN
+}
+
+fn y(row: f32) -> f32 {
+    (row - 1.0) * PIXELS_PER_ROW
+}
+
+// The label a widget is drawn with. Ampersands mark the mnemonic in a label and are not shown.
+fn label_text(widget: &WidgetLayout) -> String {
+    escape_xml(&widget.label.clone().unwrap_or(widget.name.clone()).replace("&", ""))
+}
+
+/// Render an approximation of the window as an SVG image. This is only a mockup: fonts, colors and
+/// most widget attributes are ignored.
+pub fn render_svg(layout: &WindowLayout) -> String {
+    let width = layout.width * PIXELS_PER_COLUMN;
+    let height = layout.height * PIXELS_PER_ROW;
+
+    let mut svg = String::new();
+    let _ = write!(svg, "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{}\" height=\"{}\" viewBox=\"0 0 {} {}\" font-family=\"sans-serif\" font-size=\"{}\">\n",
+                   width, height, width, height, FONT_SIZE);
+    let _ = write!(svg, "<rect x=\"0\" y=\"0\" width=\"{}\" height=\"{}\" fill=\"#f0f0f0\" stroke=\"#808080\"/>\n", width, height);
+    for frame in &layout.frames {
+        render_frame(&mut svg, frame);
+    }
+    svg.push_str("</svg>\n");
+    svg
+}
+
+fn render_frame(svg: &mut String, frame: &FrameLayout) {
+    let (width, height) = frame.extent();
+    let _ = write!(svg, "<g transform=\"translate({},{})\">\n", x(frame.column), y(frame.row));
+    let _ = write!(svg, "<rect x=\"0\" y=\"0\" width=\"{}\" height=\"{}\" fill=\"none\" stroke=\"#c0c0c0\" stroke-dasharray=\"4,2\"/>\n",
+                   width * PIXELS_PER_COLUMN, height * PIXELS_PER_ROW);
+    if let Some(ref title) = frame.title {
+        let _ = write!(svg, "<text x=\"4\" y=\"{}\" font-weight=\"bold\">{}</text>\n", FONT_SIZE + 2.0, escape_xml(title));
+    }
+    for widget in &frame.widgets {
+        render_widget(svg, widget);
+    }
+    svg.push_str("</g>\n");
+}
+
+fn render_widget(svg: &mut String, widget: &WidgetLayout) {
+    let left = x(widget.column);
+    let top = y(widget.row);
+    let width = widget.width * PIXELS_PER_COLUMN;
+    let height = widget.height * PIXELS_PER_ROW;
+    let text_y = top + height / 2.0 + FONT_SIZE / 3.0;
+    match widget.kind {
+        WidgetKind::Button => {
+            let _ = write!(svg, "<rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" rx=\"2\" fill=\"#e0e0e0\" stroke=\"#606060\"/>\n", left, top, width, height);
+            let _ = write!(svg, "<text x=\"{}\" y=\"{}\" text-anchor=\"middle\">{}</text>\n", left + width / 2.0, text_y, label_text(widget));
+        },
+        WidgetKind::FillIn | WidgetKind::ComboBox | WidgetKind::Editor => {
+            let _ = write!(svg, "<rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" fill=\"#ffffff\" stroke=\"#606060\"/>\n", left, top, width, height);
+            if widget.label.is_some() {
+                let _ = write!(svg, "<text x=\"{}\" y=\"{}\" text-anchor=\"end\">{}:</text>\n", left - 2.0, text_y, label_text(widget));
+            }
+        },
+        WidgetKind::Toggle => {
+            let _ = write!(svg, "<rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" fill=\"#ffffff\" stroke=\"#606060\"/>\n", left, top + 2.0, PIXELS_PER_ROW - 4.0, height - 4.0);
+            let _ = write!(svg, "<text x=\"{}\" y=\"{}\">{}</text>\n", left + PIXELS_PER_ROW, text_y, label_text(widget));
+        },
+        WidgetKind::Browse => {
+            let _ = write!(svg, "<rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" fill=\"#ffffff\" stroke=\"#606060\"/>\n", left, top, width, height);
+            let _ = write!(svg, "<rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" fill=\"#d8d8d8\" stroke=\"#606060\"/>\n", left, top, width, PIXELS_PER_ROW * 0.8);
+            let _ = write!(svg, "<text x=\"{}\" y=\"{}\">{}</text>\n", left + 4.0, top + FONT_SIZE + 3.0, escape_xml(&widget.name));
+        },
+        WidgetKind::Text => {
+            let _ = write!(svg, "<text x=\"{}\" y=\"{}\">{}</text>\n", left, text_y, escape_xml(&widget.name));
+        },
+        WidgetKind::Rectangle | WidgetKind::Other => {
+            let _ = write!(svg, "<rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" fill=\"none\" stroke=\"#606060\"/>\n", left, top, width, height);
+        },
+    }
+}
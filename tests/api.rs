@@ -0,0 +1,265 @@
+//! Runs the progress server against the file server stand-in and checks every /api route end to
+//! end. Both are started as their own processes, each from a directory with its own Rocket.toml.
+
+extern crate hyper;
+extern crate serde_json;
+
+use std::env;
+use std::fs::{File, create_dir_all, remove_dir_all};
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+use hyper::Client;
+use hyper::status::StatusCode;
+use serde_json::Value;
+
+const WINDOW: &'static str = "&ANALYZE-SUSPEND _VERSION-NUMBER UIB_v9r12 GUI\n\
+&ANALYZE-RESUME\n\
+&ANALYZE-SUSPEND _UIB-CODE-BLOCK _CUSTOM _DEFINITIONS wWin\n\
+{inc/customer.i}\n\
+DEFINE BUTTON btnOk LABEL \"OK\" SIZE 15 BY 1.14.\n\
+DEFINE FRAME fMain\n    btnOk AT ROW 4 COL 2\n    WITH 1 DOWN NO-BOX SIDE-LABELS AT COL 1 ROW 1 SIZE 80 BY 10.\n\
+&ANALYZE-RESUME\n\
+&ANALYZE-SUSPEND _UIB-CODE-BLOCK _PROCEDURE initialize-customer wWin\n\
+PROCEDURE initialize-customer:\n    RUN customer.p.\nEND PROCEDURE.\n\
+&ANALYZE-RESUME\n";
+
+// A running server, stopped when it is dropped
+struct Server {
+    child: Child,
+    port: u16,
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+// Where cargo put the binaries: the integration tests are in target/<profile>/deps
+fn binary(name: &str) -> PathBuf {
+    let mut path = env::current_exe().unwrap();
+    path.pop();
+    if path.ends_with("deps") {
+        path.pop();
+    }
+    path.join(name)
+}
+
+fn free_port() -> u16 {
+    TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port()
+}
+
+// Start the binary in `dir` with a Rocket.toml holding `extras`, and wait until it takes connections
+fn start(name: &str, dir: &Path, args: &[&str], extras: &str) -> Server {
+    let port = free_port();
+    create_dir_all(dir).unwrap();
+    File::create(dir.join("Rocket.toml")).unwrap()
+        .write_all(format!("[development]\naddress = \"127.0.0.1\"\nport = {}\n{}", port, extras).as_bytes())
+        .unwrap();
+    let child = Command::new(binary(name))
+        .args(args)
+        .current_dir(dir)
+        .env_remove("ROCKET_ENV")
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+    let server = Server { child, port };
+    let started = Instant::now();
+    while TcpStream::connect(("127.0.0.1", port)).is_err() {
+        assert!(started.elapsed() < Duration::from_secs(30), "{} did not start", name);
+        sleep(Duration::from_millis(50));
+    }
+    server
+}
+
+fn get(server: &Server, path: &str) -> (StatusCode, String) {
+    let mut res = Client::new().get(&format!("http://127.0.0.1:{}{}", server.port, path)).send().unwrap();
+    let mut body = String::new();
+    res.read_to_string(&mut body).unwrap();
+    (res.status, body)
+}
+
+fn post_json(server: &Server, path: &str) -> Value {
+    let mut res = Client::new().post(&format!("http://127.0.0.1:{}{}", server.port, path)).send().unwrap();
+    let mut body = String::new();
+    res.read_to_string(&mut body).unwrap();
+    assert_eq!(StatusCode::Ok, res.status, "{}: {}", path, body);
+    serde_json::from_str(&body).unwrap()
+}
+
+fn get_json(server: &Server, path: &str) -> Value {
+    let (status, body) = get(server, path);
+    assert_eq!(StatusCode::Ok, status, "{}: {}", path, body);
+    serde_json::from_str(&body).unwrap()
+}
+
+#[test]
+fn test_api() {
+    let root = env::temp_dir().join("progress_server_test_api");
+    let _ = remove_dir_all(&root);
+    let sources = root.join("sources");
+    create_dir_all(sources.join("inc")).unwrap();
+    File::create(sources.join("wWin.w")).unwrap().write_all(WINDOW.as_bytes()).unwrap();
+    File::create(sources.join("customer.p")).unwrap().write_all(b"DEFINE VARIABLE i AS INTEGER.\nDISPLAY i.\n").unwrap();
+    File::create(sources.join("inc/customer.i")).unwrap().write_all(b"DEFINE VARIABLE j AS INTEGER.\n").unwrap();
+
+    let file_server = start("file_server", &root.join("file_server"), &[sources.to_str().unwrap()], "");
+    let server = start("progress_server", &root.join("progress_server"), &[],
+                       &format!("file_server_address = \"http://127.0.0.1:{}/\"\n", file_server.port));
+
+    let procedure = get_json(&server, "/api/procedure/wWin.w");
+    assert_eq!("UTF-8", procedure["encoding"]);
+    assert_eq!(3, procedure["sections"].as_array().unwrap().len());
+    assert_eq!(json_strings(&["inc/customer.i"]), procedure["file_references"]);
+
+    let parse = get_json(&server, "/api/procedure_parse/customer.p");
+    assert_eq!(2, parse["parse"]["statements"].as_array().unwrap().len());
+
+    let sections = get_json(&server, "/api/analysis_sections/wWin.w");
+    assert_eq!(procedure["sections"], sections["sections"]);
+
+    let (status, svg) = get(&server, "/api/window_preview/wWin.w");
+    assert_eq!(StatusCode::Ok, status);
+    assert!(svg.contains("<svg"));
+
+    let found = get_json(&server, "/api/search/procedure/CUSTOMER");
+    assert_eq!(json_strings(&["customer.p", "inc/customer.i"]), found["results"]);
+    let first = get_json(&server, "/api/search/procedure/CUSTOMER?limit=1");
+    assert_eq!((json_strings(&["customer.p"]), 2), (first["results"].clone(), first["total"].as_u64().unwrap()));
+    let second = get_json(&server, &format!("/api/search/procedure/CUSTOMER?limit=1&cursor={}", first["next"].as_str().unwrap()));
+    assert_eq!((json_strings(&["inc/customer.i"]), true), (second["results"].clone(), second["next"].is_null()));
+
+    let resolution = get_json(&server, "/api/resolve/inc/customer.i");
+    assert_eq!("inc/customer.i", resolution["name"]);
+
+    let tree = get_json(&server, "/api/tree");
+    let entries: Vec<(&str, &str)> = tree["entries"].as_array().unwrap().iter()
+        .map(|entry| (entry["name"].as_str().unwrap(), entry["type"].as_str().unwrap()))
+        .collect();
+    assert_eq!(vec![("inc", "directory"), ("customer.p", "file"), ("wWin.w", "file")], entries);
+    let inc = get_json(&server, "/api/tree/inc");
+    assert_eq!("Include", inc["entries"][0]["kind"]);
+
+    let cache = get_json(&server, "/api/admin/cache");
+    assert!(cache["content"]["hits"].as_u64().unwrap() > 0);
+    assert!(cache["parse"]["preprocessed"]["hits"].as_u64().unwrap() > 0);
+
+    let index = get_json(&server, "/api/admin/index");
+    assert!(index["state"].is_string());
+    let started = Instant::now();
+    while get_json(&server, "/api/admin/index")["crawls"].as_u64().unwrap() == 0 {
+        assert!(started.elapsed() < Duration::from_secs(30), "the index was not crawled");
+        sleep(Duration::from_millis(50));
+    }
+    assert!(post_json(&server, "/api/admin/index")["state"].is_string());
+    while get_json(&server, "/api/admin/index")["crawls"].as_u64().unwrap() < 2 {
+        assert!(started.elapsed() < Duration::from_secs(30), "the index was not crawled again");
+        sleep(Duration::from_millis(50));
+    }
+
+    // The sources are on the file server, so nothing is watched, and a poll answers at once
+    let changes = get_json(&server, "/api/changes");
+    assert_eq!(Some(false), changes["watching"].as_bool());
+    let polled = Instant::now();
+    let since = get_json(&server, &format!("/api/changes?since={}", changes["latest"]));
+    assert!(since["changes"].as_array().unwrap().is_empty());
+    assert!(polled.elapsed() < Duration::from_secs(5));
+
+    let symbols = get_json(&server, "/api/search/symbol?q=init&kind=Procedure");
+    assert_eq!("initialize-customer", symbols["results"][0]["symbol"]["name"]);
+    assert_eq!("Prefix", symbols["results"][0]["quality"]);
+    let blocks = get_json(&server, "/api/search/symbol?q=_def*&path=wWin.w");
+    assert_eq!("CustomBlock", blocks["results"][0]["symbol"]["kind"]);
+
+    let inner = get_json(&server, "/api/search?q=kind:procedure%20file:wWin*%20customer");
+    assert_eq!(1, inner["results"].as_array().unwrap().len());
+    assert_eq!("initialize-customer", inner["results"][0]["symbol"]["name"]);
+    let callers = get_json(&server, "/api/search?q=calls:customer");
+    assert_eq!("Symbol", callers["results"][0]["type"]);
+    assert_eq!("initialize-customer", callers["results"][0]["symbol"]["name"]);
+    let (status, body) = get(&server, "/api/search?q=owner:me");
+    assert_eq!(StatusCode::BadRequest, status);
+    assert_eq!("Bad Request: Unknown search key 'owner:'", serde_json::from_str::<Value>(&body).unwrap()["message"]);
+
+    let text = get_json(&server, "/api/search/text?q=run%20customer&context=1");
+    assert_eq!(1, text["results"].as_array().unwrap().len());
+    assert_eq!("wWin.w", text["results"][0]["path"]);
+    assert_eq!("END PROCEDURE.", text["results"][0]["after"][0]);
+    assert_eq!("_PROCEDURE initialize-customer", text["results"][0]["section"]);
+    assert_eq!("initialize-customer", text["results"][0]["scope"]);
+    let (status, _) = get(&server, "/api/search/text?q=(&regex=true");
+    assert_eq!(StatusCode::BadRequest, status);
+    let (status, _) = get(&server, "/api/search/text?q=customer&context=all");
+    assert_eq!(StatusCode::BadRequest, status);
+    let page = get_json(&server, "/api/search/text?q=customer&path=wWin.w&limit=1");
+    assert_eq!((1, true), (page["results"].as_array().unwrap().len(), page["total"].is_null()));
+    let next = get_json(&server, &format!("/api/search/text?q=customer&path=wWin.w&limit=1&cursor={}", page["next"].as_str().unwrap()));
+    assert!(next["results"][0]["position"]["row"].as_u64() > page["results"][0]["position"]["row"].as_u64());
+    let (status, _) = get(&server, "/api/search/text?q=customer&sort=name");
+    assert_eq!(StatusCode::BadRequest, status);
+    let (status, _) = get(&server, "/api/search/symbol?q=customer&kind=Widget");
+    assert_eq!(StatusCode::BadRequest, status);
+    let (status, _) = get(&server, "/api/search/symbol?q=customer&limit=0");
+    assert_eq!(StatusCode::BadRequest, status);
+    let (status, body) = get(&server, "/api/search/text?q=customer&limit=many");
+    assert_eq!(StatusCode::BadRequest, status);
+    assert_eq!("bad_request", serde_json::from_str::<Value>(&body).unwrap()["error"]);
+
+    let statements = get_json(&server, "/api/search/structure?q=RUN%20$PROG%7B*.p%7D&path=wWin.w");
+    assert_eq!(1, statements["results"].as_array().unwrap().len());
+    assert_eq!("customer.p", statements["results"][0]["bindings"]["PROG"]);
+    assert_eq!("initialize-customer", statements["results"][0]["scope"]);
+    let (status, _) = get(&server, "/api/search/structure?q=RUN%20$");
+    assert_eq!(StatusCode::BadRequest, status);
+
+    let callers = get_json(&server, "/api/callers/customer.p");
+    assert_eq!(1, callers["calls"].as_array().unwrap().len());
+    assert_eq!("wWin.w", callers["calls"][0]["caller"]["program"]);
+    assert_eq!("initialize-customer", callers["calls"][0]["caller"]["block"]);
+    let callees = get_json(&server, "/api/callees/wWin.w/initialize-customer");
+    assert_eq!("customer.p", callees["calls"][0]["callee"]["program"]);
+    assert_eq!(0, callees["unresolved"].as_u64().unwrap());
+    let (status, _) = get(&server, "/api/callers/nowhere.p");
+    assert_eq!(StatusCode::NotFound, status);
+
+    let (status, dot) = get(&server, "/api/graph/calls?around=customer.p");
+    assert_eq!(StatusCode::Ok, status);
+    assert!(dot.contains("\"wWin.w#initialize-customer\" -> \"customer.p\" [label=\"RUN\", type=\"RUN\"];"), "{}", dot);
+    let (status, graphml) = get(&server, "/api/graph/includes?format=graphml");
+    assert_eq!(StatusCode::Ok, status);
+    assert!(graphml.contains("<edge source=\"wWin.w\" target=\"inc/customer.i\"><data key=\"type\">INCLUDE</data></edge>"), "{}", graphml);
+    let (status, _) = get(&server, "/api/graph/classes");
+    assert_eq!(StatusCode::NotFound, status);
+    let (status, body) = get(&server, "/api/graph/calls?format=svg");
+    assert_eq!(StatusCode::BadRequest, status);
+    assert_eq!("bad_request", serde_json::from_str::<Value>(&body).unwrap()["error"]);
+    // The graph command reads the same index, and writes nothing but the graph
+    let output = Command::new(binary("progress_server"))
+        .args(&["graph", "includes"])
+        .current_dir(root.join("progress_server"))
+        .env_remove("ROCKET_ENV")
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let dot = String::from_utf8(output.stdout).unwrap();
+    assert!(dot.starts_with("digraph includes {\n"), "{}", dot);
+
+    let (status, body) = get(&server, "/api/procedure/missing.p");
+    assert_eq!(StatusCode::NotFound, status);
+    assert_eq!("not_found", serde_json::from_str::<Value>(&body).unwrap()["error"]);
+
+    drop(server);
+    drop(file_server);
+    remove_dir_all(&root).unwrap();
+}
+
+fn json_strings(strings: &[&str]) -> Value {
+    Value::Array(strings.iter().map(|string| Value::String(string.to_string())).collect())
+}